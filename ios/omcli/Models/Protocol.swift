@@ -3,7 +3,7 @@ import Foundation
 // MARK: - Device → Server messages
 
 enum DeviceMessage: Encodable {
-    case hello(deviceId: String, name: String)
+    case hello(deviceId: String, name: String, e2ePublicKey: String?, metadata: DeviceMetadata)
     case auth(deviceId: String, token: String)
     case response(id: String, status: String, data: AnyCodable?, error: ErrorInfo?)
     case event(event: String, data: AnyCodable?)
@@ -13,10 +13,12 @@ enum DeviceMessage: Encodable {
     func encode(to encoder: Encoder) throws {
         var container = encoder.container(keyedBy: CodingKeys.self)
         switch self {
-        case .hello(let deviceId, let name):
+        case .hello(let deviceId, let name, let e2ePublicKey, let metadata):
             try container.encode("hello", forKey: .type)
             try container.encode(deviceId, forKey: .deviceId)
             try container.encode(name, forKey: .name)
+            try container.encodeIfPresent(e2ePublicKey, forKey: .e2ePublicKey)
+            try metadata.encode(to: encoder)
         case .auth(let deviceId, let token):
             try container.encode("auth", forKey: .type)
             try container.encode(deviceId, forKey: .deviceId)
@@ -42,6 +44,25 @@ enum DeviceMessage: Encodable {
 
     private enum CodingKeys: String, CodingKey {
         case type, deviceId = "device_id", name, token, id, status, data, error, event
+        case e2ePublicKey = "e2e_public_key"
+    }
+}
+
+/// What the app tells the backend about itself in the hello
+struct DeviceMetadata: Encodable {
+    /// Device protocol version this app speaks
+    static let protocolVersion = 1
+
+    let protocolVersion: Int
+    let appVersion: String?
+    let platform: String
+    let osVersion: String
+    let model: String
+    let commands: [String]
+
+    private enum CodingKeys: String, CodingKey {
+        case protocolVersion = "protocol_version", appVersion = "app_version", platform
+        case osVersion = "os_version", model, commands
     }
 }
 
@@ -50,8 +71,9 @@ enum DeviceMessage: Encodable {
 enum ServerMessage: Decodable {
     case pairingCode(code: String)
     case authRequired
-    case authResult(success: Bool, token: String?, error: String?)
+    case authResult(success: Bool, token: String?, error: String?, e2eCommitment: String?)
     case command(id: String, command: String, params: [String: AnyCodable])
+    case incompatible(error: String)
 
     init(from decoder: Decoder) throws {
         let container = try decoder.container(keyedBy: CodingKeys.self)
@@ -66,12 +88,16 @@ enum ServerMessage: Decodable {
             let success = try container.decode(Bool.self, forKey: .success)
             let token = try container.decodeIfPresent(String.self, forKey: .token)
             let error = try container.decodeIfPresent(String.self, forKey: .error)
-            self = .authResult(success: success, token: token, error: error)
+            let e2eCommitment = try container.decodeIfPresent(String.self, forKey: .e2eCommitment)
+            self = .authResult(success: success, token: token, error: error, e2eCommitment: e2eCommitment)
         case "command":
             let id = try container.decode(String.self, forKey: .id)
             let command = try container.decode(String.self, forKey: .command)
             let params = try container.decodeIfPresent([String: AnyCodable].self, forKey: .params) ?? [:]
             self = .command(id: id, command: command, params: params)
+        case "incompatible":
+            let error = try container.decode(String.self, forKey: .error)
+            self = .incompatible(error: error)
         default:
             throw DecodingError.dataCorruptedError(
                 forKey: .type, in: container,
@@ -82,6 +108,7 @@ enum ServerMessage: Decodable {
 
     private enum CodingKeys: String, CodingKey {
         case type, code, success, token, error, id, command, params
+        case e2eCommitment = "e2e_commitment"
     }
 }
 
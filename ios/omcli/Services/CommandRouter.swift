@@ -1,6 +1,12 @@
 import UIKit
 
 final class CommandRouter {
+    /// Announced in the hello; the backend refuses anything else up front
+    static let supportedCommands = [
+        "alarm.start", "alarm.stop", "notify.send", "tts.speak", "location.get",
+        "camera.snap", "sleep.start", "sleep.stop", "device.status",
+    ]
+
     let alarmService: AlarmService
     let sleepService: SleepService
     let locationService: LocationService
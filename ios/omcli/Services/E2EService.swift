@@ -0,0 +1,118 @@
+import CryptoKit
+import Foundation
+
+enum E2EError: Error {
+    case noSession
+    case invalidEnvelope
+}
+
+/// End-to-end encryption with the CLI that paired this device.
+///
+/// The device sends its X25519 key in `hello`. On pairing the backend forwards a
+/// SHA-256 commitment to the CLI's key, and the CLI then reveals the key with an
+/// `e2e.key` command. Both ends show a short authentication string derived from
+/// the two keys so the user can spot a backend that swapped them.
+enum E2EService {
+    static var isEnabled: Bool { UserDefaults.standard.bool(forKey: "e2e_enabled") }
+
+    /// A CLI key was accepted; unsealed commands are refused from then on
+    static var hasPeer: Bool { KeychainService.load(key: .e2ePeer) != nil }
+
+    static var publicKey: String {
+        identity().publicKey.rawRepresentation.base64EncodedString()
+    }
+
+    private static func identity() -> Curve25519.KeyAgreement.PrivateKey {
+        if let stored = KeychainService.load(key: .e2eKey),
+           let raw = Data(base64Encoded: stored),
+           let key = try? Curve25519.KeyAgreement.PrivateKey(rawRepresentation: raw) {
+            return key
+        }
+        let key = Curve25519.KeyAgreement.PrivateKey()
+        KeychainService.save(key: .e2eKey, value: key.rawRepresentation.base64EncodedString())
+        return key
+    }
+
+    // MARK: - Key exchange
+
+    /// Called when pairing completes; a new pairing always drops the old CLI key.
+    static func startExchange(commitment: String?) {
+        KeychainService.delete(key: .e2ePeer)
+        if let commitment {
+            UserDefaults.standard.set(commitment, forKey: "e2e_commitment")
+        } else {
+            UserDefaults.standard.removeObject(forKey: "e2e_commitment")
+        }
+    }
+
+    /// Accept the CLI key revealed by `e2e.key` if it matches the commitment.
+    /// Returns the short authentication string to show.
+    static func acceptPeer(publicKey: String) -> String? {
+        let commitment = UserDefaults.standard.string(forKey: "e2e_commitment")
+        UserDefaults.standard.removeObject(forKey: "e2e_commitment")
+        guard let commitment,
+              let raw = Data(base64Encoded: publicKey), raw.count == 32,
+              hex(SHA256.hash(data: raw)) == commitment else { return nil }
+        KeychainService.save(key: .e2ePeer, value: publicKey)
+        return sas(peer: raw)
+    }
+
+    static func forgetPeer() {
+        KeychainService.delete(key: .e2ePeer)
+    }
+
+    private static func sas(peer: Data) -> String {
+        let own = identity().publicKey.rawRepresentation
+        let digest = Array(SHA256.hash(data: Data("omcli-sas".utf8) + peer + own))
+        let n = (UInt32(digest[0]) << 24 | UInt32(digest[1]) << 16
+            | UInt32(digest[2]) << 8 | UInt32(digest[3])) % 1_000_000
+        return String(format: "%03d %03d", n / 1000, n % 1000)
+    }
+
+    // MARK: - Sealing
+
+    /// SHA-256 of the X25519 secret, the CLI key and this device's key
+    private static func sessionKey() throws -> SymmetricKey {
+        guard let stored = KeychainService.load(key: .e2ePeer),
+              let peerRaw = Data(base64Encoded: stored) else { throw E2EError.noSession }
+        let own = identity()
+        let peer = try Curve25519.KeyAgreement.PublicKey(rawRepresentation: peerRaw)
+        let shared = try own.sharedSecretFromKeyAgreement(with: peer)
+        let secret = shared.withUnsafeBytes { Data($0) }
+        let digest = SHA256.hash(data: secret + peerRaw + own.publicKey.rawRepresentation)
+        return SymmetricKey(data: Data(digest))
+    }
+
+    /// Associated data binding a payload to its direction, command, this device
+    /// and the command id, so the backend can't replay it under another id or
+    /// pass an old response off as a new one.
+    private static func aad(_ kind: String, command: String, id: String) -> Data {
+        Data("omcli-e2e \(kind) \(command) \(KeychainService.getOrCreateDeviceId()) \(id)".utf8)
+    }
+
+    /// Open `{"e2e": ...}` command params sealed for command `id`, or for the
+    /// schedule named in the envelope, which sends the same params on every run.
+    static func open(params envelope: String, schedule: String?, command: String, id: String) throws -> [String: AnyCodable] {
+        guard let combined = Data(base64Encoded: envelope) else { throw E2EError.invalidEnvelope }
+        let box = try AES.GCM.SealedBox(combined: combined)
+        let bound = schedule.map { "schedule:\($0)" } ?? id
+        let plaintext = try AES.GCM.open(
+            box, using: try sessionKey(), authenticating: aad("params", command: command, id: bound)
+        )
+        return try JSONDecoder().decode([String: AnyCodable].self, from: plaintext)
+    }
+
+    /// Seal the response data of command `id` into `{"e2e": ...}`.
+    static func seal(data: AnyCodable, command: String, id: String) throws -> AnyCodable {
+        let plaintext = try JSONEncoder().encode(data)
+        let box = try AES.GCM.seal(
+            plaintext, using: try sessionKey(), authenticating: aad("data", command: command, id: id)
+        )
+        guard let combined = box.combined else { throw E2EError.invalidEnvelope }
+        return AnyCodable(["e2e": combined.base64EncodedString()])
+    }
+
+    private static func hex(_ digest: SHA256.Digest) -> String {
+        digest.map { String(format: "%02x", $0) }.joined()
+    }
+}
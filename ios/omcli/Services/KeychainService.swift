@@ -7,6 +7,8 @@ enum KeychainService {
     enum Key: String {
         case deviceId = "device_id"
         case deviceToken = "device_token"
+        case e2eKey = "e2e_key"
+        case e2ePeer = "e2e_peer"
     }
 
     static func save(key: Key, value: String) {
@@ -8,10 +8,15 @@ struct DiscoveredServer: Identifiable, Equatable, Sendable {
     var port: UInt16?
     var wsPath: String
     var version: String?
+    var scheme = "ws"
+    /// SHA-256 of the server's TLS certificate, pinned by WebSocketService
+    var fingerprint: String?
 
     var wsURL: String? {
         guard let host, let port else { return nil }
-        return "ws://\(host):\(port)\(wsPath)"
+        let url = "\(scheme)://\(host):\(port)\(wsPath)"
+        guard let fingerprint else { return url }
+        return "\(url)?fp=\(fingerprint)"
     }
 }
 
@@ -77,6 +82,8 @@ final class ServerDiscoveryService {
                 let dict = txt.dictionary
                 if let path = dict["path"] { server.wsPath = path }
                 if let ver = dict["version"] { server.version = ver }
+                if let scheme = dict["scheme"] { server.scheme = scheme }
+                if let fp = dict["fp"] { server.fingerprint = fp }
             }
             paired.append((result, server))
         }
@@ -1,3 +1,4 @@
+import CryptoKit
 import Foundation
 import UIKit
 
@@ -21,6 +22,8 @@ final class WebSocketService: @unchecked Sendable {
     var lastCommand: String?
     var log: [LogEntry] = []
     var lastError: String?
+    /// Short authentication string to compare with `omcli pair` after a key exchange
+    var e2eVerificationCode: String?
 
     @ObservationIgnored private var webSocketTask: URLSessionWebSocketTask?
     @ObservationIgnored private var session: URLSession?
@@ -104,7 +107,14 @@ final class WebSocketService: @unchecked Sendable {
 
         let config = URLSessionConfiguration.default
         config.waitsForConnectivity = true
-        session = URLSession(configuration: config)
+        // wss:// URLs from the QR code or Bonjour carry the certificate fingerprint
+        let fingerprint = URLComponents(url: url, resolvingAgainstBaseURL: false)?
+            .queryItems?.first(where: { $0.name == "fp" })?.value
+        session = URLSession(
+            configuration: config,
+            delegate: fingerprint.map { PinnedCertificateDelegate(fingerprint: $0) },
+            delegateQueue: nil
+        )
         webSocketTask = session?.webSocketTask(with: url)
         webSocketTask?.resume()
 
@@ -113,7 +123,12 @@ final class WebSocketService: @unchecked Sendable {
     }
 
     private func sendHello() {
-        let msg = DeviceMessage.hello(deviceId: deviceId, name: deviceName)
+        let msg = DeviceMessage.hello(
+            deviceId: deviceId,
+            name: deviceName,
+            e2ePublicKey: E2EService.isEnabled ? E2EService.publicKey : nil,
+            metadata: Self.metadata
+        )
         send(msg) { [weak self] success in
             guard let self, success else { return }
             self.addLog("Sent hello as \(self.deviceName)")
@@ -122,6 +137,23 @@ final class WebSocketService: @unchecked Sendable {
         }
     }
 
+    private static var metadata: DeviceMetadata {
+        var systemInfo = utsname()
+        uname(&systemInfo)
+        let model = withUnsafeBytes(of: &systemInfo.machine) { raw in
+            String(decoding: raw.prefix(while: { $0 != 0 }), as: UTF8.self)
+        }
+        return DeviceMetadata(
+            protocolVersion: DeviceMetadata.protocolVersion,
+            appVersion: Bundle.main.infoDictionary?["CFBundleShortVersionString"] as? String,
+            platform: "ios",
+            osVersion: UIDevice.current.systemVersion,
+            model: model,
+            // e2e.key is answered here rather than by the router
+            commands: CommandRouter.supportedCommands + ["e2e.key"]
+        )
+    }
+
     private func sendStoredPushToken() {
         if let token = UserDefaults.standard.string(forKey: "push_token") {
             let msg = DeviceMessage.pushToken(token: token)
@@ -202,14 +234,22 @@ final class WebSocketService: @unchecked Sendable {
         case .authRequired:
             sendAuth()
 
-        case .authResult(let success, let token, let error):
+        case .incompatible(let error):
+            // Reconnecting would be refused the same way until the app is updated
+            addLog("Server refused this app: \(error)")
+            lastError = error
+            disconnect()
+
+        case .authResult(let success, let token, let error, let e2eCommitment):
             if success {
+                // The backend rotates the token on every successful auth
                 if let token {
                     KeychainService.saveToken(token, for: serverURL)
-                    addLog("Paired successfully")
-                } else {
-                    addLog("Authenticated")
                 }
+                if pairingCode != nil {
+                    E2EService.startExchange(commitment: e2eCommitment)
+                }
+                addLog(pairingCode != nil ? "Paired successfully" : "Authenticated")
                 pairingCode = nil
                 connectionState = .paired
                 reconnectAttempt = 0
@@ -224,13 +264,57 @@ final class WebSocketService: @unchecked Sendable {
         case .command(let id, let command, let params):
             lastCommand = command
             addLog("Command: \(command)")
+            if command == "e2e.key" {
+                send(acceptE2EKey(id: id, params: params))
+                return
+            }
+
+            // Sealed params are opened here and the response sealed the same way
+            var params = params
+            let sealed = params["e2e"] != nil && params.keys.allSatisfy { $0 == "e2e" || $0 == "schedule" }
+            if sealed {
+                guard let envelope = params["e2e"]?.stringValue(),
+                      let opened = try? E2EService.open(
+                          params: envelope, schedule: params["schedule"]?.stringValue(), command: command, id: id
+                      ) else {
+                    send(errorResponse(id: id, code: "E2E_FAILED", message: "Could not decrypt the command"))
+                    return
+                }
+                params = opened
+            } else if E2EService.hasPeer {
+                send(errorResponse(id: id, code: "E2E_REQUIRED", message: "This device only accepts end-to-end encrypted commands"))
+                return
+            }
+
             if let handler = commandHandler {
-                let response = await handler(id, command, params)
+                var response = await handler(id, command, params)
+                if sealed, case .response(let rid, let status, let data, let error) = response, let data {
+                    guard let sealedData = try? E2EService.seal(data: data, command: command, id: id) else {
+                        send(errorResponse(id: id, code: "E2E_FAILED", message: "Could not encrypt the response"))
+                        return
+                    }
+                    response = .response(id: rid, status: status, data: sealedData, error: error)
+                }
                 send(response)
             }
         }
     }
 
+    private func acceptE2EKey(id: String, params: [String: AnyCodable]) -> DeviceMessage {
+        guard let publicKey = params["public_key"]?.stringValue(),
+              let code = E2EService.acceptPeer(publicKey: publicKey) else {
+            addLog("End-to-end key rejected")
+            return errorResponse(id: id, code: "E2E_FAILED", message: "Key does not match the pairing commitment")
+        }
+        e2eVerificationCode = code
+        addLog("End-to-end key received")
+        return .response(id: id, status: "ok", data: nil, error: nil)
+    }
+
+    private func errorResponse(id: String, code: String, message: String) -> DeviceMessage {
+        .response(id: id, status: "error", data: nil, error: ErrorInfo(code: code, message: message))
+    }
+
     private func handleDisconnect(error: Error) {
         let nsError = error as NSError
         let wasCancelled = nsError.domain == NSPOSIXErrorDomain && nsError.code == 57
@@ -299,3 +383,36 @@ final class WebSocketService: @unchecked Sendable {
         }
     }
 }
+
+// MARK: - Certificate pinning
+
+/// Accepts the server certificate only if its SHA-256 matches the pinned fingerprint,
+/// which lets the app trust the backend's self-signed certificate.
+private final class PinnedCertificateDelegate: NSObject, URLSessionDelegate {
+    let fingerprint: String
+
+    init(fingerprint: String) {
+        self.fingerprint = fingerprint.lowercased()
+    }
+
+    func urlSession(
+        _ session: URLSession,
+        didReceive challenge: URLAuthenticationChallenge,
+        completionHandler: @escaping (URLSession.AuthChallengeDisposition, URLCredential?) -> Void
+    ) {
+        guard challenge.protectionSpace.authenticationMethod == NSURLAuthenticationMethodServerTrust,
+              let trust = challenge.protectionSpace.serverTrust,
+              let chain = SecTrustCopyCertificateChain(trust) as? [SecCertificate],
+              let leaf = chain.first else {
+            completionHandler(.performDefaultHandling, nil)
+            return
+        }
+        let der = SecCertificateCopyData(leaf) as Data
+        let digest = SHA256.hash(data: der).map { String(format: "%02x", $0) }.joined()
+        if digest == fingerprint {
+            completionHandler(.useCredential, URLCredential(trust: trust))
+        } else {
+            completionHandler(.cancelAuthenticationChallenge, nil)
+        }
+    }
+}
@@ -50,6 +50,32 @@ struct DashboardView: View {
                     }
                 }
 
+                if let code = webSocket.e2eVerificationCode {
+                    Section {
+                        VStack(spacing: 12) {
+                            Text("Encryption Code")
+                                .font(.headline)
+                            Text(code)
+                                .font(.system(size: 40, weight: .bold, design: .monospaced))
+                            Text("Check that omcli pair shows the same code")
+                                .font(.caption)
+                                .foregroundStyle(.secondary)
+                            HStack {
+                                Button("Doesn't Match", role: .destructive) {
+                                    E2EService.forgetPeer()
+                                    webSocket.e2eVerificationCode = nil
+                                }
+                                Button("Codes Match") {
+                                    webSocket.e2eVerificationCode = nil
+                                }
+                                .buttonStyle(.borderedProminent)
+                            }
+                        }
+                        .frame(maxWidth: .infinity)
+                        .padding(.vertical, 8)
+                    }
+                }
+
                 if !webSocket.log.isEmpty {
                     Section {
                         ForEach(webSocket.log) { entry in
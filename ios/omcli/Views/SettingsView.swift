@@ -9,6 +9,7 @@ struct SettingsView: View {
 
     @AppStorage("server_url") private var serverURL = ""
     @AppStorage("device_name") private var deviceName = ""
+    @AppStorage("e2e_enabled") private var e2eEnabled = false
 
     @State private var notificationStatus = "Unknown"
     @State private var showResetConfirm = false
@@ -103,6 +104,12 @@ struct SettingsView: View {
                     }
                 }
 
+                Section {
+                    Toggle("End-to-end encryption", isOn: $e2eEnabled)
+                } footer: {
+                    Text("Takes effect the next time you pair. The server can then route commands and results but not read them.")
+                }
+
                 Section("Permissions") {
                     PermissionRow(
                         title: "Location",
@@ -1,47 +1,26 @@
-use serde_json::json;
+use crate::protocol::command::{AlarmStartParams, Command, NoParams, Sound};
 
-pub async fn alarm_start(sound: &str, message: Option<&str>, device: Option<&str>) {
-    let mut params = json!({ "sound": sound });
-    if let Some(msg) = message {
-        params["message"] = json!(msg);
-    }
-    let mut body = json!({
-        "command": "alarm.start",
-        "params": params,
-    });
-    if let Some(dev) = device {
-        body["device_id"] = json!(dev);
-    }
+pub async fn alarm_start(
+    sound: Sound,
+    message: Option<&str>,
+    device: Option<&str>,
+    group: Option<&str>,
+) -> super::CliResult {
+    let mut body = Command::AlarmStart(AlarmStartParams {
+        sound,
+        message: message.map(String::from),
+    })
+    .body();
+    super::set_target(&mut body, device, group);
 
-    match super::api_request(reqwest::Method::POST, "/api/command", Some(body)).await {
-        Ok(resp) => {
-            if resp.get("status").and_then(|s| s.as_str()) == Some("ok") {
-                println!("Alarm started");
-            } else {
-                println!("{}", serde_json::to_string_pretty(&resp).unwrap());
-            }
-        }
-        Err(e) => eprintln!("Error: {e}"),
-    }
+    let resp = super::post_command(body).await?;
+    super::finish_command(&resp, "alarm started", |_| println!("Alarm started"))
 }
 
-pub async fn alarm_stop(device: Option<&str>) {
-    let mut body = json!({
-        "command": "alarm.stop",
-        "params": {},
-    });
-    if let Some(dev) = device {
-        body["device_id"] = json!(dev);
-    }
+pub async fn alarm_stop(device: Option<&str>, group: Option<&str>) -> super::CliResult {
+    let mut body = Command::AlarmStop(NoParams {}).body();
+    super::set_target(&mut body, device, group);
 
-    match super::api_request(reqwest::Method::POST, "/api/command", Some(body)).await {
-        Ok(resp) => {
-            if resp.get("status").and_then(|s| s.as_str()) == Some("ok") {
-                println!("Alarm stopped");
-            } else {
-                println!("{}", serde_json::to_string_pretty(&resp).unwrap());
-            }
-        }
-        Err(e) => eprintln!("Error: {e}"),
-    }
+    let resp = super::post_command(body).await?;
+    super::finish_command(&resp, "alarm stopped", |_| println!("Alarm stopped"))
 }
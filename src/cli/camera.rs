@@ -3,68 +3,75 @@ use serde_json::json;
 use std::fs;
 use std::time::{SystemTime, UNIX_EPOCH};
 
-pub async fn camera_snap(facing: &str, output: Option<&str>, device: Option<&str>) {
-    let mut body = json!({
-        "command": "camera.snap",
-        "params": { "facing": facing },
-    });
+use super::output::json_output;
+use super::{CliError, ErrorKind};
+use crate::protocol::command::{CameraParams, Command, Facing};
+
+pub async fn camera_snap(
+    facing: Facing,
+    output: Option<&str>,
+    device: Option<&str>,
+) -> super::CliResult {
+    let mut body = Command::CameraSnap(CameraParams { facing }).body();
     if let Some(dev) = device {
         body["device_id"] = json!(dev);
     }
 
-    match super::api_request(reqwest::Method::POST, "/api/command", Some(body)).await {
-        Ok(resp) => {
-            // Check for device-side errors
-            if resp.get("status").and_then(|s| s.as_str()) == Some("error") {
-                let code = resp.get("error_code").and_then(|c| c.as_str()).unwrap_or("");
-                if code == "USER_DECLINED" {
-                    eprintln!("The photo was declined on the device.");
-                } else {
-                    let msg = resp.get("error").and_then(|e| e.as_str()).unwrap_or("Unknown error");
-                    eprintln!("Error: {msg}");
-                }
-                return;
-            }
-
-            // Camera snap requires a live WebSocket connection — APNS can't return data
-            if resp.get("data").and_then(|d| d.get("delivered_via")).and_then(|v| v.as_str())
-                == Some("apns")
-            {
-                eprintln!("Error: camera snap requires the device to be connected via WebSocket");
-                eprintln!("Open the app on the device and try again");
-                return;
-            }
-
-            let b64 = match resp.get("data").and_then(|d| d.get("base64")).and_then(|v| v.as_str())
-            {
-                Some(s) => s,
-                None => {
-                    eprintln!("Error: no image data in response");
-                    println!("{}", serde_json::to_string_pretty(&resp).unwrap());
-                    return;
-                }
-            };
-
-            let bytes = match base64::engine::general_purpose::STANDARD.decode(b64) {
-                Ok(b) => b,
-                Err(e) => {
-                    eprintln!("Error: failed to decode base64: {e}");
-                    return;
-                }
-            };
-
-            let path = match output {
-                Some(p) => p.to_string(),
-                None => default_filename(),
-            };
-
-            match fs::write(&path, &bytes) {
-                Ok(_) => println!("Saved {} ({} bytes)", path, bytes.len()),
-                Err(e) => eprintln!("Error: failed to write file: {e}"),
-            }
+    let resp = super::post_command(body).await?;
+
+    // Queued or device-side errors
+    if let Err(e) = super::command_outcome(&resp) {
+        if e.kind == ErrorKind::Declined && !json_output() {
+            return Err(CliError::new(
+                e.kind,
+                "The photo was declined on the device.",
+            ));
         }
-        Err(e) => eprintln!("Error: {e}"),
+        return super::finish_command(&resp, "", |_| {});
     }
+
+    // Camera snap requires a live WebSocket connection — APNS can't return data
+    if resp
+        .get("data")
+        .and_then(|d| d.get("delivered_via"))
+        .and_then(|v| v.as_str())
+        == Some("apns")
+    {
+        return Err(CliError::new(
+            ErrorKind::Offline,
+            "camera snap requires the device to be connected via WebSocket\n\
+             Open the app on the device and try again",
+        ));
+    }
+
+    let b64 = resp
+        .get("data")
+        .and_then(|d| d.get("base64"))
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| CliError::new(ErrorKind::Device, "no image data in response"))?;
+
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(b64)
+        .map_err(|e| format!("failed to decode base64: {e}"))?;
+
+    let path = match output {
+        Some(p) => p.to_string(),
+        None => default_filename(),
+    };
+
+    fs::write(&path, &bytes).map_err(|e| format!("failed to write file: {e}"))?;
+
+    // The image itself is in the file; JSON output describes it instead of repeating it
+    let summary = json!({
+        "id": resp.get("id"),
+        "status": "ok",
+        "path": path,
+        "bytes": bytes.len(),
+    });
+    super::emit(&summary, |_| {
+        println!("Saved {} ({} bytes)", path, bytes.len())
+    });
+    Ok(())
 }
 
 fn default_filename() -> String {
@@ -123,5 +130,5 @@ fn days_to_ymd(mut days: u64) -> (u64, u64, u64) {
 }
 
 fn is_leap(y: u64) -> bool {
-    y % 4 == 0 && (y % 100 != 0 || y % 400 == 0)
+    y.is_multiple_of(4) && (!y.is_multiple_of(100) || y.is_multiple_of(400))
 }
@@ -1,82 +1,200 @@
-use crate::config::{ApnsConfig, Config};
-
-pub async fn show_config() {
-    match Config::load() {
-        Ok(config) => {
-            println!("Server URL: {}", config.server.url);
-            println!("API Key:    {}", config.server.api_key);
-            println!("Port:       {}", config.server.port);
-            println!("Bind:       {}", config.server.bind);
-
-            if let Some(apns) = &config.apns {
-                println!();
-                println!("[APNs]");
-                println!("Key Path:   {}", apns.key_path);
-                println!("Key ID:     {}", apns.key_id);
-                println!("Team ID:    {}", apns.team_id);
-                println!("Bundle ID:  {}", apns.bundle_id);
-                println!("Sandbox:    {}", apns.sandbox);
-            }
+use serde_json::json;
+
+use super::output::ErrorKind;
+
+use crate::config::schema::{self, Field, FIELDS};
+use crate::config::{edit_config, Config};
+
+/// Every key with its effective value and where it came from
+/// (`file`, `env`, `default`, or `unset`).
+pub async fn show_config() -> super::CliResult {
+    let layered = Config::load_layered()?;
+    let effective = toml::Table::try_from(&layered.config)
+        .map_err(|e| format!("Failed to serialize config: {e}"))?;
+
+    let rows: Vec<_> = FIELDS
+        .iter()
+        .map(|f| {
+            let value = schema::get(&layered.table, f.key).or_else(|| schema::get(&effective, f.key));
+            let source = match (layered.sources.get(f.key), value) {
+                (Some(source), _) => source.as_str(),
+                (None, Some(_)) => "default",
+                (None, None) => "unset",
+            };
+            json!({ "key": f.key, "value": value.map(to_json), "source": source })
+        })
+        .collect();
+
+    let url = layered
+        .config
+        .server
+        .url
+        .clone()
+        .unwrap_or_else(|| layered.config.server.local_url());
+    let result = json!({ "local_url": url, "keys": rows });
+    super::emit(&result, |_| {
+        println!("Local URL: {url}");
+        println!();
+        println!("{:<40} {:<8} VALUE", "KEY", "SOURCE");
+        for row in &rows {
+            let value = match &row["value"] {
+                serde_json::Value::Null => String::new(),
+                serde_json::Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            println!(
+                "{:<40} {:<8} {}",
+                row["key"].as_str().unwrap_or(""),
+                row["source"].as_str().unwrap_or(""),
+                value
+            );
         }
-        Err(e) => eprintln!("Error: {e}"),
-    }
+    });
+    Ok(())
 }
 
-pub async fn set_config(key: &str, value: &str) {
-    let mut config = match Config::load() {
-        Ok(c) => c,
-        Err(e) => {
-            eprintln!("Error: {e}");
-            return;
-        }
-    };
-
-    match key {
-        "server" | "url" => config.server.url = value.to_string(),
-        "api_key" | "token" => config.server.api_key = value.to_string(),
-        "port" => {
-            match value.parse::<u16>() {
-                Ok(p) => config.server.port = p,
-                Err(_) => {
-                    eprintln!("Invalid port number");
-                    return;
-                }
-            }
-        }
-        "bind" => config.server.bind = value.to_string(),
-        "apns.key_path" => apns_mut(&mut config).key_path = value.to_string(),
-        "apns.key_id" => apns_mut(&mut config).key_id = value.to_string(),
-        "apns.team_id" => apns_mut(&mut config).team_id = value.to_string(),
-        "apns.bundle_id" => apns_mut(&mut config).bundle_id = value.to_string(),
-        "apns.sandbox" => {
-            match value.parse::<bool>() {
-                Ok(b) => apns_mut(&mut config).sandbox = b,
-                Err(_) => {
-                    eprintln!("Invalid boolean (use true/false)");
-                    return;
-                }
-            }
+pub async fn get_config(key: &str) -> super::CliResult {
+    let field = lookup(key)?;
+    let layered = Config::load_layered()?;
+    let effective = toml::Table::try_from(&layered.config)
+        .map_err(|e| format!("Failed to serialize config: {e}"))?;
+    let value = schema::get(&layered.table, field.key)
+        .or_else(|| schema::get(&effective, field.key))
+        .ok_or_else(|| format!("{} is not set", field.key))?;
+
+    let source = layered.sources.get(field.key).map_or("default", |s| s.as_str());
+    super::emit(
+        &json!({ "key": field.key, "value": to_json(value), "source": source }),
+        |_| match value {
+            toml::Value::String(s) => println!("{s}"),
+            other => println!("{other}"),
+        },
+    );
+    Ok(())
+}
+
+/// Write one key to config.toml, leaving comments and other keys as they are.
+pub async fn set_config(key: &str, value: &str) -> super::CliResult {
+    let field = lookup(key)?;
+    let parsed = field.parse(value)?;
+    edit_config(|doc| schema::set_item(doc, field.key, schema::to_edit_value(&parsed)))
+        .map_err(|e| format!("Error saving config: {e}"))?;
+
+    super::emit(&json!({ "key": field.key, "value": to_json(&parsed) }), |_| {
+        println!("Config updated: {} = {}", field.key, value)
+    });
+    warn_overridden(field);
+    Ok(())
+}
+
+pub async fn unset_config(key: &str) -> super::CliResult {
+    let field = lookup(key)?;
+    let mut removed = false;
+    edit_config(|doc| {
+        removed = schema::remove_item(doc, field.key);
+        Ok(())
+    })
+    .map_err(|e| format!("Error saving config: {e}"))?;
+
+    super::emit(&json!({ "key": field.key, "removed": removed }), |_| {
+        if removed {
+            println!("Config updated: {} unset", field.key);
+        } else {
+            println!("{} was not set in config.toml", field.key);
         }
-        _ => {
-            eprintln!("Unknown config key: {key}");
-            eprintln!("Available: server, api_key, port, bind");
-            eprintln!("  APNs:   apns.key_path, apns.key_id, apns.team_id, apns.bundle_id, apns.sandbox");
-            return;
+    });
+    Ok(())
+}
+
+/// List every key with its type, environment variable and meaning.
+pub async fn list_keys() -> super::CliResult {
+    let keys: Vec<_> = FIELDS
+        .iter()
+        .map(|f| {
+            json!({
+                "key": f.key,
+                "type": f.type_name(),
+                "env": f.env_var(),
+                "description": f.about,
+            })
+        })
+        .collect();
+    super::emit(&json!(keys), |_| {
+        for f in FIELDS {
+            println!("{:<40} {:<20} {}", f.key, f.type_name(), f.env_var());
+            println!("    {}", f.about);
         }
+    });
+    Ok(())
+}
+
+fn lookup(key: &str) -> Result<&'static Field, String> {
+    if key == "server" || key == "url" {
+        return Err("The server URL is a client setting: use `omcli context add <name> --url <url> \
+                    --api-key <key>` or `omcli connect <url> <code>`"
+            .to_string());
     }
+    schema::find(key).ok_or_else(|| {
+        format!("Unknown config key: {key}\nRun `omcli config keys` to list the available keys")
+    })
+}
 
-    match config.save() {
-        Ok(()) => println!("Config updated: {key} = {value}"),
-        Err(e) => eprintln!("Error saving config: {e}"),
+/// Point out a value written to the file that will not take effect.
+fn warn_overridden(field: &Field) {
+    let env = field.env_var();
+    if std::env::var_os(&env).is_some() {
+        eprintln!("Note: {env} is set and overrides this value");
+        return;
+    }
+    if field.secret {
+        let file_key = format!("{}_file", field.key);
+        let layered = Config::load_layered().ok();
+        if layered.is_some_and(|l| l.sources.contains_key(file_key.as_str())) {
+            eprintln!("Note: {file_key} is set and takes precedence over this value");
+        }
     }
 }
 
-fn apns_mut(config: &mut Config) -> &mut ApnsConfig {
-    config.apns.get_or_insert(ApnsConfig {
-        key_path: String::new(),
-        key_id: String::new(),
-        team_id: String::new(),
-        bundle_id: String::new(),
-        sandbox: false,
-    })
+fn to_json(value: &toml::Value) -> serde_json::Value {
+    serde_json::to_value(value).unwrap_or_default()
+}
+
+/// Ask the running server to re-read config.toml.
+pub async fn reload_config() -> super::CliResult {
+    let resp = super::api_request(reqwest::Method::POST, "/api/admin/reload", None)
+        .await
+        .map_err(|mut e| {
+            if e.kind == ErrorKind::Auth {
+                // The CLI reads the new key from config.toml before the server has it
+                e.message += "\nIf you just changed server.api_key, reload with SIGHUP instead: \
+                              pkill -HUP -f 'omcli serve'";
+            }
+            e
+        })?;
+    super::emit(&resp, |resp| {
+        let changed = resp["changed"].as_array().cloned().unwrap_or_default();
+        if changed.is_empty() {
+            println!("Config reloaded, nothing changed");
+            return;
+        }
+        println!("Config reloaded:");
+        let show = |v: &serde_json::Value| match v {
+            serde_json::Value::Null => "(unset)".to_string(),
+            serde_json::Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        for change in &changed {
+            println!(
+                "  {}: {} -> {}",
+                change["key"].as_str().unwrap_or(""),
+                show(&change["old"]),
+                show(&change["new"])
+            );
+        }
+        if let Some(keys) = resp["restart_required"].as_array().filter(|k| !k.is_empty()) {
+            let keys: Vec<_> = keys.iter().filter_map(|k| k.as_str()).collect();
+            println!("Restart `omcli serve` to apply: {}", keys.join(", "));
+        }
+    });
+    Ok(())
 }
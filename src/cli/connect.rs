@@ -0,0 +1,123 @@
+use serde_json::json;
+use std::path::PathBuf;
+
+use super::output::{CliError, ErrorKind};
+use crate::config::{self, ClientConfig, ClientContext, Config};
+
+/// Exchange an enrollment code for an API key and make `url` the current context.
+pub async fn connect(
+    url: &str,
+    code: &str,
+    name: Option<&str>,
+    fingerprint: Option<&str>,
+) -> super::CliResult {
+    let url = url.trim_end_matches('/');
+    let parsed = reqwest::Url::parse(url).map_err(|e| format!("Invalid URL '{url}': {e}"))?;
+    let host = parsed
+        .host_str()
+        .ok_or_else(|| format!("Invalid URL '{url}': no host"))?
+        .trim_matches(|c| c == '[' || c == ']')
+        .to_string();
+    let context_name = name.map(String::from).unwrap_or_else(|| host.clone());
+    if context_name == super::context::LOCAL {
+        return Err("'local' is reserved for the server on this machine; pass --name".to_string().into());
+    }
+
+    let ca_cert = match fingerprint {
+        Some(expected) => {
+            if parsed.scheme() != "https" {
+                return Err("--fingerprint needs an https:// URL".to_string().into());
+            }
+            Some(pin_certificate(&parsed, &context_name, expected)?)
+        }
+        None => None,
+    };
+
+    let client = super::http_client(ca_cert.as_deref())?;
+    let machine = hostname::get()
+        .ok()
+        .and_then(|h| h.into_string().ok())
+        .map(|h| h.trim_end_matches(".local").to_string());
+    let resp = client
+        .post(format!("{url}/api/enroll"))
+        .json(&json!({ "code": code, "name": machine }))
+        .send()
+        .await
+        .map_err(|e| {
+            let mut message = format!("Cannot reach server at {url}: {e}");
+            if fingerprint.is_none() && url.starts_with("https://") {
+                message += "\nIf the server uses a self-signed certificate, pass the \
+                            --fingerprint printed by `omcli keys enroll`.";
+            }
+            CliError::new(ErrorKind::Unreachable, message)
+        })?;
+    let key = super::read_response(resp).await?;
+    let api_key = key["key"]
+        .as_str()
+        .ok_or_else(|| "Server did not return an API key".to_string())?
+        .to_string();
+
+    let mut client_config = ClientConfig::load()?;
+    let default_device = client_config
+        .contexts
+        .get(&context_name)
+        .and_then(|c| c.default_device.clone());
+    client_config.contexts.insert(
+        context_name.clone(),
+        ClientContext {
+            url: url.to_string(),
+            api_key,
+            default_device,
+            ca_cert: ca_cert.map(|p| p.display().to_string()),
+        },
+    );
+    client_config.current = Some(context_name.clone());
+    client_config.save()?;
+
+    let key_name = key["name"].as_str().unwrap_or("?");
+    let result = json!({
+        "context": context_name,
+        "url": url,
+        "key_id": key["id"],
+        "key_name": key_name,
+    });
+    super::emit(&result, |_| {
+        println!("Connected to {url} as API key '{key_name}'");
+        println!(
+            "Saved as context '{}' in {}",
+            context_name,
+            Config::client_path().display()
+        );
+    });
+    Ok(())
+}
+
+/// Check the server's certificate against the fingerprint from the enrollment
+/// and store it, so later requests trust exactly that certificate.
+fn pin_certificate(
+    url: &reqwest::Url,
+    context_name: &str,
+    expected: &str,
+) -> Result<PathBuf, String> {
+    let addrs = url
+        .socket_addrs(|| None)
+        .map_err(|e| format!("Cannot resolve {url}: {e}"))?;
+    let cert = crate::tls::peer_certificate(&addrs, url.domain())?;
+    let actual = crate::tls::cert_fingerprint(&cert)?;
+    let expected = expected.replace(':', "").to_lowercase();
+    if actual != expected {
+        return Err(format!(
+            "Certificate fingerprint mismatch: the server presented {actual}, \
+             expected {expected}. Not sending the enrollment code."
+        ));
+    }
+
+    let pem = cert
+        .to_pem()
+        .map_err(|e| format!("Failed to encode certificate: {e}"))?;
+    let dir = Config::certs_dir();
+    std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {e}", dir.display()))?;
+    let path = dir.join(format!("{context_name}.pem"));
+    config::write_private(&path, &pem)?;
+    Ok(path)
+}
@@ -0,0 +1,226 @@
+use serde_json::json;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use super::output::CliError;
+use crate::config::{ClientConfig, ClientContext, Config};
+
+/// Name that selects this machine's own server from config.toml
+pub const LOCAL: &str = "local";
+
+static SELECTED: OnceLock<Option<String>> = OnceLock::new();
+
+/// Context picked with the global `--context` flag, overriding the current one.
+pub fn set_context(name: Option<String>) {
+    let _ = SELECTED.set(name);
+}
+
+/// Server and credentials a command talks to.
+pub(crate) struct Resolved {
+    pub url: String,
+    pub api_key: String,
+    pub ca_cert: Option<PathBuf>,
+    pub default_device: Option<String>,
+}
+
+/// Pick the context (`--context`, else the current one, else the local server)
+/// and apply the `OMCLI_URL` / `OMCLI_API_KEY` overrides on top.
+pub(crate) fn resolve() -> Result<Resolved, CliError> {
+    let client = ClientConfig::load()?;
+    let base = match selected(&client).as_deref() {
+        Some(name) => {
+            let context = client.contexts.get(name).ok_or_else(|| {
+                format!("Unknown context '{name}' (see `omcli context list`)")
+            })?;
+            Ok(from_context(context))
+        }
+        _ => local(),
+    };
+
+    let env = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+    match (base, env("OMCLI_URL"), env("OMCLI_API_KEY")) {
+        (Ok(mut resolved), url, api_key) => {
+            if let Some(url) = url {
+                resolved.url = url;
+            }
+            if let Some(api_key) = api_key {
+                resolved.api_key = api_key;
+            }
+            Ok(resolved)
+        }
+        // Both overrides given: no config file needed at all
+        (Err(_), Some(url), Some(api_key)) => Ok(Resolved {
+            url,
+            api_key,
+            ca_cert: None,
+            default_device: None,
+        }),
+        (Err(e), _, _) => Err(e),
+    }
+}
+
+/// The remote context in use, or `None` for the local server.
+pub(crate) fn selected(client: &ClientConfig) -> Option<String> {
+    SELECTED
+        .get()
+        .cloned()
+        .flatten()
+        .or_else(|| client.current.clone())
+        .filter(|name| name != LOCAL)
+}
+
+fn from_context(context: &ClientContext) -> Resolved {
+    Resolved {
+        url: context.url.clone(),
+        api_key: context.api_key.clone(),
+        ca_cert: context.ca_cert.as_ref().map(PathBuf::from),
+        default_device: context.default_device.clone(),
+    }
+}
+
+/// The server on this machine, using its admin key. Its certificate is
+/// trusted when it serves TLS, so a self-signed one works without installing it.
+fn local() -> Result<Resolved, CliError> {
+    let config = Config::load()?;
+    let ca_cert = config
+        .server
+        .tls
+        .as_ref()
+        .map(|tls| crate::tls::paths(tls, "server").0)
+        .filter(|path| path.exists());
+    Ok(Resolved {
+        url: config
+            .server
+            .url
+            .clone()
+            .unwrap_or_else(|| config.server.local_url()),
+        api_key: config.server.api_key,
+        ca_cert,
+        default_device: None,
+    })
+}
+
+pub async fn context_add(
+    name: &str,
+    url: &str,
+    api_key: &str,
+    device: Option<&str>,
+    ca_cert: Option<&str>,
+) -> super::CliResult {
+    if name == LOCAL {
+        return Err(format!("'{LOCAL}' is reserved for the server on this machine").into());
+    }
+    let ca_cert = ca_cert
+        .map(|path| {
+            std::fs::canonicalize(path)
+                .map(|p| p.display().to_string())
+                .map_err(|e| format!("Cannot read {path}: {e}"))
+        })
+        .transpose()?;
+
+    let mut client = ClientConfig::load()?;
+    let first = client.contexts.is_empty();
+    client.contexts.insert(
+        name.to_string(),
+        ClientContext {
+            url: url.trim_end_matches('/').to_string(),
+            api_key: api_key.to_string(),
+            default_device: device.map(String::from),
+            ca_cert,
+        },
+    );
+    if first && client.current.is_none() {
+        client.current = Some(name.to_string());
+    }
+    client.save()?;
+
+    let current = client.current.as_deref() == Some(name);
+    super::emit(&json!({ "context": name, "current": current }), |_| {
+        println!("Added context '{name}'");
+        if !current {
+            println!("Switch to it with `omcli context use {name}`");
+        }
+    });
+    Ok(())
+}
+
+pub async fn context_use(name: &str) -> super::CliResult {
+    let mut client = ClientConfig::load()?;
+    if name == LOCAL {
+        Config::load()?;
+        client.current = None;
+    } else if client.contexts.contains_key(name) {
+        client.current = Some(name.to_string());
+    } else {
+        return Err(format!("Unknown context '{name}' (see `omcli context list`)").into());
+    }
+    client.save()?;
+    super::emit(&json!({ "context": name }), |_| {
+        println!("Using context '{name}'")
+    });
+    Ok(())
+}
+
+pub async fn context_rm(name: &str) -> super::CliResult {
+    let mut client = ClientConfig::load()?;
+    let context = client
+        .contexts
+        .remove(name)
+        .ok_or_else(|| format!("Unknown context '{name}' (see `omcli context list`)"))?;
+    if client.current.as_deref() == Some(name) {
+        client.current = None;
+    }
+    client.save()?;
+    // Certificates pinned by `omcli connect` belong to the context
+    if let Some(path) = context.ca_cert.map(PathBuf::from) {
+        if path.starts_with(Config::certs_dir()) {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+    super::emit(&json!({ "context": name, "removed": true }), |_| {
+        println!("Removed context '{name}'")
+    });
+    Ok(())
+}
+
+pub async fn context_list() -> super::CliResult {
+    let client = ClientConfig::load()?;
+    let current = client.current.as_deref().unwrap_or(LOCAL);
+    let mut list = Vec::new();
+    if let Ok(config) = Config::load() {
+        let url = config
+            .server
+            .url
+            .clone()
+            .unwrap_or_else(|| config.server.local_url());
+        list.push(json!({ "name": LOCAL, "url": url, "current": current == LOCAL }));
+    }
+    for (name, context) in &client.contexts {
+        list.push(json!({
+            "name": name,
+            "url": context.url,
+            "default_device": context.default_device,
+            "current": current == name,
+        }));
+    }
+
+    super::emit(&json!(list), |_| {
+        if list.is_empty() {
+            println!("No contexts. Add one with `omcli connect <url> <code>` or `omcli context add`.");
+            return;
+        }
+        println!("  {:<16} {:<36} DEFAULT DEVICE", "NAME", "URL");
+        for c in &list {
+            let field = |k: &str| c[k].as_str().unwrap_or("").to_string();
+            let marker = if c["current"] == true { "*" } else { " " };
+            println!(
+                "{} {:<16} {:<36} {}",
+                marker,
+                field("name"),
+                field("url"),
+                field("default_device")
+            );
+        }
+    });
+    Ok(())
+}
@@ -1,24 +1,268 @@
-pub async fn list_devices() {
-    match super::api_request(reqwest::Method::GET, "/api/devices", None).await {
-        Ok(resp) => {
-            if let Some(devices) = resp.as_array() {
-                if devices.is_empty() {
-                    println!("No devices paired");
-                    return;
-                }
-                println!("{:<38} {:<20} {:<10}", "ID", "NAME", "STATUS");
-                println!("{}", "-".repeat(68));
-                for d in devices {
-                    let id = d.get("id").and_then(|v| v.as_str()).unwrap_or("?");
-                    let name = d.get("name").and_then(|v| v.as_str()).unwrap_or("?");
-                    let online = d.get("online").and_then(|v| v.as_bool()).unwrap_or(false);
-                    let status = if online { "online" } else { "offline" };
-                    println!("{:<38} {:<20} {:<10}", id, name, status);
-                }
-            } else {
-                println!("{}", serde_json::to_string_pretty(&resp).unwrap());
+use serde_json::json;
+
+use super::output::{json_output, print_json, CliError, ErrorKind};
+use super::CliResult;
+
+pub async fn list_devices(json: bool) -> CliResult {
+    let resp = super::api_request(reqwest::Method::GET, "/api/devices", None).await?;
+    if json || json_output() {
+        print_json(&resp);
+        return Ok(());
+    }
+
+    if let Some(devices) = resp.as_array() {
+        if devices.is_empty() {
+            println!("No devices paired");
+            return Ok(());
+        }
+        println!(
+            "{:<38} {:<20} {:<12} {:<10} GROUPS",
+            "ID", "NAME", "ALIAS", "STATUS"
+        );
+        println!("{}", "-".repeat(92));
+        for d in devices {
+            let id = d.get("id").and_then(|v| v.as_str()).unwrap_or("?");
+            let name = d.get("name").and_then(|v| v.as_str()).unwrap_or("?");
+            let alias = d.get("alias").and_then(|v| v.as_str()).unwrap_or("-");
+            let is_default = d
+                .get("is_default")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false);
+            let online = d.get("online").and_then(|v| v.as_bool()).unwrap_or(false);
+            let status = if online { "online" } else { "offline" };
+            let groups = d
+                .get("groups")
+                .and_then(|v| v.as_array())
+                .map(|g| {
+                    g.iter()
+                        .filter_map(|v| v.as_str())
+                        .collect::<Vec<_>>()
+                        .join(",")
+                })
+                .unwrap_or_default();
+            let marker = if is_default { "*" } else { "" };
+            println!(
+                "{:<38} {:<20} {:<12} {:<10} {}",
+                format!("{id}{marker}"),
+                name,
+                alias,
+                status,
+                groups
+            );
+        }
+        if devices
+            .iter()
+            .any(|d| d.get("is_default") == Some(&true.into()))
+        {
+            println!("\n* default device");
+        }
+    } else {
+        print_json(&resp);
+    }
+    Ok(())
+}
+
+pub async fn group_add(device: &str, group: &str) -> CliResult {
+    let path = format!("/api/devices/{device}/groups");
+    let body = json!({ "group": group });
+    let info = super::api_request(reqwest::Method::POST, &path, Some(body)).await?;
+    super::emit(&info, |_| println!("Added {device} to group {group}"));
+    Ok(())
+}
+
+pub async fn group_rm(device: &str, group: &str) -> CliResult {
+    let path = format!("/api/devices/{device}/groups/{group}");
+    let info = super::api_request(reqwest::Method::DELETE, &path, None).await?;
+    super::emit(&info, |_| println!("Removed {device} from group {group}"));
+    Ok(())
+}
+
+pub async fn show_device(device: &str) -> CliResult {
+    let path = format!("/api/devices/{device}");
+    let d = super::api_request(reqwest::Method::GET, &path, None).await?;
+    if json_output() {
+        print_json(&d);
+        return Ok(());
+    }
+
+    let str_field = |k: &str| d.get(k).and_then(|v| v.as_str()).unwrap_or("-");
+    let bool_field = |k: &str| d.get(k).and_then(|v| v.as_bool()).unwrap_or(false);
+    let time_field = |k: &str| {
+        d.get(k)
+            .and_then(|v| v.as_u64())
+            .map(super::format_time)
+            .unwrap_or_else(|| "-".into())
+    };
+    let yes_no = |b: bool| if b { "yes" } else { "no" };
+
+    let connection = if bool_field("online") {
+        "online (authenticated)"
+    } else if bool_field("connected") {
+        "connected, not authenticated"
+    } else {
+        "offline"
+    };
+    let groups = d
+        .get("groups")
+        .and_then(|v| v.as_array())
+        .map(|g| {
+            g.iter()
+                .filter_map(|v| v.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        })
+        .filter(|g| !g.is_empty())
+        .unwrap_or_else(|| "-".into());
+
+    println!("ID:          {}", str_field("id"));
+    println!("Name:        {}", str_field("name"));
+    println!("Alias:       {}", str_field("alias"));
+    println!("Default:     {}", yes_no(bool_field("is_default")));
+    println!("Groups:      {groups}");
+    println!("Paired:      {}", time_field("paired_at"));
+    if d.get("revoked_at").is_some_and(|v| !v.is_null()) {
+        println!(
+            "Revoked:     {} (must pair again)",
+            time_field("revoked_at")
+        );
+    }
+    println!("Connection:  {connection}");
+    if bool_field("connected") {
+        println!("Connected:   {}", time_field("connected_since"));
+    }
+    println!("Last seen:   {}", time_field("last_seen"));
+    if let Some(addr) = d.get("remote_addr").and_then(|v| v.as_str()) {
+        println!("Address:     {addr}");
+    }
+    if let Some(rtt) = d.get("rtt_ms").and_then(|v| v.as_u64()) {
+        println!("RTT:         {rtt} ms");
+    }
+    println!("Push token:  {}", yes_no(bool_field("has_push_token")));
+    println!("VoIP token:  {}", yes_no(bool_field("has_voip_token")));
+    let platform: Vec<_> = ["platform", "os_version", "model"]
+        .iter()
+        .filter_map(|k| d.get(*k).and_then(|v| v.as_str()))
+        .collect();
+    if !platform.is_empty() {
+        println!("Platform:    {}", platform.join(" "));
+    }
+    if let Some(app) = d.get("app_version").and_then(|v| v.as_str()) {
+        let protocol = d.get("protocol_version").and_then(|v| v.as_u64()).unwrap_or(1);
+        println!("App:         {app} (protocol {protocol})");
+    }
+    if let Some(commands) = d.get("commands").and_then(|v| v.as_array()) {
+        let commands: Vec<_> = commands.iter().filter_map(|v| v.as_str()).collect();
+        println!("Commands:    {}", commands.join(", "));
+    }
+    Ok(())
+}
+
+/// Round trip to one device, or to every online device when none is given.
+pub async fn ping(device: Option<&str>) -> CliResult {
+    if let Some(device) = device {
+        let path = format!("/api/devices/{device}/ping");
+        let resp = super::api_request(reqwest::Method::POST, &path, None).await?;
+        super::emit(&resp, |resp| {
+            println!("{}: {} ms", device, resp["rtt_ms"].as_u64().unwrap_or(0))
+        });
+        return Ok(());
+    }
+
+    let devices = super::api_request(reqwest::Method::GET, "/api/devices", None).await?;
+    let online: Vec<_> = devices
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|d| d["online"].as_bool().unwrap_or(false))
+        .collect();
+    if online.is_empty() {
+        return Err(CliError::new(ErrorKind::Offline, "No devices connected"));
+    }
+
+    let mut results = Vec::new();
+    for d in online {
+        let id = d["id"].as_str().unwrap_or("?");
+        let path = format!("/api/devices/{id}/ping");
+        let result = match super::api_request(reqwest::Method::POST, &path, None).await {
+            Ok(resp) => json!({ "device_id": id, "name": d["name"], "rtt_ms": resp["rtt_ms"] }),
+            Err(e) => json!({ "device_id": id, "name": d["name"], "error": e.message }),
+        };
+        results.push(result);
+    }
+    let failed = results.iter().filter(|r| r.get("error").is_some()).count();
+    super::emit(&json!(results), |_| {
+        for r in &results {
+            let name = r["name"].as_str().unwrap_or("?");
+            let id = r["device_id"].as_str().unwrap_or("?");
+            match r["rtt_ms"].as_u64() {
+                Some(rtt) => println!("{name} ({id}): {rtt} ms"),
+                None => println!("{name} ({id}): {}", r["error"].as_str().unwrap_or("failed")),
             }
         }
-        Err(e) => eprintln!("Error: {e}"),
+    });
+    if failed > 0 {
+        let message = format!("{failed} device(s) did not answer");
+        return Err(CliError::new(ErrorKind::Failed, message).reported());
     }
+    Ok(())
+}
+
+pub async fn remove_device(device: &str) -> CliResult {
+    let path = format!("/api/devices/{device}");
+    super::api_request(reqwest::Method::DELETE, &path, None).await?;
+    super::emit(&json!({ "device": device, "removed": true }), |_| {
+        println!("Removed {device}")
+    });
+    Ok(())
+}
+
+pub async fn revoke_device(device: &str) -> CliResult {
+    let path = format!("/api/devices/{device}/revoke");
+    let info = super::api_request(reqwest::Method::POST, &path, None).await?;
+    super::emit(&info, |info| {
+        println!("Revoked {}; it must pair again", device_label(info));
+        if info.get("connected").and_then(|v| v.as_bool()) == Some(true) {
+            println!("A new pairing code is shown on the device");
+        }
+    });
+    Ok(())
+}
+
+pub async fn rename(device: &str, name: &str) -> CliResult {
+    let info = update(device, json!({ "name": name })).await?;
+    super::emit(&info, |_| println!("Renamed {device} to {name}"));
+    Ok(())
+}
+
+pub async fn set_alias(device: &str, alias: Option<&str>) -> CliResult {
+    let info = update(device, json!({ "alias": alias.unwrap_or("") })).await?;
+    super::emit(&info, |info| match alias {
+        Some(alias) => println!("{} is now reachable as {alias}", device_label(info)),
+        None => println!("Cleared alias of {}", device_label(info)),
+    });
+    Ok(())
+}
+
+pub async fn set_default(device: &str, unset: bool) -> CliResult {
+    let info = update(device, json!({ "is_default": !unset })).await?;
+    super::emit(&info, |info| {
+        if unset {
+            println!("{} is no longer the default device", device_label(info));
+        } else {
+            println!("{} is now the default device", device_label(info));
+        }
+    });
+    Ok(())
+}
+
+async fn update(
+    device: &str,
+    body: serde_json::Value,
+) -> Result<serde_json::Value, super::CliError> {
+    let path = format!("/api/devices/{device}");
+    super::api_request(reqwest::Method::PATCH, &path, Some(body)).await
+}
+
+fn device_label(info: &serde_json::Value) -> &str {
+    info.get("name").and_then(|v| v.as_str()).unwrap_or("?")
 }
@@ -0,0 +1,152 @@
+use serde_json::json;
+
+use super::output::{CliError, ErrorKind};
+use crate::config::{ClientConfig, Config};
+use crate::protocol::{CheckStatus, DoctorCheck, ErrorCode};
+
+/// Check the config, the server and everything push delivery depends on,
+/// printing a pass/warn/fail report. Fails when any check fails.
+pub async fn doctor() -> super::CliResult {
+    let mut checks = Vec::new();
+
+    let client = ClientConfig::load();
+    if let Err(e) = &client {
+        checks.push(DoctorCheck::fail(
+            "config",
+            e.clone(),
+            format!("Fix or remove {}", Config::client_path().display()),
+        ));
+    }
+    let remote = client.is_ok_and(|c| super::context::selected(&c).is_some());
+
+    let config = if Config::config_path().exists() {
+        match Config::load() {
+            Ok(config) => {
+                checks.push(DoctorCheck::pass(
+                    "config",
+                    format!("{} loads", Config::config_path().display()),
+                ));
+                Some(config)
+            }
+            Err(e) => {
+                checks.push(DoctorCheck::fail(
+                    "config",
+                    e,
+                    "`omcli config keys` lists the valid keys; an OMCLI_* variable named in the \
+                     error overrides the file",
+                ));
+                None
+            }
+        }
+    } else {
+        if !remote {
+            checks.push(DoctorCheck::fail(
+                "config",
+                format!("No config at {}", Config::config_path().display()),
+                "Run `omcli serve` once to create it, or `omcli connect <url> <code>` to use a \
+                 remote server",
+            ));
+        }
+        None
+    };
+
+    let server_checks = match check_server(&mut checks).await {
+        Some(server_checks) => server_checks,
+        // The server cannot say how push is set up; config.toml can, if this is its machine
+        None => match &config {
+            Some(config) if !remote => crate::server::doctor::local_push_checks(config).await,
+            _ => Vec::new(),
+        },
+    };
+    checks.extend(server_checks);
+
+    let failed = checks.iter().filter(|c| c.status == CheckStatus::Fail).count();
+    let warned = checks.iter().filter(|c| c.status == CheckStatus::Warn).count();
+    super::emit(&json!({ "ok": failed == 0, "checks": checks }), |_| {
+        for check in &checks {
+            let label = match check.status {
+                CheckStatus::Pass => "PASS",
+                CheckStatus::Warn => "WARN",
+                CheckStatus::Fail => "FAIL",
+            };
+            println!("[{label}] {:<8} {}", check.name, check.message);
+            if let Some(hint) = &check.hint {
+                println!("                {hint}");
+            }
+        }
+        println!();
+        println!("{} passed, {warned} warning(s), {failed} failed", checks.len() - warned - failed);
+    });
+
+    if failed > 0 {
+        return Err(CliError::new(ErrorKind::Failed, format!("{failed} check(s) failed")).reported());
+    }
+    Ok(())
+}
+
+/// Reachability and the API key, then the server's own checks. Returns `None`
+/// when the server could not be asked.
+async fn check_server(checks: &mut Vec<DoctorCheck>) -> Option<Vec<DoctorCheck>> {
+    let url = match super::context::resolve() {
+        Ok(resolved) => resolved.url,
+        Err(e) => {
+            checks.push(DoctorCheck::fail(
+                "server",
+                e.message,
+                "`omcli context list` shows the servers this CLI knows",
+            ));
+            return None;
+        }
+    };
+
+    match super::api_request(reqwest::Method::GET, "/api/status", None).await {
+        Ok(status) => {
+            let version = status["version"].as_str().unwrap_or("?");
+            checks.push(DoctorCheck::pass(
+                "server",
+                format!("Reachable at {url} (omcli {version})"),
+            ));
+            checks.push(DoctorCheck::pass("auth", "API key accepted"));
+        }
+        Err(e) if e.kind == ErrorKind::Unreachable => {
+            checks.push(DoctorCheck::fail(
+                "server",
+                e.message,
+                "Start it with `omcli serve`, or check the URL with `omcli context list`",
+            ));
+            return None;
+        }
+        Err(e) if e.kind == ErrorKind::Auth => {
+            checks.push(DoctorCheck::pass("server", format!("Reachable at {url}")));
+            checks.push(DoctorCheck::fail(
+                "auth",
+                format!("API key rejected: {}", e.message),
+                "The local server's key is server.api_key (`omcli config get api_key`); for a \
+                 context, run `omcli connect` again with a new code",
+            ));
+            return None;
+        }
+        Err(e) => {
+            checks.push(DoctorCheck::fail(
+                "server",
+                e.message,
+                format!("Is {url} an omcli server?"),
+            ));
+            return None;
+        }
+    }
+
+    match super::api_request(reqwest::Method::GET, "/api/doctor", None).await {
+        Ok(resp) => Some(serde_json::from_value(resp).unwrap_or_default()),
+        Err(e) if e.code == Some(ErrorCode::Forbidden) => Some(vec![DoctorCheck::warn(
+            "push",
+            "Push and device checks need the admin key",
+            "Run `omcli doctor` with the admin key, e.g. on the server itself",
+        )]),
+        Err(e) => Some(vec![DoctorCheck::warn(
+            "push",
+            format!("The server did not run its checks: {}", e.message),
+            "Upgrade the server to this version of omcli",
+        )]),
+    }
+}
@@ -0,0 +1,277 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use openssl::derive::Deriver;
+use openssl::pkey::{Id, PKey, Private};
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+use sha2::{Digest, Sha256};
+
+use crate::config::{self, Config};
+use crate::protocol::SealedEnvelope;
+
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// The CLI's X25519 key pair, created in the data dir on first use
+pub(crate) struct Identity {
+    key: PKey<Private>,
+    public: Vec<u8>,
+}
+
+impl Identity {
+    pub fn load_or_create() -> Result<Self, String> {
+        let path = Config::e2e_key_path();
+        let key = match std::fs::read(&path) {
+            Ok(pem) => PKey::private_key_from_pem(&pem)
+                .map_err(|e| format!("Invalid key in {}: {e}", path.display()))?,
+            Err(_) => {
+                let key = PKey::generate_x25519()
+                    .map_err(|e| format!("Failed to generate key: {e}"))?;
+                let pem = key
+                    .private_key_to_pem_pkcs8()
+                    .map_err(|e| format!("Failed to encode key: {e}"))?;
+                std::fs::create_dir_all(Config::data_dir())
+                    .map_err(|e| format!("Failed to create data dir: {e}"))?;
+                config::write_private(&path, &pem)?;
+                key
+            }
+        };
+        let public = key
+            .raw_public_key()
+            .map_err(|e| format!("Failed to read public key: {e}"))?;
+        Ok(Self { key, public })
+    }
+
+    pub fn public_key(&self) -> String {
+        STANDARD.encode(&self.public)
+    }
+
+    /// Sent at pairing so the device can check the key revealed afterwards
+    pub fn commitment(&self) -> String {
+        format!("{:x}", Sha256::digest(&self.public))
+    }
+
+    /// Short authentication string both ends show after the exchange
+    pub fn sas(&self, device_key: &str) -> Result<String, String> {
+        let device = decode_key(device_key)?;
+        let mut hasher = Sha256::new();
+        hasher.update(b"omcli-sas");
+        hasher.update(&self.public);
+        hasher.update(&device);
+        let digest = hasher.finalize();
+        let n = u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]) % 1_000_000;
+        Ok(format!("{:03} {:03}", n / 1000, n % 1000))
+    }
+
+    /// AES-256 key shared with a device: SHA-256 of the X25519 secret and both public keys.
+    pub fn session_key(&self, device_key: &str) -> Result<Vec<u8>, String> {
+        let device = decode_key(device_key)?;
+        let peer = PKey::public_key_from_raw_bytes(&device, Id::X25519)
+            .map_err(|e| format!("Invalid device key: {e}"))?;
+        let shared = Deriver::new(&self.key)
+            .and_then(|mut d| {
+                d.set_peer(&peer)?;
+                d.derive_to_vec()
+            })
+            .map_err(|e| format!("Key agreement failed: {e}"))?;
+        let mut hasher = Sha256::new();
+        hasher.update(&shared);
+        hasher.update(&self.public);
+        hasher.update(&device);
+        Ok(hasher.finalize().to_vec())
+    }
+}
+
+fn decode_key(key: &str) -> Result<Vec<u8>, String> {
+    match STANDARD.decode(key) {
+        Ok(bytes) if bytes.len() == 32 => Ok(bytes),
+        _ => Err("Invalid end-to-end public key".to_string()),
+    }
+}
+
+/// What sealed params are bound to: the command id, or for a schedule, which
+/// sends the same params on every run, the schedule id
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Binding<'a> {
+    Command(&'a str),
+    Schedule(&'a str),
+}
+
+impl Binding<'_> {
+    fn id(&self) -> String {
+        match self {
+            Binding::Command(id) => id.to_string(),
+            Binding::Schedule(id) => format!("schedule:{id}"),
+        }
+    }
+}
+
+/// Associated data binding a payload to its direction, command, device and
+/// id, so the server can't replay it under another command id, move it to
+/// another command or device, or pass a response off as params.
+fn aad(kind: &str, command: &str, device_id: &str, id: &str) -> Vec<u8> {
+    format!("omcli-e2e {kind} {command} {device_id} {id}").into_bytes()
+}
+
+fn seal(key: &[u8], aad: &[u8], value: &serde_json::Value) -> Result<String, String> {
+    let plaintext = serde_json::to_vec(value).map_err(|e| e.to_string())?;
+    let mut nonce = [0u8; NONCE_LEN];
+    openssl::rand::rand_bytes(&mut nonce).map_err(|e| e.to_string())?;
+    let mut tag = [0u8; TAG_LEN];
+    let ciphertext = encrypt_aead(
+        Cipher::aes_256_gcm(),
+        key,
+        Some(&nonce),
+        aad,
+        &plaintext,
+        &mut tag,
+    )
+    .map_err(|e| format!("Encryption failed: {e}"))?;
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    sealed.extend_from_slice(&tag);
+    Ok(STANDARD.encode(sealed))
+}
+
+fn open(key: &[u8], aad: &[u8], sealed: &str) -> Result<serde_json::Value, String> {
+    let sealed = STANDARD
+        .decode(sealed)
+        .map_err(|_| "Sealed payload is not valid base64".to_string())?;
+    if sealed.len() < NONCE_LEN + TAG_LEN {
+        return Err("Sealed payload is too short".to_string());
+    }
+    let (nonce, rest) = sealed.split_at(NONCE_LEN);
+    let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+    let plaintext = decrypt_aead(
+        Cipher::aes_256_gcm(),
+        key,
+        Some(nonce),
+        aad,
+        ciphertext,
+        tag,
+    )
+    .map_err(|_| "Could not decrypt the device's response".to_string())?;
+    serde_json::from_slice(&plaintext).map_err(|e| format!("Invalid decrypted response: {e}"))
+}
+
+/// Seal command params for the device.
+pub(crate) fn seal_params(
+    key: &[u8],
+    command: &str,
+    device_id: &str,
+    binding: Binding,
+    params: &serde_json::Value,
+) -> Result<serde_json::Value, String> {
+    let e2e = seal(
+        key,
+        &aad("params", command, device_id, &binding.id()),
+        params,
+    )?;
+    let schedule = match binding {
+        Binding::Schedule(id) => Some(id.to_string()),
+        Binding::Command(_) => None,
+    };
+    Ok(serde_json::json!(SealedEnvelope { e2e, schedule }))
+}
+
+/// Open response data the device sealed for command `id`.
+pub(crate) fn open_data(
+    key: &[u8],
+    command: &str,
+    device_id: &str,
+    id: &str,
+    envelope: &SealedEnvelope,
+) -> Result<serde_json::Value, String> {
+    open(key, &aad("data", command, device_id, id), &envelope.e2e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const KEY: [u8; 32] = [7; 32];
+
+    fn sealed_data(
+        command: &str,
+        device_id: &str,
+        id: &str,
+        data: &serde_json::Value,
+    ) -> SealedEnvelope {
+        SealedEnvelope {
+            e2e: seal(&KEY, &aad("data", command, device_id, id), data).unwrap(),
+            schedule: None,
+        }
+    }
+
+    #[test]
+    fn params_round_trip() {
+        let params = json!({"accuracy": "precise"});
+        let sealed = seal_params(
+            &KEY,
+            "location.get",
+            "dev1",
+            Binding::Command("c1"),
+            &params,
+        )
+        .unwrap();
+        let envelope = SealedEnvelope::from_value(&sealed).unwrap();
+        assert_eq!(envelope.schedule, None);
+        let aad = aad("params", "location.get", "dev1", "c1");
+        assert_eq!(open(&KEY, &aad, &envelope.e2e).unwrap(), params);
+    }
+
+    #[test]
+    fn schedule_params_are_bound_to_the_schedule() {
+        let params = json!({"sound": "loud"});
+        let sealed = seal_params(
+            &KEY,
+            "alarm.start",
+            "dev1",
+            Binding::Schedule("s1"),
+            &params,
+        )
+        .unwrap();
+        let envelope = SealedEnvelope::from_value(&sealed).unwrap();
+        assert_eq!(envelope.schedule.as_deref(), Some("s1"));
+        let aad = aad("params", "alarm.start", "dev1", "schedule:s1");
+        assert_eq!(open(&KEY, &aad, &envelope.e2e).unwrap(), params);
+        assert!(open(&KEY, b"omcli-e2e params alarm.start dev1 s1", &envelope.e2e).is_err());
+    }
+
+    #[test]
+    fn data_round_trip() {
+        let data = json!({"lat": 1.5, "lon": 2.5});
+        let envelope = sealed_data("location.get", "dev1", "c1", &data);
+        assert_eq!(
+            open_data(&KEY, "location.get", "dev1", "c1", &envelope).unwrap(),
+            data
+        );
+    }
+
+    #[test]
+    fn open_with_wrong_aad_fails() {
+        let envelope = sealed_data("camera.snap", "dev1", "c1", &json!({"photo": "..."}));
+        // An old response passed off as the answer to a new command
+        assert!(open_data(&KEY, "camera.snap", "dev1", "c2", &envelope).is_err());
+        assert!(open_data(&KEY, "camera.snap", "dev2", "c1", &envelope).is_err());
+        assert!(open_data(&KEY, "location.get", "dev1", "c1", &envelope).is_err());
+        // Params are never accepted as a response
+        let aad = aad("params", "camera.snap", "dev1", "c1");
+        assert!(open(&KEY, &aad, &envelope.e2e).is_err());
+    }
+
+    #[test]
+    fn open_with_wrong_key_or_tampered_payload_fails() {
+        let envelope = sealed_data("location.get", "dev1", "c1", &json!({}));
+        assert!(open_data(&[8; 32], "location.get", "dev1", "c1", &envelope).is_err());
+
+        let mut bytes = STANDARD.decode(&envelope.e2e).unwrap();
+        bytes[NONCE_LEN] ^= 1;
+        let tampered = SealedEnvelope {
+            e2e: STANDARD.encode(bytes),
+            schedule: None,
+        };
+        assert!(open_data(&KEY, "location.get", "dev1", "c1", &tampered).is_err());
+    }
+}
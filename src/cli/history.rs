@@ -0,0 +1,75 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub async fn history(
+    device: Option<&str>,
+    command: Option<&str>,
+    since: Option<&str>,
+    limit: usize,
+) -> super::CliResult {
+    let mut query = vec![format!("limit={limit}")];
+    if let Some(dev) = device {
+        query.push(format!("device={dev}"));
+    }
+    if let Some(cmd) = command {
+        query.push(format!("command={cmd}"));
+    }
+    if let Some(since) = since {
+        query.push(format!("since={}", parse_since(since)?));
+    }
+    let path = format!("/api/commands?{}", query.join("&"));
+
+    let resp = super::api_request(reqwest::Method::GET, &path, None).await?;
+    super::emit(&resp, |resp| {
+        let Some(entries) = resp.as_array() else {
+            println!("{}", serde_json::to_string_pretty(resp).unwrap());
+            return;
+        };
+        if entries.is_empty() {
+            println!("No commands recorded");
+            return;
+        }
+        println!(
+            "{:<38} {:<14} {:<10} {:<12} {:>8}  {:<20} SOURCE",
+            "ID", "COMMAND", "STATE", "VIA", "LATENCY", "DEVICE"
+        );
+        println!("{}", "-".repeat(112));
+        for e in entries {
+            let field = |k: &str| e.get(k).and_then(|v| v.as_str()).unwrap_or("-");
+            let latency = e.get("latency_ms").and_then(|v| v.as_u64()).unwrap_or(0);
+            println!(
+                "{:<38} {:<14} {:<10} {:<12} {:>6}ms  {:<20} {}",
+                field("id"),
+                field("command"),
+                field("state"),
+                field("delivered_via"),
+                latency,
+                field("device_id"),
+                field("source"),
+            );
+        }
+    });
+    Ok(())
+}
+
+pub async fn history_replay(id: &str) -> super::CliResult {
+    let path = format!("/api/commands/{id}/replay");
+    let resp = super::api_request(reqwest::Method::POST, &path, None).await?;
+    super::finish_command(&resp, "replayed", |resp| {
+        println!("{}", serde_json::to_string_pretty(resp).unwrap())
+    })
+}
+
+/// Accept a unix timestamp or a relative duration like `30m`, `6h`, `2d`.
+fn parse_since(since: &str) -> Result<u64, String> {
+    if let Ok(ts) = since.parse::<u64>() {
+        return Ok(ts);
+    }
+    let secs = super::parse_duration(since).ok_or_else(|| {
+        format!("Invalid --since '{since}': use e.g. 30m, 6h, 2d or a unix timestamp")
+    })?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    Ok(now.saturating_sub(secs))
+}
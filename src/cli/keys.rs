@@ -0,0 +1,151 @@
+use serde_json::json;
+
+use crate::protocol::EnrollmentInfo;
+
+pub async fn create_key(
+    name: &str,
+    commands: &[String],
+    devices: &[String],
+    read_only: bool,
+    expires: Option<&str>,
+) -> super::CliResult {
+    let mut body = json!({
+        "name": name,
+        "commands": commands,
+        "devices": devices,
+        "read_only": read_only,
+    });
+    if let Some(expires) = expires {
+        let ttl = super::parse_duration(expires)
+            .ok_or_else(|| format!("Invalid --expires '{expires}': use e.g. 12h, 30d"))?;
+        body["ttl_secs"] = json!(ttl);
+    }
+
+    let resp = super::api_request(reqwest::Method::POST, "/api/keys", Some(body)).await?;
+    super::emit(&resp, |resp| {
+        let field = |k: &str| resp.get(k).and_then(|v| v.as_str()).unwrap_or("?");
+        println!("Created API key '{}' ({})", field("name"), field("id"));
+        println!("Scope: {}", describe_scope(resp));
+        if let Some(exp) = resp.get("expires_at").and_then(|v| v.as_u64()) {
+            println!("Expires: {}", super::format_time(exp));
+        }
+        println!();
+        println!("  {}", field("key"));
+        println!();
+        println!("Store it now, it will not be shown again.");
+    });
+    Ok(())
+}
+
+pub async fn list_keys() -> super::CliResult {
+    let resp = super::api_request(reqwest::Method::GET, "/api/keys", None).await?;
+    super::emit(&resp, |resp| {
+        let Some(keys) = resp.as_array() else {
+            println!("{}", serde_json::to_string_pretty(resp).unwrap());
+            return;
+        };
+        if keys.is_empty() {
+            println!("No API keys (only the admin key from config.toml)");
+            return;
+        }
+        println!(
+            "{:<38} {:<16} {:<10} {:<18} SCOPE",
+            "ID", "NAME", "PREFIX", "EXPIRES"
+        );
+        println!("{}", "-".repeat(100));
+        for k in keys {
+            let field = |key: &str| k.get(key).and_then(|v| v.as_str()).unwrap_or("?");
+            let expired = k.get("expired").and_then(|v| v.as_bool()) == Some(true);
+            let expires = match k.get("expires_at").and_then(|v| v.as_u64()) {
+                Some(_) if expired => "expired".to_string(),
+                Some(exp) => super::format_time(exp),
+                None => "never".to_string(),
+            };
+            println!(
+                "{:<38} {:<16} {:<10} {:<18} {}",
+                field("id"),
+                field("name"),
+                field("prefix"),
+                expires,
+                describe_scope(k)
+            );
+        }
+    });
+    Ok(())
+}
+
+pub async fn revoke_key(key: &str) -> super::CliResult {
+    let path = format!("/api/keys/{key}");
+    super::api_request(reqwest::Method::DELETE, &path, None).await?;
+    super::emit(&json!({ "key": key, "revoked": true }), |_| {
+        println!("API key {key} revoked")
+    });
+    Ok(())
+}
+
+fn describe_scope(key: &serde_json::Value) -> String {
+    let list = |k: &str| {
+        key.get(k)
+            .and_then(|v| v.as_array())
+            .filter(|a| !a.is_empty())
+            .map(|a| {
+                a.iter()
+                    .filter_map(|v| v.as_str())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            })
+    };
+    let mut parts = Vec::new();
+    if key.get("read_only").and_then(|v| v.as_bool()) == Some(true) {
+        parts.push("read-only".to_string());
+    }
+    if let Some(commands) = list("commands") {
+        parts.push(format!("commands={commands}"));
+    }
+    if let Some(devices) = list("devices") {
+        parts.push(format!("devices={devices}"));
+    }
+    if parts.is_empty() {
+        "all commands, all devices".to_string()
+    } else {
+        parts.join(" ")
+    }
+}
+
+pub async fn create_enrollment(
+    name: Option<&str>,
+    commands: &[String],
+    devices: &[String],
+    read_only: bool,
+    expires: Option<&str>,
+) -> super::CliResult {
+    let mut body = json!({
+        "commands": commands,
+        "devices": devices,
+        "read_only": read_only,
+    });
+    let scope = describe_scope(&body);
+    if let Some(name) = name {
+        body["name"] = json!(name);
+    }
+    if let Some(expires) = expires {
+        let ttl = super::parse_duration(expires)
+            .ok_or_else(|| format!("Invalid --expires '{expires}': use e.g. 12h, 30d"))?;
+        body["ttl_secs"] = json!(ttl);
+    }
+
+    let resp = super::api_request(reqwest::Method::POST, "/api/keys/enroll", Some(body)).await?;
+    super::emit(&resp, |resp| {
+        let Ok(info) = serde_json::from_value::<EnrollmentInfo>(resp.clone()) else {
+            println!("{}", serde_json::to_string_pretty(resp).unwrap());
+            return;
+        };
+        println!("Enrollment code: {}", info.code);
+        println!("Valid until:     {}", super::format_time(info.expires_at));
+        println!("Key scope:       {}", scope);
+        println!();
+        println!("On the other machine run:");
+        println!("  {}", info.connect_command());
+    });
+    Ok(())
+}
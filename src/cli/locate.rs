@@ -1,31 +1,63 @@
-use serde_json::json;
+use super::output::json_output;
+use crate::protocol::command::{Accuracy, Command, LocationParams};
 
-pub async fn locate(device: Option<&str>) {
-    let mut body = json!({
-        "command": "location.get",
-        "params": { "accuracy": "precise" },
-    });
-    if let Some(dev) = device {
-        body["device_id"] = json!(dev);
-    }
+pub async fn locate(device: Option<&str>, group: Option<&str>) -> super::CliResult {
+    let mut body = Command::LocationGet(LocationParams {
+        accuracy: Accuracy::Precise,
+    })
+    .body();
+    super::set_target(&mut body, device, group);
 
-    match super::api_request(reqwest::Method::POST, "/api/command", Some(body)).await {
-        Ok(resp) => {
-            if let Some(data) = resp.get("data") {
-                if let Some(lat) = data.get("lat") {
-                    if let Some(lon) = data.get("lon") {
-                        println!("Location: {}, {}", lat, lon);
-                        if let Some(acc) = data.get("accuracy") {
-                            println!("Accuracy: {}m", acc);
-                        }
-                        return;
+    let resp = super::post_command(body).await?;
+    let results = resp.get("results").and_then(|r| r.as_array());
+    if let Some(results) = results.filter(|_| !json_output()) {
+        for r in results {
+            print_location(r);
+        }
+        return super::command_outcome(&resp).map_err(super::CliError::reported);
+    }
+    super::finish_command(&resp, "located", |resp| {
+        if let Some(data) = resp.get("data") {
+            if let Some(lat) = data.get("lat") {
+                if let Some(lon) = data.get("lon") {
+                    println!("Location: {}, {}", lat, lon);
+                    if let Some(acc) = data.get("accuracy") {
+                        println!("Accuracy: {}m", acc);
                     }
+                    return;
                 }
-                println!("{}", serde_json::to_string_pretty(data).unwrap());
-            } else {
-                println!("{}", serde_json::to_string_pretty(&resp).unwrap());
             }
+            println!("{}", serde_json::to_string_pretty(data).unwrap());
+        } else {
+            println!("{}", serde_json::to_string_pretty(resp).unwrap());
+        }
+    })
+}
+
+fn print_location(result: &serde_json::Value) {
+    let device = result
+        .get("device_id")
+        .and_then(|v| v.as_str())
+        .unwrap_or("?");
+    if let Some(err) = result.get("error") {
+        let msg = err.get("message").and_then(|m| m.as_str()).unwrap_or("?");
+        println!("{device}: error: {msg}");
+        return;
+    }
+    if result.get("pending").is_some() {
+        println!("{device}: offline, queued");
+        return;
+    }
+    let data = result.get("response").and_then(|r| r.get("data"));
+    match data.and_then(|d| Some((d.get("lat")?, d.get("lon")?))) {
+        Some((lat, lon)) => println!("{device}: {lat}, {lon}"),
+        None => {
+            let msg = result
+                .get("response")
+                .and_then(|r| r.get("error"))
+                .and_then(|e| e.as_str())
+                .unwrap_or("no location in response");
+            println!("{device}: error: {msg}");
         }
-        Err(e) => eprintln!("Error: {e}"),
     }
 }
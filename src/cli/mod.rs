@@ -1,43 +1,109 @@
 mod alarm;
 mod camera;
 mod config_cmd;
+mod connect;
+mod context;
 mod devices;
+mod doctor;
+mod e2e;
+mod history;
+mod keys;
 mod locate;
 mod notify;
+mod openapi;
+mod output;
 mod pair;
+mod schedule;
 mod sleep;
 mod status;
 
 pub use alarm::{alarm_start, alarm_stop};
 pub use camera::camera_snap;
-pub use config_cmd::{set_config, show_config};
-pub use devices::list_devices;
+pub use config_cmd::{
+    get_config, list_keys as config_keys, reload_config, set_config, show_config, unset_config,
+};
+pub use connect::connect;
+pub use context::{context_add, context_list, context_rm, context_use, set_context};
+pub use devices::{
+    group_add, group_rm, list_devices, ping, remove_device, rename, revoke_device, set_alias,
+    set_default, show_device,
+};
+pub use doctor::doctor;
+pub use history::{history, history_replay};
+pub use keys::{create_enrollment, create_key, list_keys, revoke_key};
 pub use locate::locate;
 pub use notify::send_notification;
+pub use openapi::print_openapi;
+pub use output::{set_output, CliError, CliResult, ErrorKind, OutputFormat};
 pub use pair::pair;
+pub use schedule::{schedule_add, schedule_list, schedule_rm};
 pub use sleep::{sleep_start, sleep_stop};
 pub use status::server_status;
 
-use crate::config::Config;
+use crate::config;
+use crate::protocol::{command, ApiErrorBody, SealedEnvelope};
+use output::{json_output, print_json};
+use std::path::Path;
+
+/// Where API requests go, see `context::resolve`.
+struct Target {
+    url: String,
+    api_key: String,
+    client: reqwest::Client,
+}
+
+fn target() -> Result<Target, CliError> {
+    let resolved = context::resolve()?;
+    Ok(Target {
+        client: http_client(resolved.ca_cert.as_deref())?,
+        url: resolved.url,
+        api_key: resolved.api_key,
+    })
+}
+
+/// HTTP client that also trusts `ca_cert`, if given.
+fn http_client(ca_cert: Option<&Path>) -> Result<reqwest::Client, CliError> {
+    let mut builder = reqwest::Client::builder();
+    if let Some(path) = ca_cert {
+        let pem = std::fs::read(path)
+            .map_err(|e| format!("Failed to read certificate {}: {e}", path.display()))?;
+        let cert = reqwest::Certificate::from_pem(&pem)
+            .map_err(|e| format!("Invalid certificate {}: {e}", path.display()))?;
+        builder = builder.add_root_certificate(cert);
+    }
+    builder
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {e}").into())
+}
 
 pub(crate) async fn api_request(
     method: reqwest::Method,
     path: &str,
     body: Option<serde_json::Value>,
-) -> Result<serde_json::Value, String> {
-    let config = Config::load()?;
-    let url = format!("{}{}", config.server.url, path);
-    let client = reqwest::Client::new();
+) -> Result<serde_json::Value, CliError> {
+    let target = target()?;
+    let url = format!("{}{}", target.url, path);
 
-    let mut req = client
+    let mut req = target
+        .client
         .request(method, &url)
-        .header("Authorization", format!("Bearer {}", config.server.api_key));
+        .header("Authorization", format!("Bearer {}", target.api_key));
 
     if let Some(body) = body {
         req = req.json(&body);
     }
 
-    let resp = req.send().await.map_err(|e| format!("Request failed: {e}"))?;
+    let resp = req.send().await.map_err(|e| {
+        CliError::new(
+            ErrorKind::Unreachable,
+            format!("Cannot reach server at {}: {e}", target.url),
+        )
+    })?;
+    read_response(resp).await
+}
+
+/// Parse a JSON response, turning error statuses into a `CliError`.
+async fn read_response(resp: reqwest::Response) -> Result<serde_json::Value, CliError> {
     let status = resp.status();
     let text = resp
         .text()
@@ -45,12 +111,340 @@ pub(crate) async fn api_request(
         .map_err(|e| format!("Failed to read response: {e}"))?;
 
     if !status.is_success() {
-        return Err(format!("Server error ({}): {}", status, text));
+        if let Ok(body) = serde_json::from_str::<ApiErrorBody>(&text) {
+            return Err(CliError::from_api(body.error));
+        }
+        // Proxies and older servers answer with plain text
+        let kind = match status {
+            reqwest::StatusCode::UNAUTHORIZED => ErrorKind::Auth,
+            reqwest::StatusCode::GATEWAY_TIMEOUT => ErrorKind::Timeout,
+            _ => ErrorKind::Failed,
+        };
+        return Err(CliError::new(
+            kind,
+            format!("Server error ({}): {}", status, text),
+        ));
     }
 
     if text.is_empty() {
         return Ok(serde_json::Value::Null);
     }
 
-    serde_json::from_str(&text).map_err(|e| format!("Failed to parse response: {e}"))
+    serde_json::from_str(&text).map_err(|e| format!("Failed to parse response: {e}").into())
+}
+
+/// POST /api/command, sealing the params when the target device exchanged
+/// end-to-end keys with this CLI and opening the sealed response data.
+pub(crate) async fn post_command(
+    mut body: serde_json::Value,
+) -> Result<serde_json::Value, CliError> {
+    check_params(&body)?;
+    apply_default_device(&mut body)?;
+    let key = seal_for_device(&mut body, false).await?;
+    let mut resp = api_request(reqwest::Method::POST, "/api/command", Some(body.clone())).await?;
+    if let Some(key) = key {
+        // Sealing pinned the device and picked the command id
+        let id = body["id"].as_str().unwrap_or_default();
+        if resp["id"].as_str().is_some_and(|r| r != id) {
+            return Err(format!(
+                "The server answered for command {} instead of {id}",
+                resp["id"].as_str().unwrap_or_default()
+            )
+            .into());
+        }
+        let command = body["command"].as_str().unwrap_or_default();
+        let device_id = body["device_id"].as_str().unwrap_or_default();
+        if let Some(envelope) = resp.get("data").and_then(SealedEnvelope::from_value) {
+            resp["data"] = e2e::open_data(&key, command, device_id, id, &envelope)?;
+        }
+    }
+    Ok(resp)
+}
+
+/// Check a known command's params the way the server would, while they can
+/// still be read: sealed params reach the device unchecked. Unknown commands
+/// are left to the server, which may know more of them than this CLI.
+pub(crate) fn check_params(body: &serde_json::Value) -> Result<(), CliError> {
+    if body["custom"].as_bool() == Some(true) {
+        return Ok(());
+    }
+    match body["command"].as_str().and_then(command::find) {
+        Some(spec) => spec.check(&body["params"]).map_err(CliError::from_api),
+        None => Ok(()),
+    }
+}
+
+/// Target the context's default device when the command names no device or group.
+pub(crate) fn apply_default_device(body: &mut serde_json::Value) -> Result<(), CliError> {
+    let targeted = ["device_id", "device_ids", "group"]
+        .iter()
+        .any(|k| body.get(k).is_some());
+    if !targeted {
+        if let Some(device) = context::resolve()?.default_device {
+            body["device_id"] = serde_json::json!(device);
+        }
+    }
+    Ok(())
+}
+
+/// Replace `params` with a sealed envelope if the single target device has a
+/// verified end-to-end key, pinning `device_id` to it and setting the `id` the
+/// envelope is bound to: the command's, or the schedule's for `schedule`.
+/// Returns the session key.
+///
+/// Fan-out commands are sent as they are; devices with encryption on refuse them.
+pub(crate) async fn seal_for_device(
+    body: &mut serde_json::Value,
+    schedule: bool,
+) -> Result<Option<Vec<u8>>, CliError> {
+    let peers = config::load_e2e_peers();
+    let fanout = body.get("group").is_some()
+        || body.get("device_ids").is_some()
+        || body["device_id"].as_str().is_some_and(|d| d.contains(','));
+    if peers.is_empty() || fanout {
+        return Ok(None);
+    }
+
+    let device_id = match body["device_id"].as_str() {
+        Some(query) => {
+            let path = format!("/api/devices/{}", query);
+            let device = api_request(reqwest::Method::GET, &path, None).await?;
+            device["id"].as_str().map(String::from)
+        }
+        None => default_device_id().await?,
+    };
+    let Some((device_id, device_key)) =
+        device_id.and_then(|id| peers.get(&id).map(|key| (id, key.clone())))
+    else {
+        return Ok(None);
+    };
+
+    let identity = e2e::Identity::load_or_create()?;
+    let key = identity.session_key(&device_key)?;
+    let command = body["command"].as_str().unwrap_or_default().to_string();
+    let id = uuid::Uuid::new_v4().to_string();
+    let binding = match schedule {
+        true => e2e::Binding::Schedule(&id),
+        false => e2e::Binding::Command(&id),
+    };
+    body["params"] = e2e::seal_params(&key, &command, &device_id, binding, &body["params"])?;
+    body["device_id"] = serde_json::json!(device_id);
+    body["id"] = serde_json::json!(id);
+    Ok(Some(key))
+}
+
+/// The device the server picks when none is given: the default, else the only
+/// online one, else the only paired one.
+async fn default_device_id() -> Result<Option<String>, CliError> {
+    let resp = api_request(reqwest::Method::GET, "/api/devices", None).await?;
+    let devices = resp.as_array().cloned().unwrap_or_default();
+    let id = |d: &serde_json::Value| d["id"].as_str().map(String::from);
+    if let Some(d) = devices.iter().find(|d| d["is_default"] == true) {
+        return Ok(id(d));
+    }
+    let online: Vec<_> = devices.iter().filter(|d| d["online"] == true).collect();
+    Ok(match (online.as_slice(), devices.as_slice()) {
+        ([d], _) => id(d),
+        ([], [d]) => id(d),
+        _ => None,
+    })
+}
+
+/// Format unix seconds as local `YYYY-MM-DD HH:MM`.
+pub(crate) fn format_time(ts: u64) -> String {
+    use chrono::{Local, TimeZone};
+    Local
+        .timestamp_opt(ts as i64, 0)
+        .single()
+        .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_else(|| ts.to_string())
+}
+
+/// Parse a relative duration like `90s`, `30m`, `6h` or `2d` into seconds.
+pub(crate) fn parse_duration(s: &str) -> Option<u64> {
+    let (num, unit) = s.split_at(s.len().saturating_sub(1));
+    let n: u64 = num.parse().ok()?;
+    match unit {
+        "s" => Some(n),
+        "m" => Some(n * 60),
+        "h" => Some(n * 3600),
+        "d" => Some(n * 86400),
+        _ => None,
+    }
+}
+
+/// Print an API result: raw JSON with `--output json`, otherwise via `text`.
+pub(crate) fn emit(resp: &serde_json::Value, text: impl FnOnce(&serde_json::Value)) {
+    if json_output() {
+        print_json(resp);
+    } else {
+        text(resp);
+    }
+}
+
+/// Report the answer to POST /api/command and turn it into an exit status.
+///
+/// `text` prints a successful single-device response; fan-out results get one
+/// line per device ending in `fanout_success`.
+pub(crate) fn finish_command(
+    resp: &serde_json::Value,
+    fanout_success: &str,
+    text: impl FnOnce(&serde_json::Value),
+) -> CliResult {
+    let outcome = command_outcome(resp);
+    if json_output() {
+        print_json(resp);
+        return outcome.map_err(CliError::reported);
+    }
+    if report_fanout(resp, fanout_success) || report_queued(resp) {
+        return outcome.map_err(CliError::reported);
+    }
+    if outcome.is_ok() {
+        text(resp);
+    }
+    outcome
+}
+
+/// Classify a command response, queued status or fan-out result.
+///
+/// A command queued for an offline device is `ErrorKind::Offline` (exit 5)
+/// even though the server accepted it: it has not run yet, and a script that
+/// wants to wait for it can read the id from the `"state": "queued"` JSON.
+pub(crate) fn command_outcome(resp: &serde_json::Value) -> CliResult {
+    if let Some(results) = resp.get("results").and_then(|r| r.as_array()) {
+        for r in results {
+            let device = r.get("device_id").and_then(|v| v.as_str()).unwrap_or("?");
+            if let Some(err) = r.get("error") {
+                let mut e = match serde_json::from_value(err.clone()) {
+                    Ok(info) => CliError::from_api(info),
+                    Err(_) => CliError::new(ErrorKind::Failed, err.to_string()),
+                };
+                e.message = format!("{device}: {}", e.message);
+                return Err(e);
+            }
+            if r.get("pending").is_some() {
+                return Err(CliError::new(
+                    ErrorKind::Offline,
+                    format!("{device}: offline, command queued"),
+                ));
+            }
+            if let Some(resp) = r.get("response") {
+                command_outcome(resp).map_err(|mut e| {
+                    e.message = format!("{device}: {}", e.message);
+                    e
+                })?;
+            }
+        }
+        return Ok(());
+    }
+
+    if resp.get("state").and_then(|s| s.as_str()) == Some("queued") {
+        let id = resp.get("id").and_then(|v| v.as_str()).unwrap_or("?");
+        return Err(CliError::new(
+            ErrorKind::Offline,
+            format!("Device is offline — command queued ({id})"),
+        ));
+    }
+    if resp.get("status").and_then(|s| s.as_str()) == Some("ok") {
+        return Ok(());
+    }
+    let code = resp.get("error_code").and_then(|c| c.as_str());
+    let msg = resp
+        .get("error")
+        .and_then(|e| e.as_str())
+        .unwrap_or("Unknown error");
+    let message = match code {
+        Some(code) => format!("{msg} ({code})"),
+        None => msg.to_string(),
+    };
+    Err(CliError::new(ErrorKind::from_device_code(code), message))
+}
+
+/// Print a notice and return true if the server queued the command for an offline device.
+fn report_queued(resp: &serde_json::Value) -> bool {
+    if resp.get("state").and_then(|s| s.as_str()) != Some("queued") {
+        return false;
+    }
+    let id = resp.get("id").and_then(|v| v.as_str()).unwrap_or("?");
+    println!("Device is offline — command queued ({id})");
+    println!("It will run when the app reconnects. Check: GET /api/commands/{id}");
+    true
+}
+
+/// Target a command body at a single device or at every device in a group.
+pub(crate) fn set_target(body: &mut serde_json::Value, device: Option<&str>, group: Option<&str>) {
+    if let Some(dev) = device {
+        body["device_id"] = serde_json::json!(dev);
+    }
+    if let Some(group) = group {
+        body["group"] = serde_json::json!(group);
+    }
+}
+
+/// Print one line per device and return true if the server answered a fan-out command.
+fn report_fanout(resp: &serde_json::Value, success: &str) -> bool {
+    let Some(results) = resp.get("results").and_then(|r| r.as_array()) else {
+        return false;
+    };
+    for r in results {
+        let device = r.get("device_id").and_then(|v| v.as_str()).unwrap_or("?");
+        if let Some(err) = r.get("error") {
+            let msg = err.get("message").and_then(|m| m.as_str()).unwrap_or("?");
+            println!("{device}: error: {msg}");
+        } else if r.get("pending").is_some() {
+            println!("{device}: queued");
+        } else if let Some(resp) = r.get("response") {
+            if resp.get("status").and_then(|s| s.as_str()) == Some("ok") {
+                println!("{device}: {success}");
+            } else {
+                let msg = resp
+                    .get("error")
+                    .and_then(|e| e.as_str())
+                    .unwrap_or("Unknown error");
+                println!("{device}: error: {msg}");
+            }
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn ok_response_succeeds() {
+        assert!(command_outcome(&json!({"id": "c1", "status": "ok"})).is_ok());
+    }
+
+    #[test]
+    fn queued_command_is_offline() {
+        let err = command_outcome(&json!({"id": "c1", "state": "queued"})).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Offline);
+        assert_eq!(err.exit_code(), 5);
+        assert!(err.message.contains("c1"));
+
+        let fanout = json!({"results": [
+            {"device_id": "a", "response": {"id": "c2", "status": "ok"}},
+            {"device_id": "b", "pending": {"id": "c3", "state": "queued"}},
+        ]});
+        let err = command_outcome(&fanout).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Offline);
+        assert!(err.message.starts_with("b: "));
+    }
+
+    #[test]
+    fn device_errors_map_to_their_exit_codes() {
+        let kind = |resp: serde_json::Value| command_outcome(&resp).unwrap_err().kind;
+        let declined = json!({
+            "id": "c1",
+            "status": "error",
+            "error": "Declined",
+            "error_code": "USER_DECLINED",
+        });
+        assert_eq!(kind(declined), ErrorKind::Declined);
+        let failed = json!({"id": "c1", "status": "error", "error": "No camera"});
+        assert_eq!(kind(failed), ErrorKind::Device);
+    }
 }
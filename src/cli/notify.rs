@@ -1,17 +1,21 @@
-use serde_json::json;
+use crate::protocol::command::{Command, NotifyParams, Priority};
 
-pub async fn send_notification(message: &str, priority: &str) {
-    let body = json!({
-        "command": "notify.send",
-        "params": {
-            "title": "omcli",
-            "body": message,
-            "priority": priority,
-        },
-    });
+pub async fn send_notification(
+    message: &str,
+    priority: Priority,
+    device: Option<&str>,
+    group: Option<&str>,
+) -> super::CliResult {
+    let mut body = Command::NotifySend(NotifyParams {
+        title: Some("omcli".to_string()),
+        body: message.to_string(),
+        priority,
+    })
+    .body();
+    super::set_target(&mut body, device, group);
 
-    match super::api_request(reqwest::Method::POST, "/api/command", Some(body)).await {
-        Ok(_) => println!("Notification sent"),
-        Err(e) => eprintln!("Error: {e}"),
-    }
+    let resp = super::post_command(body).await?;
+    super::finish_command(&resp, "notification sent", |_| {
+        println!("Notification sent")
+    })
 }
@@ -0,0 +1,9 @@
+/// Print the OpenAPI document of the REST API. It is built into this binary,
+/// so no server needs to run; `GET /api/openapi.json` serves the same document.
+pub async fn print_openapi() -> super::CliResult {
+    let json = crate::server::openapi()
+        .to_pretty_json()
+        .map_err(|e| format!("Failed to serialize the OpenAPI document: {e}"))?;
+    println!("{json}");
+    Ok(())
+}
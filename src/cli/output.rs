@@ -0,0 +1,157 @@
+use std::fmt;
+use std::sync::OnceLock;
+
+use crate::protocol::{ApiErrorInfo, ErrorCode};
+
+/// Output format selected with the global `--output` flag
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+static OUTPUT: OnceLock<OutputFormat> = OnceLock::new();
+
+pub fn set_output(format: OutputFormat) {
+    let _ = OUTPUT.set(format);
+}
+
+pub(crate) fn json_output() -> bool {
+    OUTPUT.get().copied().unwrap_or_default() == OutputFormat::Json
+}
+
+pub(crate) fn print_json(value: &serde_json::Value) {
+    println!("{}", serde_json::to_string_pretty(value).unwrap());
+}
+
+/// Failure category; the discriminant is the process exit code.
+///
+/// 2 is left to clap for usage errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Anything not covered below
+    Failed = 1,
+    /// The server could not be reached
+    Unreachable = 3,
+    /// The API key was rejected or its scope does not allow the request
+    Auth = 4,
+    /// No device online; the command was queued or could not be delivered
+    Offline = 5,
+    /// The device did not respond in time
+    Timeout = 6,
+    /// The user declined the request on the device (`USER_DECLINED`)
+    Declined = 7,
+    /// The device ran the command and reported an error
+    Device = 8,
+}
+
+impl ErrorKind {
+    pub fn code(self) -> &'static str {
+        match self {
+            ErrorKind::Failed => "ERROR",
+            ErrorKind::Unreachable => "SERVER_UNREACHABLE",
+            ErrorKind::Auth => "AUTH_FAILED",
+            ErrorKind::Offline => "DEVICE_OFFLINE",
+            ErrorKind::Timeout => "TIMEOUT",
+            ErrorKind::Declined => "USER_DECLINED",
+            ErrorKind::Device => "DEVICE_ERROR",
+        }
+    }
+
+    /// Map a server error code to an exit category.
+    pub fn from_api_code(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::Unauthorized | ErrorCode::Forbidden => ErrorKind::Auth,
+            ErrorCode::DeviceOffline | ErrorCode::NoPushToken | ErrorCode::PushNotConfigured => {
+                ErrorKind::Offline
+            }
+            ErrorCode::Timeout => ErrorKind::Timeout,
+            _ => ErrorKind::Failed,
+        }
+    }
+
+    /// Map a device-side `error_code` to an exit category.
+    pub fn from_device_code(code: Option<&str>) -> Self {
+        match code {
+            Some("TIMEOUT") => ErrorKind::Timeout,
+            Some("USER_DECLINED") => ErrorKind::Declined,
+            Some("DEVICE_OFFLINE") => ErrorKind::Offline,
+            _ => ErrorKind::Device,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CliError {
+    pub kind: ErrorKind,
+    /// Server error code when the server sent one
+    pub code: Option<ErrorCode>,
+    pub message: String,
+    pub details: Option<serde_json::Value>,
+    /// Already shown to the user, only the exit code is left to apply
+    pub reported: bool,
+}
+
+pub type CliResult = Result<(), CliError>;
+
+impl CliError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            code: None,
+            message: message.into(),
+            details: None,
+            reported: false,
+        }
+    }
+
+    /// Error parsed from the server's `{"error": {...}}` envelope.
+    pub fn from_api(info: ApiErrorInfo) -> Self {
+        Self {
+            kind: ErrorKind::from_api_code(info.code),
+            code: Some(info.code),
+            message: info.message,
+            details: info.details,
+            reported: false,
+        }
+    }
+
+    pub fn reported(mut self) -> Self {
+        self.reported = true;
+        self
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        self.kind as i32
+    }
+
+    /// Print the error in the selected output format unless it was already shown.
+    pub fn report(&self) {
+        if self.reported {
+            return;
+        }
+        if json_output() {
+            let code = self.code.map(|c| c.as_str()).unwrap_or(self.kind.code());
+            let mut error = serde_json::json!({ "code": code, "message": self.message });
+            if let Some(details) = &self.details {
+                error["details"] = details.clone();
+            }
+            print_json(&serde_json::json!({ "error": error }));
+        } else {
+            eprintln!("Error: {}", self.message);
+        }
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl From<String> for CliError {
+    fn from(message: String) -> Self {
+        CliError::new(ErrorKind::Failed, message)
+    }
+}
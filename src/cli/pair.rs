@@ -1,17 +1,104 @@
 use serde_json::json;
+use std::io::{BufRead, IsTerminal, Write};
 
-pub async fn pair(code: &str) {
-    let body = json!({ "code": code });
-
-    match super::api_request(reqwest::Method::POST, "/api/devices/pair", Some(body)).await {
-        Ok(resp) => {
-            let device_id = resp
-                .get("device_id")
-                .and_then(|v| v.as_str())
-                .unwrap_or("?");
-            let name = resp.get("name").and_then(|v| v.as_str()).unwrap_or("?");
-            println!("Paired: {} ({})", name, device_id);
+use super::e2e::Identity;
+use super::output::json_output;
+use crate::config;
+use crate::protocol::command::{Command, E2eKeyParams};
+use crate::protocol::ErrorCode;
+
+pub async fn pair(code: &str, confirm: bool) -> super::CliResult {
+    let identity = Identity::load_or_create()?;
+    let body = json!({
+        "code": code,
+        "confirm": confirm,
+        "e2e_commitment": identity.commitment(),
+    });
+
+    let mut resp = super::api_request(reqwest::Method::POST, "/api/devices/pair", Some(body))
+        .await
+        .map_err(|mut e| {
+            if e.code == Some(ErrorCode::PairingConfirmationRequired) {
+                e.message += &format!("\nRun `omcli pair {code} --confirm` to pair it anyway.");
+            }
+            e
+        })?;
+    let device_id = resp
+        .get("device_id")
+        .and_then(|v| v.as_str())
+        .unwrap_or("?")
+        .to_string();
+    let name = resp
+        .get("name")
+        .and_then(|v| v.as_str())
+        .unwrap_or("?")
+        .to_string();
+    if !json_output() {
+        println!("Paired: {} ({})", name, device_id);
+    }
+
+    // Forget any key from an earlier pairing; it is replaced below if the device has one
+    let mut peers = config::load_e2e_peers();
+    peers.remove(&device_id);
+    if let Some(device_key) = resp
+        .get("e2e_public_key")
+        .and_then(|v| v.as_str())
+        .map(String::from)
+    {
+        let e2e = exchange_keys(&identity, &device_id, &name, &device_key).await?;
+        if e2e["enabled"] == true {
+            peers.insert(device_id.clone(), device_key);
         }
-        Err(e) => eprintln!("Error: {e}"),
+        resp["e2e"] = e2e;
+    }
+    config::save_e2e_peers(&peers)?;
+
+    if json_output() {
+        super::output::print_json(&resp);
+    }
+    Ok(())
+}
+
+/// Reveal our key to the device and have the user compare the short
+/// authentication string shown on both ends.
+async fn exchange_keys(
+    identity: &Identity,
+    device_id: &str,
+    name: &str,
+    device_key: &str,
+) -> Result<serde_json::Value, super::CliError> {
+    let mut body = Command::E2eKey(E2eKeyParams {
+        public_key: identity.public_key(),
+    })
+    .body();
+    body["device_id"] = json!(device_id);
+    let resp = super::api_request(reqwest::Method::POST, "/api/command", Some(body)).await?;
+    if let Err(e) = super::command_outcome(&resp) {
+        if !json_output() {
+            eprintln!("Warning: end-to-end key exchange failed: {}", e.message);
+        }
+        return Ok(json!({ "enabled": false, "error": e.message }));
+    }
+
+    let sas = identity.sas(device_key)?;
+    // Scripts and agents can't compare codes; they trust the first exchange
+    if json_output() || !std::io::stdin().is_terminal() {
+        if !json_output() {
+            println!("End-to-end encryption enabled; {} should show the code {}", name, sas);
+        }
+        return Ok(json!({ "enabled": true, "sas": sas, "verified": false }));
+    }
+
+    println!("End-to-end encryption: check that {} shows the code {}", name, sas);
+    print!("Do the codes match? [y/N] ");
+    let _ = std::io::stdout().flush();
+    let mut answer = String::new();
+    let _ = std::io::stdin().lock().read_line(&mut answer);
+    if answer.trim().eq_ignore_ascii_case("y") {
+        println!("End-to-end encryption enabled for {}", name);
+        Ok(json!({ "enabled": true, "sas": sas, "verified": true }))
+    } else {
+        println!("Codes differ — end-to-end encryption not enabled. Tap \"Doesn't match\" on the device.");
+        Ok(json!({ "enabled": false, "sas": sas, "verified": false }))
     }
 }
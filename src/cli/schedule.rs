@@ -0,0 +1,190 @@
+use chrono::{Local, NaiveDateTime, NaiveTime, TimeZone};
+use serde_json::json;
+
+use crate::protocol::ErrorCode;
+
+pub async fn schedule_add(
+    at: Option<&str>,
+    cron: Option<&str>,
+    command: &str,
+    args: &[String],
+    device: Option<&str>,
+    custom: bool,
+) -> super::CliResult {
+    let params = parse_params(args)?;
+
+    let mut body = json!({
+        "command": command,
+        "params": params,
+    });
+    if custom {
+        body["custom"] = json!(true);
+    }
+    super::check_params(&body)?;
+    if let Some(at) = at {
+        body["at"] = json!(parse_at(at)?);
+    }
+    if let Some(cron) = cron {
+        body["cron"] = json!(cron);
+    }
+    if let Some(dev) = device {
+        body["device_id"] = json!(dev);
+    }
+
+    super::apply_default_device(&mut body)?;
+    // Every run replays the same sealed params, so they are bound to the schedule
+    super::seal_for_device(&mut body, true).await?;
+    let resp = super::api_request(reqwest::Method::POST, "/api/schedules", Some(body))
+        .await
+        .map_err(|mut e| {
+            if e.code == Some(ErrorCode::UnknownCommand) {
+                e.message += "\nAdd --custom before the command to schedule it anyway";
+            }
+            e
+        })?;
+    super::emit(&resp, |resp| {
+        let id = resp.get("id").and_then(|v| v.as_str()).unwrap_or("?");
+        println!("Scheduled {command} ({id})");
+        if let Some(next) = resp.get("next_run").and_then(|v| v.as_u64()) {
+            println!("Next run: {}", super::format_time(next));
+        }
+    });
+    Ok(())
+}
+
+pub async fn schedule_list() -> super::CliResult {
+    let resp = super::api_request(reqwest::Method::GET, "/api/schedules", None).await?;
+    super::emit(&resp, |resp| {
+        let Some(schedules) = resp.as_array() else {
+            println!("{}", serde_json::to_string_pretty(resp).unwrap());
+            return;
+        };
+        if schedules.is_empty() {
+            println!("No schedules");
+            return;
+        }
+        println!(
+            "{:<38} {:<16} {:<18} {:<20}",
+            "ID", "COMMAND", "WHEN", "NEXT RUN"
+        );
+        println!("{}", "-".repeat(92));
+        for s in schedules {
+            let id = s.get("id").and_then(|v| v.as_str()).unwrap_or("?");
+            let command = s.get("command").and_then(|v| v.as_str()).unwrap_or("?");
+            let when = match s.get("cron").and_then(|v| v.as_str()) {
+                Some(cron) => cron.to_string(),
+                None => "once".to_string(),
+            };
+            let next = s
+                .get("next_run")
+                .and_then(|v| v.as_u64())
+                .map(super::format_time)
+                .unwrap_or_else(|| "-".into());
+            println!("{:<38} {:<16} {:<18} {:<20}", id, command, when, next);
+        }
+    });
+    Ok(())
+}
+
+pub async fn schedule_rm(id: &str) -> super::CliResult {
+    let path = format!("/api/schedules/{id}");
+    super::api_request(reqwest::Method::DELETE, &path, None).await?;
+    super::emit(&json!({ "id": id, "removed": true }), |_| {
+        println!("Schedule removed")
+    });
+    Ok(())
+}
+
+/// Turn `--key value` pairs into a params object. Values that parse as JSON keep their type.
+fn parse_params(args: &[String]) -> Result<serde_json::Value, String> {
+    let mut params = serde_json::Map::new();
+    let mut iter = args.iter().peekable();
+    while let Some(arg) = iter.next() {
+        let key = arg
+            .strip_prefix("--")
+            .ok_or_else(|| format!("Unexpected argument '{arg}', expected --key value"))?;
+        let value = match iter.next_if(|v| !v.starts_with("--")) {
+            Some(v) => serde_json::from_str(v).unwrap_or_else(|_| json!(v)),
+            None => json!(true),
+        };
+        params.insert(key.to_string(), value);
+    }
+    Ok(serde_json::Value::Object(params))
+}
+
+/// Parse `HH:MM` (next occurrence) or `YYYY-MM-DD HH:MM` in local time into unix seconds.
+fn parse_at(at: &str) -> Result<u64, String> {
+    let now = Local::now();
+    if let Ok(time) = NaiveTime::parse_from_str(at, "%H:%M") {
+        let mut date = now.date_naive();
+        if time <= now.time() {
+            date = date.succ_opt().ok_or("Date out of range")?;
+        }
+        return to_local_timestamp(date.and_time(time));
+    }
+    if let Ok(dt) = NaiveDateTime::parse_from_str(at, "%Y-%m-%d %H:%M") {
+        return to_local_timestamp(dt);
+    }
+    Err(format!(
+        "Invalid time '{at}': use HH:MM or \"YYYY-MM-DD HH:MM\""
+    ))
+}
+
+fn to_local_timestamp(dt: NaiveDateTime) -> Result<u64, String> {
+    Local
+        .from_local_datetime(&dt)
+        .earliest()
+        .map(|t| t.timestamp() as u64)
+        .ok_or_else(|| format!("{dt} does not exist in the local time zone"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn params_keep_json_types() {
+        let params = parse_params(&args(&["--sound", "loud", "--count", "3", "--force"])).unwrap();
+        assert_eq!(params, json!({"sound": "loud", "count": 3, "force": true}));
+        assert_eq!(parse_params(&[]).unwrap(), json!({}));
+    }
+
+    #[test]
+    fn params_need_flags() {
+        assert!(parse_params(&args(&["loud"])).is_err());
+        assert!(parse_params(&args(&["--sound", "loud", "extra"])).is_err());
+    }
+
+    #[test]
+    fn at_takes_the_next_occurrence_of_a_time() {
+        let now = Local::now().timestamp() as u64;
+        let at = parse_at("07:00").unwrap();
+        assert!(
+            at > now && at <= now + 24 * 3600,
+            "{at} is not within a day of {now}"
+        );
+        let local = Local.timestamp_opt(at as i64, 0).unwrap();
+        assert_eq!(local.format("%H:%M").to_string(), "07:00");
+    }
+
+    #[test]
+    fn at_takes_a_date_and_time() {
+        let expected = Local
+            .with_ymd_and_hms(2030, 1, 2, 7, 30, 0)
+            .earliest()
+            .unwrap()
+            .timestamp() as u64;
+        assert_eq!(parse_at("2030-01-02 07:30").unwrap(), expected);
+    }
+
+    #[test]
+    fn at_rejects_other_formats() {
+        for at in ["7am", "25:00", "2030-01-02", "2030-01-02T07:30", ""] {
+            assert!(parse_at(at).is_err(), "{at}");
+        }
+    }
+}
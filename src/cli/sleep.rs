@@ -1,43 +1,21 @@
-use serde_json::json;
+use crate::protocol::command::{Command, NoParams};
 
-pub async fn sleep_start(device: Option<&str>) {
-    let mut body = json!({
-        "command": "sleep.start",
-        "params": {},
-    });
-    if let Some(dev) = device {
-        body["device_id"] = json!(dev);
-    }
+pub async fn sleep_start(device: Option<&str>, group: Option<&str>) -> super::CliResult {
+    let mut body = Command::SleepStart(NoParams {}).body();
+    super::set_target(&mut body, device, group);
 
-    match super::api_request(reqwest::Method::POST, "/api/command", Some(body)).await {
-        Ok(resp) => {
-            if resp.get("status").and_then(|s| s.as_str()) == Some("ok") {
-                println!("Sleep mode activated — screen will stay on");
-            } else {
-                println!("{}", serde_json::to_string_pretty(&resp).unwrap());
-            }
-        }
-        Err(e) => eprintln!("Error: {e}"),
-    }
+    let resp = super::post_command(body).await?;
+    super::finish_command(&resp, "sleep mode activated", |_| {
+        println!("Sleep mode activated — screen will stay on")
+    })
 }
 
-pub async fn sleep_stop(device: Option<&str>) {
-    let mut body = json!({
-        "command": "sleep.stop",
-        "params": {},
-    });
-    if let Some(dev) = device {
-        body["device_id"] = json!(dev);
-    }
+pub async fn sleep_stop(device: Option<&str>, group: Option<&str>) -> super::CliResult {
+    let mut body = Command::SleepStop(NoParams {}).body();
+    super::set_target(&mut body, device, group);
 
-    match super::api_request(reqwest::Method::POST, "/api/command", Some(body)).await {
-        Ok(resp) => {
-            if resp.get("status").and_then(|s| s.as_str()) == Some("ok") {
-                println!("Sleep mode deactivated");
-            } else {
-                println!("{}", serde_json::to_string_pretty(&resp).unwrap());
-            }
-        }
-        Err(e) => eprintln!("Error: {e}"),
-    }
+    let resp = super::post_command(body).await?;
+    super::finish_command(&resp, "sleep mode deactivated", |_| {
+        println!("Sleep mode deactivated")
+    })
 }
@@ -0,0 +1,659 @@
+pub mod schema;
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use crate::protocol::{ApiKey, Device, HistoryEntry, QueuedCommand, Schedule};
+use schema::Source;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Config {
+    pub server: ServerConfig,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub apns: Option<ApnsConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relay: Option<RelayConfig>,
+}
+
+/// Fields may be set one `omcli config set` at a time, so a missing one is
+/// empty here and reported when the APNs client is built.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ApnsConfig {
+    #[serde(default)]
+    pub key_path: String,
+    #[serde(default)]
+    pub key_id: String,
+    #[serde(default)]
+    pub team_id: String,
+    #[serde(default)]
+    pub bundle_id: String,
+    #[serde(default)]
+    pub sandbox: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ServerConfig {
+    /// Where the CLI on this machine sends requests. Older versions wrote it on
+    /// every `serve`; it is now only read, for setups that pointed it elsewhere.
+    /// Use a client context instead.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Admin key
+    pub api_key: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    #[serde(default = "default_bind")]
+    pub bind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relay_url: Option<String>,
+    /// How long a pairing code shown on the device stays valid
+    #[serde(default = "default_pairing_code_ttl")]
+    pub pairing_code_ttl_secs: u64,
+    /// What to do when a device authenticates while already connected
+    #[serde(default)]
+    pub reconnect_policy: ReconnectPolicy,
+    /// How often connected devices are pinged; one that answers nothing for
+    /// two intervals is disconnected. 0 turns the heartbeat off.
+    #[serde(default = "default_ping_interval")]
+    pub ping_interval_secs: u64,
+    /// Serve HTTPS/WSS instead of plain HTTP/WS
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsConfig>,
+}
+
+/// `[server.tls]` / `[relay.tls]`
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct TlsConfig {
+    /// PEM certificate chain; defaults to `<server|relay>-cert.pem` in the data dir
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cert_path: Option<String>,
+    /// PEM private key; defaults to `<server|relay>-key.pem` in the data dir
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_path: Option<String>,
+    /// Generate a self-signed certificate when the files do not exist
+    #[serde(default)]
+    pub self_signed: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReconnectPolicy {
+    /// Close the existing connection and keep the new one (phones switching networks)
+    #[default]
+    KickOld,
+    /// Keep the existing connection and refuse the new one
+    RejectNew,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RelayConfig {
+    #[serde(default = "default_relay_port")]
+    pub port: u16,
+    #[serde(default = "default_bind")]
+    pub bind: String,
+    #[serde(default)]
+    pub apns_key_path: String,
+    #[serde(default)]
+    pub apns_key_id: String,
+    #[serde(default)]
+    pub apns_team_id: String,
+    #[serde(default)]
+    pub apns_bundle_id: String,
+    #[serde(default)]
+    pub apns_sandbox: bool,
+    #[serde(default = "default_max_requests")]
+    pub max_requests_per_device_per_hour: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsConfig>,
+}
+
+impl RelayConfig {
+    pub fn to_apns_config(&self) -> ApnsConfig {
+        ApnsConfig {
+            key_path: self.apns_key_path.clone(),
+            key_id: self.apns_key_id.clone(),
+            team_id: self.apns_team_id.clone(),
+            bundle_id: self.apns_bundle_id.clone(),
+            sandbox: self.apns_sandbox,
+        }
+    }
+}
+
+/// Servers this CLI talks to (client.toml), kept apart from the server's own config.toml.
+///
+/// When a context is selected the CLI uses it instead of the server in config.toml.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ClientConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub current: Option<String>,
+    #[serde(default)]
+    pub contexts: BTreeMap<String, ClientContext>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ClientContext {
+    pub url: String,
+    pub api_key: String,
+    /// Device to target when a command has no `--device` or `--group`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_device: Option<String>,
+    /// Server certificate to trust (PEM), for self-signed certificates
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_cert: Option<String>,
+}
+
+impl ClientConfig {
+    pub fn load() -> Result<Self, String> {
+        let path = Config::client_path();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+        toml::from_str(&content).map_err(|e| format!("Failed to parse {}: {e}", path.display()))
+    }
+
+    /// Saved readable only by the user, since contexts hold API keys
+    pub fn save(&self) -> Result<(), String> {
+        std::fs::create_dir_all(Config::data_dir())
+            .map_err(|e| format!("Failed to create config dir: {e}"))?;
+        let content =
+            toml::to_string_pretty(self).map_err(|e| format!("Failed to serialize: {e}"))?;
+        write_private(&Config::client_path(), content.as_bytes())
+    }
+}
+
+fn default_port() -> u16 {
+    7333
+}
+
+fn default_bind() -> String {
+    "127.0.0.1".to_string()
+}
+
+fn default_pairing_code_ttl() -> u64 {
+    300
+}
+
+fn default_ping_interval() -> u64 {
+    15
+}
+
+fn default_relay_port() -> u16 {
+    7334
+}
+
+fn default_max_requests() -> u32 {
+    60
+}
+
+impl Config {
+    pub fn data_dir() -> PathBuf {
+        if let Ok(dir) = std::env::var("OMCLI_DATA_DIR") {
+            return PathBuf::from(dir);
+        }
+        dirs::home_dir()
+            .expect("cannot determine home directory")
+            .join(".omcli")
+    }
+
+    pub fn config_path() -> PathBuf {
+        Self::data_dir().join("config.toml")
+    }
+
+    pub fn devices_path() -> PathBuf {
+        Self::data_dir().join("devices.json")
+    }
+
+    pub fn queue_path() -> PathBuf {
+        Self::data_dir().join("queue.json")
+    }
+
+    pub fn schedules_path() -> PathBuf {
+        Self::data_dir().join("schedules.json")
+    }
+
+    pub fn keys_path() -> PathBuf {
+        Self::data_dir().join("keys.json")
+    }
+
+    pub fn history_path() -> PathBuf {
+        Self::data_dir().join("history.jsonl")
+    }
+
+    /// Client profiles written by `omcli connect`
+    pub fn client_path() -> PathBuf {
+        Self::data_dir().join("client.toml")
+    }
+
+    /// Certificates of servers enrolled with `--fingerprint`
+    pub fn certs_dir() -> PathBuf {
+        Self::data_dir().join("certs")
+    }
+
+    /// The CLI's X25519 private key for end-to-end encryption
+    pub fn e2e_key_path() -> PathBuf {
+        Self::data_dir().join("e2e-key.pem")
+    }
+
+    /// Device public keys the CLI verified at pairing, by device id
+    pub fn e2e_peers_path() -> PathBuf {
+        Self::data_dir().join("e2e-peers.json")
+    }
+
+    /// Load config.toml with `OMCLI_*` overrides and `*_file` secrets applied.
+    pub fn load() -> Result<Self, String> {
+        Self::load_layered().map(|layered| layered.config)
+    }
+
+    /// Like `load`, also saying which keys were set by the file or the environment.
+    pub fn load_layered() -> Result<Layered, String> {
+        let path = Self::config_path();
+        if !path.exists() {
+            return Err(format!(
+                "Config not found at {}. Run 'omcli serve' first, or 'omcli connect <url> <code>' \
+                 to use a remote server.",
+                path.display()
+            ));
+        }
+        let content =
+            std::fs::read_to_string(&path).map_err(|e| format!("Failed to read config: {e}"))?;
+        Self::parse(&content)
+    }
+
+    fn parse(content: &str) -> Result<Layered, String> {
+        let table: toml::Table =
+            toml::from_str(content).map_err(|e| format!("Failed to parse config: {e}"))?;
+        let (table, sources) = schema::layer(table)?;
+        let config = Self::deserialize(toml::Value::Table(table.clone()))
+            .map_err(|e| format!("Invalid config: {e}"))?;
+        Ok(Layered {
+            config,
+            table,
+            sources,
+        })
+    }
+
+    /// Load the config for `omcli serve`, creating it with a generated API key
+    /// on first run. `port` and `bind` from the command line win over the file
+    /// and the environment, and are written back so the local CLI finds the server.
+    pub fn load_or_create(port: Option<u16>, bind: Option<&str>) -> Result<Self, String> {
+        if !Self::config_path().exists() {
+            // The key may come from OMCLI_SERVER_API_KEY or its _FILE variant instead
+            let (env, _) = schema::layer(toml::Table::new())?;
+            let generate = schema::get(&env, "server.api_key").is_none();
+            edit_config(|doc| {
+                if generate {
+                    let key = uuid::Uuid::new_v4().to_string();
+                    schema::set_item(doc, "server.api_key", key.into())?;
+                }
+                let port = port.unwrap_or_else(default_port);
+                schema::set_item(doc, "server.port", i64::from(port).into())?;
+                schema::set_item(doc, "server.bind", bind.unwrap_or(&default_bind()).into())
+            })?;
+        }
+
+        let current = Self::load()?;
+        edit_config(|doc| {
+            // A url older versions wrote for this server is dropped; the CLI
+            // derives it from bind and port. One pointing elsewhere is kept.
+            let written = [
+                format!("http://{}:{}", current.server.bind, current.server.port),
+                current.server.local_url(),
+            ];
+            if current.server.url.as_ref().is_some_and(|u| written.contains(u)) {
+                schema::remove_item(doc, "server.url");
+            }
+            if let Some(port) = port {
+                schema::set_item(doc, "server.port", i64::from(port).into())?;
+            }
+            if let Some(bind) = bind {
+                schema::set_item(doc, "server.bind", bind.into())?;
+            }
+            Ok(())
+        })?;
+
+        let mut config = Self::load()?;
+        if let Some(port) = port {
+            config.server.port = port;
+        }
+        if let Some(bind) = bind {
+            config.server.bind = bind.to_string();
+        }
+        Ok(config)
+    }
+}
+
+/// Config with the raw layered values behind it
+pub struct Layered {
+    pub config: Config,
+    /// config.toml with the environment and secret files applied, before defaults
+    pub table: toml::Table,
+    /// Keys set by the file or the environment; the rest are defaults or unset
+    pub sources: HashMap<&'static str, Source>,
+}
+
+/// Change config.toml in place, keeping comments and layout. Nothing is
+/// written if the result would not load.
+pub fn edit_config(
+    f: impl FnOnce(&mut toml_edit::DocumentMut) -> Result<(), String>,
+) -> Result<(), String> {
+    let path = Config::config_path();
+    let content = std::fs::read_to_string(&path).unwrap_or_default();
+    let mut doc: toml_edit::DocumentMut = content
+        .parse()
+        .map_err(|e| format!("Failed to parse config: {e}"))?;
+    f(&mut doc)?;
+
+    let updated = doc.to_string();
+    if updated == content && path.exists() {
+        return Ok(());
+    }
+    Config::parse(&updated)?;
+    std::fs::create_dir_all(Config::data_dir())
+        .map_err(|e| format!("Failed to create config dir: {e}"))?;
+    write_private(&path, updated.as_bytes())
+}
+
+impl ServerConfig {
+    /// URL the CLI on this machine uses to reach this server.
+    pub fn local_url(&self) -> String {
+        local_url(&self.bind, self.port, self.tls.is_some())
+    }
+}
+
+/// URL the CLI on this machine uses to reach a server bound to `bind`.
+fn local_url(bind: &str, port: u16, tls: bool) -> String {
+    let scheme = if tls { "https" } else { "http" };
+    // A wildcard bind is reachable on loopback, which the self-signed certificate covers
+    let host = match bind {
+        "0.0.0.0" => "127.0.0.1",
+        "::" => "[::1]",
+        _ => bind,
+    };
+    format!("{scheme}://{host}:{port}")
+}
+
+// --- Device persistence ---
+
+pub fn load_devices() -> Vec<Device> {
+    let path = Config::devices_path();
+    if !path.exists() {
+        return Vec::new();
+    }
+    let content = match std::fs::read_to_string(&path) {
+        Ok(c) => c,
+        Err(_) => return Vec::new(),
+    };
+    serde_json::from_str(&content).unwrap_or_default()
+}
+
+pub fn save_devices(devices: &[Device]) -> Result<(), String> {
+    let dir = Config::data_dir();
+    std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create data dir: {e}"))?;
+    let content =
+        serde_json::to_string_pretty(devices).map_err(|e| format!("Failed to serialize: {e}"))?;
+    std::fs::write(Config::devices_path(), content)
+        .map_err(|e| format!("Failed to write devices: {e}"))?;
+    Ok(())
+}
+
+// --- Offline command queue persistence ---
+
+/// Commands waiting for offline devices. A queue.json that does not parse is
+/// moved aside rather than overwritten by the next save; the error says where.
+pub fn load_queue() -> Result<HashMap<String, Vec<QueuedCommand>>, String> {
+    load_or_set_aside(&Config::queue_path())
+}
+
+pub fn save_queue(queue: &HashMap<String, Vec<QueuedCommand>>) -> Result<(), String> {
+    let dir = Config::data_dir();
+    std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create data dir: {e}"))?;
+    let content =
+        serde_json::to_string_pretty(queue).map_err(|e| format!("Failed to serialize: {e}"))?;
+    write_atomic(&Config::queue_path(), content.as_bytes())
+        .map_err(|e| format!("Failed to write queue: {e}"))
+}
+
+/// Replace `path` by writing a temporary file next to it and renaming it into
+/// place, so a crash mid-write leaves the old contents intact.
+pub(crate) fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), String> {
+    use std::io::Write;
+
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    std::fs::File::create(&tmp)
+        .and_then(|mut f| {
+            f.write_all(contents)?;
+            f.sync_all()
+        })
+        .and_then(|()| std::fs::rename(&tmp, path))
+        .map_err(|e| {
+            let _ = std::fs::remove_file(&tmp);
+            format!("{}: {e}", path.display())
+        })
+}
+
+/// Read a JSON file, `T::default()` if there is none. One that does not parse
+/// is renamed to `<name>.corrupt-<unix secs>` so it can be recovered by hand.
+fn load_or_set_aside<T: serde::de::DeserializeOwned + Default>(path: &Path) -> Result<T, String> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(T::default()),
+        Err(e) => return Err(format!("Failed to read {}: {e}", path.display())),
+    };
+    let error = match serde_json::from_str(&content) {
+        Ok(value) => return Ok(value),
+        Err(e) => e,
+    };
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(format!(".corrupt-{secs}"));
+    let backup = PathBuf::from(backup);
+    match std::fs::rename(path, &backup) {
+        Ok(()) => Err(format!(
+            "{} is not valid ({error}); moved it to {}",
+            path.display(),
+            backup.display()
+        )),
+        Err(e) => Err(format!(
+            "{} is not valid ({error}) and could not be moved aside: {e}",
+            path.display()
+        )),
+    }
+}
+
+// --- Schedule persistence ---
+
+pub fn load_schedules() -> Vec<Schedule> {
+    let path = Config::schedules_path();
+    if !path.exists() {
+        return Vec::new();
+    }
+    let content = match std::fs::read_to_string(&path) {
+        Ok(c) => c,
+        Err(_) => return Vec::new(),
+    };
+    serde_json::from_str(&content).unwrap_or_default()
+}
+
+pub fn save_schedules(schedules: &[Schedule]) -> Result<(), String> {
+    let dir = Config::data_dir();
+    std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create data dir: {e}"))?;
+    let content =
+        serde_json::to_string_pretty(schedules).map_err(|e| format!("Failed to serialize: {e}"))?;
+    write_atomic(&Config::schedules_path(), content.as_bytes())
+        .map_err(|e| format!("Failed to write schedules: {e}"))
+}
+
+// --- Scoped API key persistence ---
+
+pub fn load_api_keys() -> Vec<ApiKey> {
+    let path = Config::keys_path();
+    if !path.exists() {
+        return Vec::new();
+    }
+    let content = match std::fs::read_to_string(&path) {
+        Ok(c) => c,
+        Err(_) => return Vec::new(),
+    };
+    serde_json::from_str(&content).unwrap_or_default()
+}
+
+pub fn save_api_keys(keys: &[ApiKey]) -> Result<(), String> {
+    let dir = Config::data_dir();
+    std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create data dir: {e}"))?;
+    let content =
+        serde_json::to_string_pretty(keys).map_err(|e| format!("Failed to serialize: {e}"))?;
+    std::fs::write(Config::keys_path(), content)
+        .map_err(|e| format!("Failed to write keys: {e}"))?;
+    Ok(())
+}
+
+// --- End-to-end peer keys (CLI side) ---
+
+pub fn load_e2e_peers() -> HashMap<String, String> {
+    let path = Config::e2e_peers_path();
+    if !path.exists() {
+        return HashMap::new();
+    }
+    let content = match std::fs::read_to_string(&path) {
+        Ok(c) => c,
+        Err(_) => return HashMap::new(),
+    };
+    serde_json::from_str(&content).unwrap_or_default()
+}
+
+pub fn save_e2e_peers(peers: &HashMap<String, String>) -> Result<(), String> {
+    let dir = Config::data_dir();
+    std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create data dir: {e}"))?;
+    let content =
+        serde_json::to_string_pretty(peers).map_err(|e| format!("Failed to serialize: {e}"))?;
+    std::fs::write(Config::e2e_peers_path(), content)
+        .map_err(|e| format!("Failed to write e2e peers: {e}"))?;
+    Ok(())
+}
+
+/// Write a file readable only by the current user.
+pub(crate) fn write_private(path: &Path, contents: &[u8]) -> Result<(), String> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+        .open(path)
+        .and_then(|mut f| f.write_all(contents))
+        .map_err(|e| format!("Failed to write {}: {e}", path.display()))
+}
+
+// --- Command history (one JSON object per line, append-only) ---
+
+/// Once history.jsonl reaches this size it is renamed to history.jsonl.1,
+/// replacing the one before, so the log stays under twice this on disk.
+const HISTORY_ROTATE_BYTES: u64 = 4 * 1024 * 1024;
+
+/// Where history.jsonl goes when it is rotated
+fn rotated_history_path() -> PathBuf {
+    let mut path = Config::history_path().into_os_string();
+    path.push(".1");
+    PathBuf::from(path)
+}
+
+pub fn append_history(entry: &HistoryEntry) -> Result<(), String> {
+    use std::io::Write;
+
+    let dir = Config::data_dir();
+    std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create data dir: {e}"))?;
+    let line = serde_json::to_string(entry).map_err(|e| format!("Failed to serialize: {e}"))?;
+    let path = Config::history_path();
+    if std::fs::metadata(&path).is_ok_and(|m| m.len() >= HISTORY_ROTATE_BYTES) {
+        std::fs::rename(&path, rotated_history_path())
+            .map_err(|e| format!("Failed to rotate history: {e}"))?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(|e| format!("Failed to open history: {e}"))?;
+    writeln!(file, "{line}").map_err(|e| format!("Failed to write history: {e}"))
+}
+
+/// The newest `limit` entries of history.jsonl and the file rotated before it,
+/// oldest first.
+pub fn load_history(limit: usize) -> Vec<HistoryEntry> {
+    let mut entries: Vec<HistoryEntry> = [rotated_history_path(), Config::history_path()]
+        .iter()
+        .filter_map(|path| std::fs::read_to_string(path).ok())
+        .flat_map(|content| {
+            content
+                .lines()
+                .filter_map(|line| serde_json::from_str(line).ok())
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    let excess = entries.len().saturating_sub(limit);
+    entries.drain(..excess);
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory under the system temp dir
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("omcli-test-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn write_atomic_replaces_the_file() {
+        let dir = temp_dir("atomic");
+        let path = dir.join("queue.json");
+        write_atomic(&path, b"old").unwrap();
+        write_atomic(&path, b"new").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        assert!(!dir.join("queue.json.tmp").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn missing_file_loads_as_default() {
+        let dir = temp_dir("missing");
+        let loaded: HashMap<String, Vec<QueuedCommand>> =
+            load_or_set_aside(&dir.join("queue.json")).unwrap();
+        assert!(loaded.is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn corrupt_file_is_moved_aside() {
+        let dir = temp_dir("corrupt");
+        let path = dir.join("queue.json");
+        std::fs::write(&path, "{\"device\": [").unwrap();
+        let err = load_or_set_aside::<HashMap<String, Vec<QueuedCommand>>>(&path).unwrap_err();
+        assert!(err.contains("moved it to"), "{err}");
+        assert!(!path.exists());
+
+        let backups: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert_eq!(backups.len(), 1);
+        assert!(backups[0].starts_with("queue.json.corrupt-"));
+        let kept = std::fs::read_to_string(dir.join(&backups[0])).unwrap();
+        assert_eq!(kept, "{\"device\": [");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,384 @@
+use std::collections::HashMap;
+
+/// Type of a config.toml value, used to check `omcli config set` and `OMCLI_*` values
+#[derive(Debug, Clone, Copy)]
+pub enum Kind {
+    Str,
+    Port,
+    Int,
+    Bool,
+    Choice(&'static [&'static str]),
+}
+
+/// One key in config.toml
+#[derive(Debug)]
+pub struct Field {
+    /// Dotted path, e.g. `server.tls.self_signed`
+    pub key: &'static str,
+    pub kind: Kind,
+    pub about: &'static str,
+    /// Can also be read from the file named by `<key>_file`
+    pub secret: bool,
+}
+
+const fn field(key: &'static str, kind: Kind, about: &'static str) -> Field {
+    Field {
+        key,
+        kind,
+        about,
+        secret: false,
+    }
+}
+
+const fn secret(key: &'static str, kind: Kind, about: &'static str) -> Field {
+    Field {
+        key,
+        kind,
+        about,
+        secret: true,
+    }
+}
+
+/// Every key `omcli config` can read and write
+pub const FIELDS: &[Field] = &[
+    secret("server.api_key", Kind::Str, "Admin API key"),
+    field("server.api_key_file", Kind::Str, "File holding the admin API key (e.g. a Docker secret)"),
+    field("server.port", Kind::Port, "Port `omcli serve` listens on"),
+    field("server.bind", Kind::Str, "Address `omcli serve` binds to"),
+    field("server.relay_url", Kind::Str, "Push relay used when APNs is not configured"),
+    field("server.pairing_code_ttl_secs", Kind::Int, "How long a pairing code stays valid"),
+    field(
+        "server.reconnect_policy",
+        Kind::Choice(&["kick_old", "reject_new"]),
+        "What to do when a device connects twice",
+    ),
+    field(
+        "server.ping_interval_secs",
+        Kind::Int,
+        "Seconds between WebSocket pings to devices (0 disables the heartbeat)",
+    ),
+    field("server.tls.cert_path", Kind::Str, "PEM certificate chain for HTTPS/WSS"),
+    field("server.tls.key_path", Kind::Str, "PEM private key for HTTPS/WSS"),
+    field("server.tls.self_signed", Kind::Bool, "Generate a self-signed certificate if missing"),
+    field("apns.key_path", Kind::Str, "APNs .p8 key file"),
+    field("apns.key_id", Kind::Str, "APNs key ID"),
+    field("apns.team_id", Kind::Str, "Apple team ID"),
+    field("apns.bundle_id", Kind::Str, "App bundle ID"),
+    field("apns.sandbox", Kind::Bool, "Use the APNs sandbox (development builds)"),
+    field("relay.port", Kind::Port, "Port `omcli relay` listens on"),
+    field("relay.bind", Kind::Str, "Address `omcli relay` binds to"),
+    field("relay.apns_key_path", Kind::Str, "Relay's APNs .p8 key file"),
+    field("relay.apns_key_id", Kind::Str, "Relay's APNs key ID"),
+    field("relay.apns_team_id", Kind::Str, "Relay's Apple team ID"),
+    field("relay.apns_bundle_id", Kind::Str, "Relay's app bundle ID"),
+    field("relay.apns_sandbox", Kind::Bool, "Relay uses the APNs sandbox"),
+    field(
+        "relay.max_requests_per_device_per_hour",
+        Kind::Int,
+        "Pushes allowed per device token per hour",
+    ),
+    field("relay.tls.cert_path", Kind::Str, "PEM certificate chain for the relay"),
+    field("relay.tls.key_path", Kind::Str, "PEM private key for the relay"),
+    field("relay.tls.self_signed", Kind::Bool, "Generate a self-signed relay certificate if missing"),
+];
+
+/// Short names accepted by `omcli config set` before keys were namespaced
+const ALIASES: &[(&str, &str)] = &[
+    ("api_key", "server.api_key"),
+    ("token", "server.api_key"),
+    ("port", "server.port"),
+    ("bind", "server.bind"),
+    ("relay_url", "server.relay_url"),
+];
+
+pub fn find(key: &str) -> Option<&'static Field> {
+    let key = ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == key)
+        .map_or(key, |(_, full)| full);
+    FIELDS.iter().find(|f| f.key == key)
+}
+
+impl Field {
+    /// `server.tls.self_signed` → `OMCLI_SERVER_TLS_SELF_SIGNED`
+    pub fn env_var(&self) -> String {
+        format!("OMCLI_{}", self.key.replace('.', "_").to_uppercase())
+    }
+
+    pub fn type_name(&self) -> String {
+        match self.kind {
+            Kind::Str => "string".to_string(),
+            Kind::Port => "port".to_string(),
+            Kind::Int => "integer".to_string(),
+            Kind::Bool => "bool".to_string(),
+            Kind::Choice(choices) => choices.join("|"),
+        }
+    }
+
+    /// Parse a value given on the command line or in the environment.
+    pub fn parse(&self, raw: &str) -> Result<toml::Value, String> {
+        let invalid = || format!("Invalid value '{}' for {} (expected {})", raw, self.key, self.type_name());
+        match self.kind {
+            Kind::Str => Ok(toml::Value::String(raw.to_string())),
+            Kind::Port => raw
+                .parse::<u16>()
+                .map(|p| toml::Value::Integer(p.into()))
+                .map_err(|_| invalid()),
+            Kind::Int => raw
+                .parse::<u32>()
+                .map(|n| toml::Value::Integer(n.into()))
+                .map_err(|_| invalid()),
+            Kind::Bool => raw
+                .parse::<bool>()
+                .map(toml::Value::Boolean)
+                .map_err(|_| invalid()),
+            Kind::Choice(choices) if choices.contains(&raw) => {
+                Ok(toml::Value::String(raw.to_string()))
+            }
+            Kind::Choice(_) => Err(invalid()),
+        }
+    }
+}
+
+/// Where the effective value of a key came from, lowest precedence first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Source {
+    File,
+    Env,
+}
+
+impl Source {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Source::File => "file",
+            Source::Env => "env",
+        }
+    }
+}
+
+pub fn get<'a>(table: &'a toml::Table, key: &str) -> Option<&'a toml::Value> {
+    let (parents, leaf) = split(key);
+    let mut table = table;
+    for part in parents {
+        table = table.get(part)?.as_table()?;
+    }
+    table.get(leaf)
+}
+
+fn set(table: &mut toml::Table, key: &str, value: toml::Value) {
+    let (parents, leaf) = split(key);
+    let mut table = table;
+    for part in parents {
+        let entry = table
+            .entry(part.to_string())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+        if !entry.is_table() {
+            *entry = toml::Value::Table(toml::Table::new());
+        }
+        table = entry.as_table_mut().unwrap();
+    }
+    table.insert(leaf.to_string(), value);
+}
+
+pub(crate) fn split(key: &str) -> (Vec<&str>, &str) {
+    let mut parts: Vec<_> = key.split('.').collect();
+    let leaf = parts.pop().unwrap_or_default();
+    (parts, leaf)
+}
+
+/// Apply `OMCLI_*` overrides and `<key>_file` secrets to the parsed file.
+///
+/// A `_file` key only fills in its secret when it comes from the same layer or
+/// a later one, so `OMCLI_SERVER_API_KEY` still beats `api_key_file` in the file.
+pub fn layer(table: toml::Table) -> Result<(toml::Table, HashMap<&'static str, Source>), String> {
+    layer_with(table, |var| std::env::var(var).ok())
+}
+
+/// `layer` with the environment read through `env`
+fn layer_with(
+    mut table: toml::Table,
+    env: impl Fn(&str) -> Option<String>,
+) -> Result<(toml::Table, HashMap<&'static str, Source>), String> {
+    let mut sources = HashMap::new();
+    for f in FIELDS {
+        if get(&table, f.key).is_some() {
+            sources.insert(f.key, Source::File);
+        }
+    }
+    for f in FIELDS {
+        let var = f.env_var();
+        if let Some(raw) = env(&var) {
+            let value = f.parse(&raw).map_err(|e| format!("{var}: {e}"))?;
+            set(&mut table, f.key, value);
+            sources.insert(f.key, Source::Env);
+        }
+    }
+
+    for f in FIELDS.iter().filter(|f| f.secret) {
+        let file_key = FIELDS
+            .iter()
+            .find(|k| k.key.strip_suffix("_file") == Some(f.key))
+            .map(|k| k.key);
+        let Some(file_key) = file_key else { continue };
+        let Some(path) = get(&table, file_key).and_then(|v| v.as_str()).map(String::from) else {
+            continue;
+        };
+        let layer = sources[file_key];
+        if sources.get(f.key).is_some_and(|&s| s > layer) {
+            continue;
+        }
+        let secret = std::fs::read_to_string(&path)
+            .map_err(|e| format!("{file_key}: failed to read {path}: {e}"))?;
+        set(&mut table, f.key, toml::Value::String(secret.trim().to_string()));
+        sources.insert(f.key, layer);
+    }
+    Ok((table, sources))
+}
+
+/// Set a dotted key in a config document, creating its tables.
+pub fn set_item(
+    doc: &mut toml_edit::DocumentMut,
+    key: &str,
+    value: toml_edit::Value,
+) -> Result<(), String> {
+    let (parents, leaf) = split(key);
+    let mut table = doc.as_table_mut();
+    for part in parents {
+        let item = table.entry(part).or_insert_with(|| {
+            let mut t = toml_edit::Table::new();
+            t.set_implicit(true);
+            toml_edit::Item::Table(t)
+        });
+        table = item
+            .as_table_mut()
+            .ok_or_else(|| format!("'{part}' in config.toml is not a table"))?;
+    }
+    match table.get_mut(leaf).and_then(|item| item.as_value_mut()) {
+        // Keep the comment after an existing value
+        Some(existing) => {
+            let decor = existing.decor().clone();
+            *existing = value;
+            *existing.decor_mut() = decor;
+        }
+        None => {
+            table.insert(leaf, toml_edit::value(value));
+        }
+    }
+    Ok(())
+}
+
+/// Remove a dotted key, and tables it leaves empty. Returns whether it was set.
+pub fn remove_item(doc: &mut toml_edit::DocumentMut, key: &str) -> bool {
+    fn remove(table: &mut toml_edit::Table, path: &[&str]) -> bool {
+        match path {
+            [] => false,
+            [leaf] => table.remove(leaf).is_some(),
+            [part, rest @ ..] => {
+                let Some(child) = table.get_mut(part).and_then(|i| i.as_table_mut()) else {
+                    return false;
+                };
+                let removed = remove(child, rest);
+                if removed && child.is_empty() {
+                    table.remove(part);
+                }
+                removed
+            }
+        }
+    }
+    let parts: Vec<_> = key.split('.').collect();
+    remove(doc.as_table_mut(), &parts)
+}
+
+/// Convert a value from `Field::parse` for a config document.
+pub fn to_edit_value(value: &toml::Value) -> toml_edit::Value {
+    match value {
+        toml::Value::Integer(n) => (*n).into(),
+        toml::Value::Boolean(b) => (*b).into(),
+        toml::Value::String(s) => s.as_str().into(),
+        other => other.to_string().into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn table(toml: &str) -> toml::Table {
+        toml::from_str(toml).unwrap()
+    }
+
+    fn str_at<'a>(table: &'a toml::Table, key: &str) -> &'a str {
+        get(table, key).and_then(|v| v.as_str()).unwrap()
+    }
+
+    /// Layer `toml` under the given `OMCLI_*` variables, ignoring the real environment
+    fn layer_env(
+        toml: &str,
+        vars: &[(&str, &str)],
+    ) -> Result<(toml::Table, HashMap<&'static str, Source>), String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        layer_with(table(toml), |var| vars.get(var).cloned())
+    }
+
+    /// A file holding an API key, in a directory of its own
+    fn secret_file(name: &str) -> (PathBuf, String) {
+        let dir = std::env::temp_dir().join(format!("omcli-test-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let secret = dir.join("api_key");
+        std::fs::write(&secret, "from-secret-file\n").unwrap();
+        let secret = secret.display().to_string();
+        (dir, secret)
+    }
+
+    #[test]
+    fn file_values_are_kept() {
+        let (t, sources) =
+            layer_env("[server]\napi_key = \"from-file\"\nport = 8080", &[]).unwrap();
+        assert_eq!(str_at(&t, "server.api_key"), "from-file");
+        assert_eq!(sources["server.port"], Source::File);
+    }
+
+    #[test]
+    fn env_beats_the_file() {
+        let vars = [("OMCLI_SERVER_PORT", "9090")];
+        let (t, sources) = layer_env("[server]\nport = 8080", &vars).unwrap();
+        assert_eq!(get(&t, "server.port").unwrap().as_integer(), Some(9090));
+        assert_eq!(sources["server.port"], Source::Env);
+
+        let err = layer_env("", &[("OMCLI_SERVER_PORT", "not-a-port")]).unwrap_err();
+        assert!(err.contains("OMCLI_SERVER_PORT"), "{err}");
+    }
+
+    #[test]
+    fn secret_file_precedence() {
+        let (dir, secret) = secret_file("layer");
+        let file = format!("[server]\napi_key = \"from-file\"\napi_key_file = \"{secret}\"");
+
+        // `api_key_file` in the file beats `api_key` in the same file
+        let (t, sources) = layer_env(&file, &[]).unwrap();
+        assert_eq!(str_at(&t, "server.api_key"), "from-secret-file");
+        assert_eq!(sources["server.api_key"], Source::File);
+
+        // ...but not `OMCLI_SERVER_API_KEY`
+        let (t, sources) = layer_env(&file, &[("OMCLI_SERVER_API_KEY", "from-env")]).unwrap();
+        assert_eq!(str_at(&t, "server.api_key"), "from-env");
+        assert_eq!(sources["server.api_key"], Source::Env);
+
+        // `OMCLI_SERVER_API_KEY_FILE` beats `api_key` in the file
+        let vars = [("OMCLI_SERVER_API_KEY_FILE", secret.as_str())];
+        let (t, sources) = layer_env("[server]\napi_key = \"from-file\"", &vars).unwrap();
+        assert_eq!(str_at(&t, "server.api_key"), "from-secret-file");
+        assert_eq!(sources["server.api_key"], Source::Env);
+
+        // A secret file that cannot be read is an error, not an empty key
+        let missing = dir.join("missing").display().to_string();
+        let err = layer_env("", &[("OMCLI_SERVER_API_KEY_FILE", missing.as_str())]).unwrap_err();
+        assert!(err.contains("api_key_file"), "{err}");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -3,3 +3,4 @@ pub mod config;
 pub mod protocol;
 pub mod relay;
 pub mod server;
+pub mod tls;
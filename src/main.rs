@@ -1,26 +1,45 @@
 use clap::{Parser, Subcommand};
+use omcli::cli::OutputFormat;
+use omcli::protocol::command::{Facing, Priority, Sound};
 
 #[derive(Parser)]
-#[command(name = "omcli", version, about = "Remote mobile device control")]
+#[command(
+    name = "omcli",
+    version,
+    about = "Remote mobile device control",
+    after_help = "Exit codes: 0 ok, 1 error, 2 usage, 3 server unreachable, 4 auth failed,\n\
+                  5 device offline or command queued, 6 timeout, 7 user declined, 8 device error"
+)]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    /// Output format: human-readable text or the raw JSON result
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+    /// Server context to use for this command (see `omcli context list`)
+    #[arg(long, global = true)]
+    context: Option<String>,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// Start the relay server
     Serve {
-        #[arg(long, default_value = "7333")]
-        port: u16,
-        #[arg(long, default_value = "127.0.0.1")]
-        bind: String,
+        /// Port to listen on (default: server.port, else 7333)
+        #[arg(long)]
+        port: Option<u16>,
+        /// Address to bind (default: server.bind, else 127.0.0.1)
+        #[arg(long)]
+        bind: Option<String>,
         /// Suppress QR code output
         #[arg(long)]
         no_qr: bool,
         /// Override host in QR code (for VPS/remote servers)
         #[arg(long)]
         host: Option<String>,
+        /// Print an enrollment code for `omcli connect` on another machine
+        #[arg(long)]
+        enroll: bool,
     },
     /// Alarm commands
     Alarm {
@@ -38,39 +57,118 @@ enum Commands {
         message: String,
         /// Priority: low, normal, critical
         #[arg(long, default_value = "normal")]
-        priority: String,
+        priority: Priority,
+        /// Target device (id, alias or name prefix)
+        #[arg(long)]
+        device: Option<String>,
+        /// Send to every device in this group
+        #[arg(long, conflicts_with = "device")]
+        group: Option<String>,
     },
     /// Get device location
     Locate {
         #[arg(long)]
         device: Option<String>,
+        /// Send to every device in this group
+        #[arg(long, conflicts_with = "device")]
+        group: Option<String>,
     },
     /// Server and device status
     Status,
+    /// Check config, server, API key, push setup and devices; exits 1 if a check fails
+    Doctor,
+    /// Print the OpenAPI 3 document of the REST API, e.g. to generate a client
+    Openapi,
+    /// Measure the WebSocket round trip to a device, or to every online device
+    Ping {
+        /// Target device (id, alias or name prefix)
+        #[arg(long)]
+        device: Option<String>,
+    },
     /// Pair a device using the 6-digit code
     Pair {
         /// 6-digit pairing code from device
         code: String,
+        /// Replace the token of a device that is already paired
+        #[arg(long)]
+        confirm: bool,
+    },
+    /// Use a remote server: exchange an enrollment code for an API key
+    Connect {
+        /// Server URL, e.g. https://vps.example.com:7333
+        url: String,
+        /// Code from `omcli keys enroll` or `omcli serve --enroll`
+        code: String,
+        /// Context name to save the server under (default: the URL's host)
+        #[arg(long)]
+        name: Option<String>,
+        /// SHA-256 fingerprint of the server's self-signed certificate
+        #[arg(long)]
+        fingerprint: Option<String>,
+    },
+    /// Manage the servers this CLI talks to
+    Context {
+        #[command(subcommand)]
+        action: ContextAction,
     },
     /// Activate sleep/standby mode (keeps screen on for alarm)
     Sleep {
-        /// Target device ID
+        /// Target device (id, alias or name prefix)
         #[arg(long)]
         device: Option<String>,
+        /// Send to every device in this group
+        #[arg(long, conflicts_with = "device")]
+        group: Option<String>,
     },
     /// Deactivate sleep mode
     Wake {
-        /// Target device ID
+        /// Target device (id, alias or name prefix)
         #[arg(long)]
         device: Option<String>,
+        /// Send to every device in this group
+        #[arg(long, conflicts_with = "device")]
+        group: Option<String>,
+    },
+    /// List paired devices, manage groups
+    Devices {
+        #[command(subcommand)]
+        action: Option<DevicesAction>,
+        /// Print the device list as JSON
+        #[arg(long)]
+        json: bool,
     },
-    /// List paired devices
-    Devices,
     /// View or update configuration
     Config {
         #[command(subcommand)]
         action: Option<ConfigAction>,
     },
+    /// Show recorded commands
+    History {
+        #[command(subcommand)]
+        action: Option<HistoryAction>,
+        /// Only commands sent to this device
+        #[arg(long)]
+        device: Option<String>,
+        /// Only this command (e.g. camera.snap) or family (e.g. alarm)
+        #[arg(long)]
+        command: Option<String>,
+        /// Only commands newer than this: 30m, 6h, 2d or a unix timestamp
+        #[arg(long)]
+        since: Option<String>,
+        /// Maximum number of entries
+        #[arg(long, default_value = "50")]
+        limit: usize,
+    },
+    /// Manage scoped API keys (admin key only)
+    Keys {
+        #[command(subcommand)]
+        action: KeysAction,
+    },
+    /// Run commands at a set time or on a cron schedule (server-side)
+    Schedule {
+        #[command(subcommand)]
+        action: ScheduleAction,
+    },
     /// Start push notification relay server
     Relay {
         #[arg(long)]
@@ -86,19 +184,89 @@ enum AlarmAction {
     Start {
         /// Sound: default, loud, hell
         #[arg(long, default_value = "default")]
-        sound: String,
+        sound: Sound,
         /// Optional message to display
         #[arg(long)]
         message: Option<String>,
-        /// Target device ID
+        /// Target device (id, alias or name prefix)
         #[arg(long)]
         device: Option<String>,
+        /// Send to every device in this group
+        #[arg(long, conflicts_with = "device")]
+        group: Option<String>,
     },
     /// Stop alarm on device
     Stop {
-        /// Target device ID
+        /// Target device (id, alias or name prefix)
         #[arg(long)]
         device: Option<String>,
+        /// Send to every device in this group
+        #[arg(long, conflicts_with = "device")]
+        group: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum DevicesAction {
+    /// Show pairing, token and connection details
+    Show {
+        /// Device id, alias or name prefix
+        device: String,
+    },
+    /// Unpair a device and drop its queued commands
+    Remove {
+        /// Device id, alias or name prefix
+        device: String,
+    },
+    /// Invalidate a device's token and force it to pair again
+    Revoke {
+        /// Device id, alias or name prefix
+        device: String,
+    },
+    /// Manage device groups
+    Group {
+        #[command(subcommand)]
+        action: GroupAction,
+    },
+    /// Change a device's display name
+    Rename {
+        /// Device id, alias or name prefix
+        device: String,
+        /// New name
+        name: String,
+    },
+    /// Set a short alias usable wherever a device id is (omit to clear)
+    Alias {
+        /// Device id, alias or name prefix
+        device: String,
+        /// Alias, e.g. phone
+        alias: Option<String>,
+    },
+    /// Make a device the target when --device is omitted
+    Default {
+        /// Device id, alias or name prefix
+        device: String,
+        /// Clear the default instead of setting it
+        #[arg(long)]
+        unset: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum GroupAction {
+    /// Add a device to a group
+    Add {
+        /// Device id, alias or name prefix
+        device: String,
+        /// Group name, e.g. oncall
+        group: String,
+    },
+    /// Remove a device from a group
+    Rm {
+        /// Device id, alias or name prefix
+        device: String,
+        /// Group name
+        group: String,
     },
 }
 
@@ -108,43 +276,194 @@ enum CameraAction {
     Snap {
         /// Camera: front or back
         #[arg(long, default_value = "back")]
-        facing: String,
+        facing: Facing,
         /// Output file path (default: photo_TIMESTAMP.jpg)
         #[arg(long)]
         output: Option<String>,
-        /// Target device ID
+        /// Target device (id, alias or name prefix)
         #[arg(long)]
         device: Option<String>,
     },
 }
 
+#[derive(Subcommand)]
+enum HistoryAction {
+    /// Send a recorded command again with the same params and device
+    Replay {
+        /// Command ID from `omcli history`
+        id: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum KeysAction {
+    /// Create a key, e.g. `keys create ci --commands notify --expires 90d`
+    Create {
+        /// Name shown in listings and recorded as the command source
+        name: String,
+        /// Allowed commands or families (e.g. notify, alarm.stop); default all
+        #[arg(long, value_delimiter = ',')]
+        commands: Vec<String>,
+        /// Allowed devices (id, alias or name prefix); default all
+        #[arg(long, value_delimiter = ',')]
+        devices: Vec<String>,
+        /// Only allow reading status, devices, history and events
+        #[arg(long, conflicts_with = "commands")]
+        read_only: bool,
+        /// Lifetime, e.g. 12h or 30d; default never expires
+        #[arg(long)]
+        expires: Option<String>,
+    },
+    /// Print a short-lived code that `omcli connect` exchanges for a new key
+    Enroll {
+        /// Key name; default the enrolling machine's hostname
+        #[arg(long)]
+        name: Option<String>,
+        /// Allowed commands or families (e.g. notify, alarm.stop); default all
+        #[arg(long, value_delimiter = ',')]
+        commands: Vec<String>,
+        /// Allowed devices (id, alias or name prefix); default all
+        #[arg(long, value_delimiter = ',')]
+        devices: Vec<String>,
+        /// Only allow reading status, devices, history and events
+        #[arg(long, conflicts_with = "commands")]
+        read_only: bool,
+        /// Lifetime of the key, e.g. 12h or 30d; default never expires
+        #[arg(long)]
+        expires: Option<String>,
+    },
+    /// List keys and their scopes
+    List,
+    /// Delete a key immediately
+    Revoke {
+        /// Key id or name
+        key: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum ScheduleAction {
+    /// Schedule a command, e.g. `schedule add --cron "0 7 * * 1-5" alarm.start --sound loud`
+    Add {
+        /// One-shot time: HH:MM (next occurrence) or "YYYY-MM-DD HH:MM", local time.
+        /// With --cron, the first run; the cron takes over after it
+        #[arg(long, required_unless_present = "cron")]
+        at: Option<String>,
+        /// Five-field cron expression, evaluated in the server's local time
+        #[arg(long)]
+        cron: Option<String>,
+        /// Target device (id, alias or name prefix)
+        #[arg(long)]
+        device: Option<String>,
+        /// Pass a command the server does not know through without checking its params
+        #[arg(long)]
+        custom: bool,
+        /// Command to send, e.g. alarm.start
+        command: String,
+        /// Command params as --key value pairs
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        params: Vec<String>,
+    },
+    /// List schedules
+    List,
+    /// Remove a schedule
+    Rm {
+        /// Schedule ID
+        id: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum ContextAction {
+    /// Add or replace a context
+    Add {
+        /// Context name, e.g. home
+        name: String,
+        /// Server URL, e.g. https://vps.example.com:7333
+        #[arg(long)]
+        url: String,
+        /// API key for that server
+        #[arg(long)]
+        api_key: String,
+        /// Device to target when --device is omitted
+        #[arg(long)]
+        device: Option<String>,
+        /// PEM certificate to trust, for a self-signed server certificate
+        #[arg(long)]
+        ca_cert: Option<String>,
+    },
+    /// Make a context current (`local` is the server on this machine)
+    Use {
+        /// Context name
+        name: String,
+    },
+    /// List contexts; the current one is marked with *
+    List,
+    /// Remove a context
+    Rm {
+        /// Context name
+        name: String,
+    },
+}
+
 #[derive(Subcommand)]
 enum ConfigAction {
-    /// Set a config value (keys: server, api_key, port, bind)
+    /// Print one value, after environment overrides and defaults
+    Get {
+        /// Dotted key, e.g. apns.key_id (see `omcli config keys`)
+        key: String,
+    },
+    /// Write a value to config.toml, keeping its comments
     Set {
+        /// Dotted key, e.g. relay.port (see `omcli config keys`)
         key: String,
         value: String,
     },
+    /// Remove a value from config.toml so its default applies
+    Unset {
+        /// Dotted key (see `omcli config keys`)
+        key: String,
+    },
+    /// List every key with its type and environment variable
+    Keys,
+    /// Make the running server re-read config.toml (same as sending it SIGHUP)
+    Reload,
 }
 
 #[tokio::main]
 async fn main() {
     let cli = Cli::parse();
+    omcli::cli::set_output(cli.output);
+    omcli::cli::set_context(cli.context);
 
-    match cli.command {
-        Commands::Serve { port, bind, no_qr, host } => {
-            omcli::server::serve(port, bind, no_qr, host).await;
+    let result = match cli.command {
+        Commands::Serve {
+            port,
+            bind,
+            no_qr,
+            host,
+            enroll,
+        } => {
+            omcli::server::serve(port, bind, no_qr, host, enroll).await;
+            Ok(())
         }
         Commands::Alarm { action } => match action {
             AlarmAction::Start {
                 sound,
                 message,
                 device,
+                group,
             } => {
-                omcli::cli::alarm_start(&sound, message.as_deref(), device.as_deref()).await;
+                omcli::cli::alarm_start(
+                    sound,
+                    message.as_deref(),
+                    device.as_deref(),
+                    group.as_deref(),
+                )
+                .await
             }
-            AlarmAction::Stop { device } => {
-                omcli::cli::alarm_stop(device.as_deref()).await;
+            AlarmAction::Stop { device, group } => {
+                omcli::cli::alarm_stop(device.as_deref(), group.as_deref()).await
             }
         },
         Commands::Camera { action } => match action {
@@ -152,41 +471,165 @@ async fn main() {
                 facing,
                 output,
                 device,
-            } => {
-                omcli::cli::camera_snap(&facing, output.as_deref(), device.as_deref()).await;
-            }
+            } => omcli::cli::camera_snap(facing, output.as_deref(), device.as_deref()).await,
         },
-        Commands::Notify { message, priority } => {
-            omcli::cli::send_notification(&message, &priority).await;
-        }
-        Commands::Locate { device } => {
-            omcli::cli::locate(device.as_deref()).await;
-        }
-        Commands::Sleep { device } => {
-            omcli::cli::sleep_start(device.as_deref()).await;
+        Commands::Notify {
+            message,
+            priority,
+            device,
+            group,
+        } => {
+            omcli::cli::send_notification(&message, priority, device.as_deref(), group.as_deref())
+                .await
         }
-        Commands::Wake { device } => {
-            omcli::cli::sleep_stop(device.as_deref()).await;
+        Commands::Locate { device, group } => {
+            omcli::cli::locate(device.as_deref(), group.as_deref()).await
         }
-        Commands::Status => {
-            omcli::cli::server_status().await;
+        Commands::Sleep { device, group } => {
+            omcli::cli::sleep_start(device.as_deref(), group.as_deref()).await
         }
-        Commands::Pair { code } => {
-            omcli::cli::pair(&code).await;
+        Commands::Wake { device, group } => {
+            omcli::cli::sleep_stop(device.as_deref(), group.as_deref()).await
         }
-        Commands::Devices => {
-            omcli::cli::list_devices().await;
+        Commands::Status => omcli::cli::server_status().await,
+        Commands::Doctor => omcli::cli::doctor().await,
+        Commands::Openapi => omcli::cli::print_openapi().await,
+        Commands::Ping { device } => omcli::cli::ping(device.as_deref()).await,
+        Commands::Pair { code, confirm } => omcli::cli::pair(&code, confirm).await,
+        Commands::Context { action } => match action {
+            ContextAction::Add {
+                name,
+                url,
+                api_key,
+                device,
+                ca_cert,
+            } => {
+                omcli::cli::context_add(
+                    &name,
+                    &url,
+                    &api_key,
+                    device.as_deref(),
+                    ca_cert.as_deref(),
+                )
+                .await
+            }
+            ContextAction::Use { name } => omcli::cli::context_use(&name).await,
+            ContextAction::List => omcli::cli::context_list().await,
+            ContextAction::Rm { name } => omcli::cli::context_rm(&name).await,
+        },
+        Commands::Connect {
+            url,
+            code,
+            name,
+            fingerprint,
+        } => {
+            omcli::cli::connect(&url, &code, name.as_deref(), fingerprint.as_deref()).await
         }
-        Commands::Config { action } => match action {
-            Some(ConfigAction::Set { key, value }) => {
-                omcli::cli::set_config(&key, &value).await;
+        Commands::Devices { action, json } => match action {
+            Some(DevicesAction::Show { device }) => omcli::cli::show_device(&device).await,
+            Some(DevicesAction::Remove { device }) => omcli::cli::remove_device(&device).await,
+            Some(DevicesAction::Revoke { device }) => omcli::cli::revoke_device(&device).await,
+            Some(DevicesAction::Group { action }) => match action {
+                GroupAction::Add { device, group } => omcli::cli::group_add(&device, &group).await,
+                GroupAction::Rm { device, group } => omcli::cli::group_rm(&device, &group).await,
+            },
+            Some(DevicesAction::Rename { device, name }) => {
+                omcli::cli::rename(&device, &name).await
+            }
+            Some(DevicesAction::Alias { device, alias }) => {
+                omcli::cli::set_alias(&device, alias.as_deref()).await
+            }
+            Some(DevicesAction::Default { device, unset }) => {
+                omcli::cli::set_default(&device, unset).await
             }
+            None => omcli::cli::list_devices(json).await,
+        },
+        Commands::Config { action } => match action {
+            Some(ConfigAction::Get { key }) => omcli::cli::get_config(&key).await,
+            Some(ConfigAction::Set { key, value }) => omcli::cli::set_config(&key, &value).await,
+            Some(ConfigAction::Unset { key }) => omcli::cli::unset_config(&key).await,
+            Some(ConfigAction::Keys) => omcli::cli::config_keys().await,
+            Some(ConfigAction::Reload) => omcli::cli::reload_config().await,
+            None => omcli::cli::show_config().await,
+        },
+        Commands::History {
+            action,
+            device,
+            command,
+            since,
+            limit,
+        } => match action {
+            Some(HistoryAction::Replay { id }) => omcli::cli::history_replay(&id).await,
             None => {
-                omcli::cli::show_config().await;
+                omcli::cli::history(
+                    device.as_deref(),
+                    command.as_deref(),
+                    since.as_deref(),
+                    limit,
+                )
+                .await
+            }
+        },
+        Commands::Schedule { action } => match action {
+            ScheduleAction::Add {
+                at,
+                cron,
+                device,
+                custom,
+                command,
+                params,
+            } => {
+                omcli::cli::schedule_add(
+                    at.as_deref(),
+                    cron.as_deref(),
+                    &command,
+                    &params,
+                    device.as_deref(),
+                    custom,
+                )
+                .await
             }
+            ScheduleAction::List => omcli::cli::schedule_list().await,
+            ScheduleAction::Rm { id } => omcli::cli::schedule_rm(&id).await,
+        },
+        Commands::Keys { action } => match action {
+            KeysAction::Create {
+                name,
+                commands,
+                devices,
+                read_only,
+                expires,
+            } => {
+                omcli::cli::create_key(&name, &commands, &devices, read_only, expires.as_deref())
+                    .await
+            }
+            KeysAction::Enroll {
+                name,
+                commands,
+                devices,
+                read_only,
+                expires,
+            } => {
+                omcli::cli::create_enrollment(
+                    name.as_deref(),
+                    &commands,
+                    &devices,
+                    read_only,
+                    expires.as_deref(),
+                )
+                .await
+            }
+            KeysAction::List => omcli::cli::list_keys().await,
+            KeysAction::Revoke { key } => omcli::cli::revoke_key(&key).await,
         },
         Commands::Relay { port, bind } => {
             omcli::relay::relay(port, bind).await;
+            Ok(())
         }
+    };
+
+    if let Err(e) = result {
+        e.report();
+        std::process::exit(e.exit_code());
     }
 }
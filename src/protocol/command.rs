@@ -0,0 +1,570 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::str::FromStr;
+
+use super::{is_sealed, ApiErrorInfo, ErrorCode, E2E_KEY_COMMAND};
+
+/// Type of a command param, used to check requests and to describe it in JSON Schema
+#[derive(Debug, Clone, Copy)]
+pub enum ParamKind {
+    Str,
+    Choice(&'static [&'static str]),
+}
+
+/// One param of a known command
+#[derive(Debug)]
+pub struct Param {
+    pub name: &'static str,
+    pub kind: ParamKind,
+    pub required: bool,
+    pub about: &'static str,
+}
+
+const fn param(name: &'static str, kind: ParamKind, about: &'static str) -> Param {
+    Param {
+        name,
+        kind,
+        required: false,
+        about,
+    }
+}
+
+const fn required(name: &'static str, kind: ParamKind, about: &'static str) -> Param {
+    Param {
+        name,
+        kind,
+        required: true,
+        about,
+    }
+}
+
+/// A command the server knows and checks before sending it
+#[derive(Debug)]
+pub struct CommandSpec {
+    pub name: &'static str,
+    pub about: &'static str,
+    pub params: &'static [Param],
+}
+
+/// Every command the server validates. Anything else must be sent with
+/// `"custom": true` and is passed to the device as it is.
+pub const COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "alarm.start",
+        about: "Ring the alarm until it is stopped or dismissed",
+        params: &[
+            param("sound", ParamKind::Choice(Sound::VALUES), "Sound level (default: default)"),
+            param("message", ParamKind::Str, "Text shown with the alarm"),
+        ],
+    },
+    CommandSpec {
+        name: "alarm.stop",
+        about: "Stop a ringing alarm",
+        params: &[],
+    },
+    CommandSpec {
+        name: "notify.send",
+        about: "Show a notification",
+        params: &[
+            param("title", ParamKind::Str, "Notification title"),
+            required("body", ParamKind::Str, "Notification text"),
+            param("priority", ParamKind::Choice(Priority::VALUES), "Priority (default: normal)"),
+        ],
+    },
+    CommandSpec {
+        name: "tts.speak",
+        about: "Speak text aloud",
+        params: &[
+            required("text", ParamKind::Str, "Text to speak"),
+            param("voice", ParamKind::Str, "Voice identifier"),
+        ],
+    },
+    CommandSpec {
+        name: "location.get",
+        about: "Current GPS position",
+        params: &[param(
+            "accuracy",
+            ParamKind::Choice(Accuracy::VALUES),
+            "Location accuracy (default: coarse)",
+        )],
+    },
+    CommandSpec {
+        name: "camera.snap",
+        about: "Take a photo",
+        params: &[param("facing", ParamKind::Choice(Facing::VALUES), "Camera (default: back)")],
+    },
+    CommandSpec {
+        name: "sleep.start",
+        about: "Standby mode: keep the screen on so the alarm can ring",
+        params: &[],
+    },
+    CommandSpec {
+        name: "sleep.stop",
+        about: "Leave standby mode",
+        params: &[],
+    },
+    CommandSpec {
+        name: "device.status",
+        about: "Battery and charging state",
+        params: &[],
+    },
+    CommandSpec {
+        name: E2E_KEY_COMMAND,
+        about: "Reveal the CLI's end-to-end public key after pairing",
+        params: &[required("public_key", ParamKind::Str, "Base64 raw X25519 public key")],
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static CommandSpec> {
+    COMMANDS.iter().find(|c| c.name == name)
+}
+
+/// Why one param was refused
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl CommandSpec {
+    /// Every problem with `params` at once. Sealed params cannot be read and
+    /// are left to the device; a missing `params` counts as `{}`.
+    pub fn validate(&self, params: &Value) -> Vec<FieldError> {
+        if is_sealed(params) {
+            return Vec::new();
+        }
+        let empty = serde_json::Map::new();
+        let object = match params {
+            Value::Null => &empty,
+            Value::Object(object) => object,
+            _ => {
+                return vec![FieldError {
+                    field: "params".into(),
+                    message: "must be an object".into(),
+                }]
+            }
+        };
+
+        let mut errors = Vec::new();
+        let error = |field: &str, message: String| FieldError {
+            field: field.to_string(),
+            message,
+        };
+        for p in self.params {
+            match (object.get(p.name), p.kind) {
+                (None, _) if p.required => errors.push(error(p.name, "is required".into())),
+                (None, _) => {}
+                (Some(Value::String(_)), ParamKind::Str) => {}
+                (Some(Value::String(s)), ParamKind::Choice(choices))
+                    if choices.contains(&s.as_str()) => {}
+                (Some(_), ParamKind::Str) => errors.push(error(p.name, "must be a string".into())),
+                (Some(_), ParamKind::Choice(choices)) => errors.push(error(
+                    p.name,
+                    format!("must be one of {}", choices.join(", ")),
+                )),
+            }
+        }
+        for name in object.keys() {
+            if !self.params.iter().any(|p| p.name == name) {
+                errors.push(error(name, "is not a parameter of this command".into()));
+            }
+        }
+        errors
+    }
+
+    /// `validate` as the `INVALID_PARAMS` error the server answers with
+    pub fn check(&self, params: &Value) -> Result<(), ApiErrorInfo> {
+        let errors = self.validate(params);
+        if errors.is_empty() {
+            return Ok(());
+        }
+        let summary: Vec<_> = errors.iter().map(|e| format!("{} {}", e.field, e.message)).collect();
+        Err(ApiErrorInfo {
+            code: ErrorCode::InvalidParams,
+            message: format!("Invalid params for {}: {}", self.name, summary.join("; ")),
+            details: Some(json!({ "errors": errors })),
+        })
+    }
+
+    /// JSON Schema of the params object
+    pub fn json_schema(&self) -> Value {
+        let properties: serde_json::Map<_, _> = self
+            .params
+            .iter()
+            .map(|p| {
+                let mut schema = json!({ "type": "string", "description": p.about });
+                if let ParamKind::Choice(choices) = p.kind {
+                    schema["enum"] = json!(choices);
+                }
+                (p.name.to_string(), schema)
+            })
+            .collect();
+        let required: Vec<_> = self.params.iter().filter(|p| p.required).map(|p| p.name).collect();
+        json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "title": self.name,
+            "description": self.about,
+            "type": "object",
+            "properties": properties,
+            "required": required,
+            "additionalProperties": false,
+        })
+    }
+}
+
+/// A known command with its params
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "command", content = "params")]
+pub enum Command {
+    #[serde(rename = "alarm.start")]
+    AlarmStart(AlarmStartParams),
+    #[serde(rename = "alarm.stop")]
+    AlarmStop(NoParams),
+    #[serde(rename = "notify.send")]
+    NotifySend(NotifyParams),
+    #[serde(rename = "tts.speak")]
+    TtsSpeak(TtsParams),
+    #[serde(rename = "location.get")]
+    LocationGet(LocationParams),
+    #[serde(rename = "camera.snap")]
+    CameraSnap(CameraParams),
+    #[serde(rename = "sleep.start")]
+    SleepStart(NoParams),
+    #[serde(rename = "sleep.stop")]
+    SleepStop(NoParams),
+    #[serde(rename = "device.status")]
+    DeviceStatus(NoParams),
+    #[serde(rename = "e2e.key")]
+    E2eKey(E2eKeyParams),
+}
+
+/// Why a command was refused
+#[derive(Debug)]
+pub enum CommandError {
+    /// Not in `COMMANDS`
+    Unknown,
+    Invalid(Vec<FieldError>),
+}
+
+impl Command {
+    /// `{"command": ..., "params": ...}`, the start of a `POST /api/command` body
+    pub fn body(&self) -> Value {
+        serde_json::to_value(self).expect("commands serialize to JSON")
+    }
+
+    /// Check `params` against the catalog and read them into the typed command.
+    pub fn parse(name: &str, params: &Value) -> Result<Command, CommandError> {
+        let spec = find(name).ok_or(CommandError::Unknown)?;
+        let errors = spec.validate(params);
+        if !errors.is_empty() {
+            return Err(CommandError::Invalid(errors));
+        }
+        let params = match params {
+            Value::Null => json!({}),
+            other => other.clone(),
+        };
+        serde_json::from_value(json!({ "command": name, "params": params })).map_err(|e| {
+            CommandError::Invalid(vec![FieldError {
+                field: "params".into(),
+                message: e.to_string(),
+            }])
+        })
+    }
+}
+
+/// Params of commands that take none
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NoParams {}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlarmStartParams {
+    #[serde(default)]
+    pub sound: Sound,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sound {
+    #[default]
+    Default,
+    Loud,
+    /// Full volume, repeated until dismissed
+    Hell,
+}
+
+impl Sound {
+    pub const VALUES: &'static [&'static str] = &["default", "loud", "hell"];
+}
+
+impl FromStr for Sound {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        parse_choice(s, Self::VALUES)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NotifyParams {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    pub body: String,
+    #[serde(default)]
+    pub priority: Priority,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    /// Plays even in silent mode
+    Critical,
+}
+
+impl Priority {
+    pub const VALUES: &'static [&'static str] = &["low", "normal", "critical"];
+}
+
+impl FromStr for Priority {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        parse_choice(s, Self::VALUES)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TtsParams {
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub voice: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LocationParams {
+    #[serde(default)]
+    pub accuracy: Accuracy,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Accuracy {
+    #[default]
+    Coarse,
+    Precise,
+}
+
+impl Accuracy {
+    pub const VALUES: &'static [&'static str] = &["coarse", "precise"];
+}
+
+impl FromStr for Accuracy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        parse_choice(s, Self::VALUES)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraParams {
+    #[serde(default)]
+    pub facing: Facing,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Facing {
+    #[default]
+    Back,
+    Front,
+}
+
+impl Facing {
+    pub const VALUES: &'static [&'static str] = &["back", "front"];
+}
+
+impl FromStr for Facing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        parse_choice(s, Self::VALUES)
+    }
+}
+
+/// A choice param from its wire name, e.g. a CLI flag
+fn parse_choice<T: DeserializeOwned>(s: &str, values: &[&str]) -> Result<T, String> {
+    serde_json::from_value(Value::String(s.to_string()))
+        .map_err(|_| format!("must be one of {}", values.join(", ")))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct E2eKeyParams {
+    pub public_key: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One command per variant, with every param set so the spec's param list
+    /// can be compared to the fields.
+    fn samples() -> Vec<Command> {
+        vec![
+            Command::AlarmStart(AlarmStartParams {
+                sound: Sound::Hell,
+                message: Some("Wake up".into()),
+            }),
+            Command::AlarmStop(NoParams {}),
+            Command::NotifySend(NotifyParams {
+                title: Some("Title".into()),
+                body: "Body".into(),
+                priority: Priority::Critical,
+            }),
+            Command::TtsSpeak(TtsParams {
+                text: "Hello".into(),
+                voice: Some("en-US".into()),
+            }),
+            Command::LocationGet(LocationParams {
+                accuracy: Accuracy::Precise,
+            }),
+            Command::CameraSnap(CameraParams {
+                facing: Facing::Front,
+            }),
+            Command::SleepStart(NoParams {}),
+            Command::SleepStop(NoParams {}),
+            Command::DeviceStatus(NoParams {}),
+            Command::E2eKey(E2eKeyParams {
+                public_key: "AAAA".into(),
+            }),
+        ]
+    }
+
+    /// Adding a variant fails to compile here until it has a sample above.
+    fn has_sample(command: &Command) -> bool {
+        match command {
+            Command::AlarmStart(_)
+            | Command::AlarmStop(_)
+            | Command::NotifySend(_)
+            | Command::TtsSpeak(_)
+            | Command::LocationGet(_)
+            | Command::CameraSnap(_)
+            | Command::SleepStart(_)
+            | Command::SleepStop(_)
+            | Command::DeviceStatus(_)
+            | Command::E2eKey(_) => true,
+        }
+    }
+
+    #[test]
+    fn catalog_lists_every_command_variant() {
+        let names: Vec<_> = samples()
+            .iter()
+            .inspect(|c| assert!(has_sample(c)))
+            .map(|c| c.body()["command"].as_str().unwrap().to_string())
+            .collect();
+        let catalog: Vec<_> = COMMANDS.iter().map(|c| c.name.to_string()).collect();
+        assert_eq!(names, catalog);
+    }
+
+    #[test]
+    fn every_command_round_trips_through_its_spec() {
+        for command in samples() {
+            let body = command.body();
+            let name = body["command"].as_str().unwrap();
+            let spec = find(name).unwrap();
+            let params = &body["params"];
+
+            let errors = spec.validate(params);
+            assert!(errors.is_empty(), "{name}: {errors:?}");
+            let parsed = Command::parse(name, params).unwrap_or_else(|e| panic!("{name}: {e:?}"));
+            assert_eq!(parsed.body(), body);
+
+            let mut fields: Vec<_> = params.as_object().unwrap().keys().cloned().collect();
+            let mut spec_params: Vec<_> = spec.params.iter().map(|p| p.name.to_string()).collect();
+            fields.sort();
+            spec_params.sort();
+            assert_eq!(fields, spec_params, "{name}");
+        }
+    }
+
+    fn fields(errors: &[FieldError]) -> Vec<(&str, &str)> {
+        errors
+            .iter()
+            .map(|e| (e.field.as_str(), e.message.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn validate_reports_every_problem() {
+        let spec = find("notify.send").unwrap();
+        let errors = spec.validate(&json!({"title": 3, "priority": "urgent", "sound": "loud"}));
+        assert_eq!(
+            fields(&errors),
+            [
+                ("title", "must be a string"),
+                ("body", "is required"),
+                ("priority", "must be one of low, normal, critical"),
+                ("sound", "is not a parameter of this command"),
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_treats_missing_params_as_empty() {
+        assert!(find("alarm.stop")
+            .unwrap()
+            .validate(&Value::Null)
+            .is_empty());
+        let errors = find("tts.speak").unwrap().validate(&Value::Null);
+        assert_eq!(fields(&errors), [("text", "is required")]);
+    }
+
+    #[test]
+    fn validate_refuses_params_that_are_not_an_object() {
+        let errors = find("alarm.start").unwrap().validate(&json!(["loud"]));
+        assert_eq!(fields(&errors), [("params", "must be an object")]);
+    }
+
+    #[test]
+    fn validate_leaves_sealed_params_to_the_device() {
+        let spec = find("notify.send").unwrap();
+        assert!(spec.validate(&json!({"e2e": "c2VhbGVk"})).is_empty());
+    }
+
+    #[test]
+    fn check_wraps_the_errors() {
+        let err = find("alarm.start")
+            .unwrap()
+            .check(&json!({"sound": "quiet"}))
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidParams);
+        assert_eq!(err.details.unwrap()["errors"][0]["field"], "sound");
+    }
+
+    #[test]
+    fn choice_values_match_the_enums() {
+        fn check<T: DeserializeOwned + Serialize>(values: &[&str]) {
+            for value in values {
+                let parsed: T = parse_choice(value, values).unwrap();
+                assert_eq!(serde_json::to_value(parsed).unwrap(), *value);
+            }
+        }
+        check::<Sound>(Sound::VALUES);
+        check::<Priority>(Priority::VALUES);
+        check::<Accuracy>(Accuracy::VALUES);
+        check::<Facing>(Facing::VALUES);
+    }
+}
@@ -1,13 +1,30 @@
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+pub mod command;
 
 // --- WebSocket messages ---
 
+/// Version of the device WebSocket protocol this server speaks
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Oldest device protocol version the server accepts; older devices are sent
+/// `incompatible` and disconnected
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
 /// Messages from device to server over WebSocket
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum DeviceMessage {
     #[serde(rename = "hello")]
-    Hello { device_id: String, name: String },
+    Hello {
+        device_id: String,
+        name: String,
+        /// X25519 public key (base64) when end-to-end encryption is enabled in the app
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        e2e_public_key: Option<String>,
+        #[serde(flatten)]
+        metadata: DeviceMetadata,
+    },
     #[serde(rename = "auth")]
     Auth { device_id: String, token: String },
     #[serde(rename = "response")]
@@ -29,6 +46,9 @@ pub enum DeviceMessage {
     PushToken { token: String },
     #[serde(rename = "voip_token")]
     VoipToken { token: String },
+    /// Optional receipt sent before the device starts executing a command
+    #[serde(rename = "ack")]
+    Ack { id: String },
 }
 
 /// Messages from server to device over WebSocket
@@ -46,6 +66,9 @@ pub enum ServerMessage {
         token: Option<String>,
         #[serde(skip_serializing_if = "Option::is_none")]
         error: Option<String>,
+        /// On pairing: SHA-256 (hex) of the CLI's public key, revealed later by `e2e.key`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        e2e_commitment: Option<String>,
     },
     #[serde(rename = "command")]
     Command {
@@ -53,6 +76,47 @@ pub enum ServerMessage {
         command: String,
         params: serde_json::Value,
     },
+    /// Answer to a hello whose protocol version is too old; the socket is closed after it
+    #[serde(rename = "incompatible")]
+    Incompatible {
+        protocol_version: u32,
+        min_protocol_version: u32,
+        error: String,
+    },
+}
+
+/// What a device reports about itself in its hello. Stored on the device when
+/// it authenticates; every field is absent for apps that predate it.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, ToSchema)]
+pub struct DeviceMetadata {
+    /// Device protocol version; apps that do not send one speak version 1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol_version: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app_version: Option<String>,
+    /// `ios`, `android`, ...
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub os_version: Option<String>,
+    /// Hardware model, e.g. `iPhone15,2`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Commands the app handles. Without a list nothing is rejected up front.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commands: Option<Vec<String>>,
+}
+
+impl DeviceMetadata {
+    pub fn protocol_version(&self) -> u32 {
+        self.protocol_version.unwrap_or(1)
+    }
+
+    pub fn supports(&self, command: &str) -> bool {
+        self.commands
+            .as_ref()
+            .is_none_or(|commands| commands.iter().any(|c| c == command))
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -63,18 +127,143 @@ pub struct ErrorInfo {
 
 // --- REST API types ---
 
+/// Stable machine-readable codes used in REST error responses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    /// Missing, wrong or expired API key
+    Unauthorized,
+    /// The API key is valid but its scope does not allow the request
+    Forbidden,
+    /// Malformed body, query or parameters
+    InvalidRequest,
+    /// Unknown route
+    NotFound,
+    MethodNotAllowed,
+    DeviceNotFound,
+    /// No device is connected and none could be picked for push fallback
+    DeviceOffline,
+    /// A name prefix matched several devices, or several are connected
+    DeviceAmbiguous,
+    /// The device token was revoked; it must pair again
+    DeviceRevoked,
+    AliasTaken,
+    GroupNotFound,
+    CommandNotFound,
+    ScheduleNotFound,
+    KeyNotFound,
+    InvalidPairingCode,
+    InvalidEnrollmentCode,
+    /// config.toml does not load, or its APNs key does not; nothing was reloaded
+    InvalidConfig,
+    /// The device did not list the command among those it supports
+    CommandNotSupported,
+    /// Not a command the server knows; send it with `"custom": true`
+    UnknownCommand,
+    /// The params do not match the command; `details.errors` lists each field
+    InvalidParams,
+    /// The code would replace the token of a device that is still paired
+    PairingConfirmationRequired,
+    /// Too many failed attempts from this client
+    RateLimited,
+    /// Device is offline and has no push/VoIP token for fallback
+    NoPushToken,
+    /// Device is offline and neither APNs nor a relay is configured
+    PushNotConfigured,
+    /// APNs or the push relay rejected the notification
+    PushFailed,
+    /// The device did not respond in time
+    Timeout,
+    #[serde(rename = "INTERNAL_ERROR")]
+    Internal,
+    /// Code from a newer server
+    #[serde(other)]
+    Unknown,
+}
+
+impl ErrorCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::Unauthorized => "UNAUTHORIZED",
+            ErrorCode::Forbidden => "FORBIDDEN",
+            ErrorCode::InvalidRequest => "INVALID_REQUEST",
+            ErrorCode::NotFound => "NOT_FOUND",
+            ErrorCode::MethodNotAllowed => "METHOD_NOT_ALLOWED",
+            ErrorCode::DeviceNotFound => "DEVICE_NOT_FOUND",
+            ErrorCode::DeviceOffline => "DEVICE_OFFLINE",
+            ErrorCode::DeviceAmbiguous => "DEVICE_AMBIGUOUS",
+            ErrorCode::DeviceRevoked => "DEVICE_REVOKED",
+            ErrorCode::AliasTaken => "ALIAS_TAKEN",
+            ErrorCode::GroupNotFound => "GROUP_NOT_FOUND",
+            ErrorCode::CommandNotFound => "COMMAND_NOT_FOUND",
+            ErrorCode::ScheduleNotFound => "SCHEDULE_NOT_FOUND",
+            ErrorCode::KeyNotFound => "KEY_NOT_FOUND",
+            ErrorCode::InvalidPairingCode => "INVALID_PAIRING_CODE",
+            ErrorCode::InvalidEnrollmentCode => "INVALID_ENROLLMENT_CODE",
+            ErrorCode::InvalidConfig => "INVALID_CONFIG",
+            ErrorCode::CommandNotSupported => "COMMAND_NOT_SUPPORTED",
+            ErrorCode::UnknownCommand => "UNKNOWN_COMMAND",
+            ErrorCode::InvalidParams => "INVALID_PARAMS",
+            ErrorCode::PairingConfirmationRequired => "PAIRING_CONFIRMATION_REQUIRED",
+            ErrorCode::RateLimited => "RATE_LIMITED",
+            ErrorCode::NoPushToken => "NO_PUSH_TOKEN",
+            ErrorCode::PushNotConfigured => "PUSH_NOT_CONFIGURED",
+            ErrorCode::PushFailed => "PUSH_FAILED",
+            ErrorCode::Timeout => "TIMEOUT",
+            ErrorCode::Internal => "INTERNAL_ERROR",
+            ErrorCode::Unknown => "UNKNOWN",
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Body of every non-2xx REST response: `{"error": {...}}`
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct ApiErrorBody {
+    pub error: ApiErrorInfo,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct ApiErrorInfo {
+    pub code: ErrorCode,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
+}
+
 /// POST /api/command body
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct CommandRequest {
+    /// Command id to use instead of a generated one. The CLI picks it for
+    /// sealed commands, whose encryption is bound to it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
     pub command: String,
     #[serde(default)]
     pub params: serde_json::Value,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub device_id: Option<String>,
+    /// Send to each of these devices instead of a single one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_ids: Option<Vec<String>>,
+    /// Send to every device tagged with this group
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// Drop the command if it is still queued for an offline device after this many seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_secs: Option<u64>,
+    /// Pass the command to the device as it is, without checking it against the catalog
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub custom: bool,
 }
 
 /// Command response (REST + internal)
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 pub struct CommandResponse {
     pub id: String,
     pub status: String,
@@ -86,52 +275,510 @@ pub struct CommandResponse {
     pub error_code: Option<String>,
 }
 
+/// Lifecycle of a command submitted via POST /api/command
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandState {
+    /// Accepted by the server, not yet handed to a transport
+    Queued,
+    /// Written to the device WebSocket
+    Sent,
+    /// Device confirmed receipt, or a push service accepted the notification
+    Acknowledged,
+    /// Device reported success
+    Completed,
+    /// Device reported an error or delivery failed
+    Failed,
+    /// Device did not respond in time
+    Expired,
+}
+
+/// GET /api/commands/{id} response (also returned by POST /api/command?async=true)
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct CommandStatus {
+    pub id: String,
+    pub command: String,
+    pub device_id: String,
+    /// Who submitted the command: `api`, `schedule:<id>`, ...
+    pub source: String,
+    pub state: CommandState,
+    /// Transport used: `websocket`, `apns`, `voip`, `relay`, `relay_voip`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delivered_via: Option<String>,
+    pub created_at: u64,
+    pub updated_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<CommandResponse>,
+}
+
 /// Stored device
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Device {
     pub id: String,
     pub name: String,
-    pub token: String,
+    /// Salted hash of the device token (`<salt>$<sha256>`); the token itself is never stored
+    #[serde(default)]
+    pub token_hash: String,
+    /// Token replaced at the last authentication, still accepted until `previous_token_expires`
+    /// in case the device never received the new one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_token_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_token_expires: Option<u64>,
+    /// Plaintext token written by older versions; hashed when the server loads it
+    #[serde(default, rename = "token", skip_serializing)]
+    pub legacy_token: Option<String>,
     pub paired_at: u64,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub push_token: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub voip_token: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+    /// User-assigned short name accepted wherever a device id is
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+    /// Target for commands that don't name a device
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub is_default: bool,
+    /// Unix seconds of the last authentication or disconnect
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<u64>,
+    /// Set when the token was revoked; the device must pair again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revoked_at: Option<u64>,
+    /// X25519 public key the device announced when it paired
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub e2e_public_key: Option<String>,
+    /// From the hello of the last authenticated connection
+    #[serde(flatten)]
+    pub metadata: DeviceMetadata,
+}
+
+/// Finished command (persisted in history.jsonl, GET /api/commands response item)
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct HistoryEntry {
+    pub id: String,
+    pub command: String,
+    #[serde(default)]
+    pub params: serde_json::Value,
+    pub device_id: String,
+    pub source: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delivered_via: Option<String>,
+    pub state: CommandState,
+    pub status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<String>,
+    pub created_at: u64,
+    pub finished_at: u64,
+    pub latency_ms: u64,
+}
+
+/// Command waiting for an offline device (persisted in queue.json)
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct QueuedCommand {
+    pub id: String,
+    pub command: String,
+    #[serde(default)]
+    pub params: serde_json::Value,
+    #[serde(default = "default_source")]
+    pub source: String,
+    pub queued_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+fn default_source() -> String {
+    "api".to_string()
+}
+
+/// Stored schedule (persisted in schedules.json, GET /api/schedules item)
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct Schedule {
+    pub id: String,
+    pub command: String,
+    #[serde(default)]
+    pub params: serde_json::Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<String>,
+    /// Unix time of a one-shot run, or of the first run of a cron schedule
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub at: Option<u64>,
+    /// Five-field cron expression, evaluated in the server's local time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cron: Option<String>,
+    pub created_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_run: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_run: Option<u64>,
+    /// Scoped API key that created the schedule. It only runs while the key
+    /// exists, has not expired and still allows the command and device.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
+}
+
+/// POST /api/schedules body — `at`, `cron`, or both for a cron schedule that
+/// first runs at `at`
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ScheduleRequest {
+    /// Schedule id to use instead of a generated one, see `CommandRequest::id`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub command: String,
+    #[serde(default)]
+    pub params: serde_json::Value,
+    /// See `CommandRequest::custom`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub custom: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cron: Option<String>,
 }
 
 /// GET /api/devices response item
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct DeviceInfo {
     pub id: String,
     pub name: String,
     pub online: bool,
     pub paired_at: u64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+    #[serde(default)]
+    pub is_default: bool,
+    /// WebSocket open, authenticated or not
+    #[serde(default)]
+    pub connected: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connected_since: Option<u64>,
+    /// Last frame from the device while connected, else when it disconnected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<u64>,
+    /// Peer address of the WebSocket, while connected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_addr: Option<String>,
+    /// Round trip of the last heartbeat ping, in milliseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rtt_ms: Option<u64>,
+    #[serde(default)]
+    pub has_push_token: bool,
+    #[serde(default)]
+    pub has_voip_token: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revoked_at: Option<u64>,
+    /// The device paired with end-to-end encryption enabled
+    #[serde(default)]
+    pub e2e: bool,
+    #[serde(flatten)]
+    pub metadata: DeviceMetadata,
+}
+
+/// POST /api/devices/{id}/ping response
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PingResponse {
+    pub device_id: String,
+    pub rtt_ms: u64,
+}
+
+/// PATCH /api/devices/{id} body — omitted fields are left unchanged
+#[derive(Debug, Serialize, Deserialize, Default, ToSchema)]
+pub struct DeviceUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Empty string clears the alias
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_default: Option<bool>,
+}
+
+/// POST /api/devices/{id}/groups body
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct GroupRequest {
+    pub group: String,
+}
+
+/// POST /api/command response when targeting several devices (`device_ids` or `group`)
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct FanoutResponse {
+    pub results: Vec<DeviceResult>,
+}
+
+/// Outcome of a fan-out command for one device
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DeviceResult {
+    pub device_id: String,
+    /// Final device or push response
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<CommandResponse>,
+    /// Accepted but not finished yet (async mode or offline queue)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pending: Option<CommandStatus>,
+    /// Delivery failed before reaching the device
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ApiErrorInfo>,
 }
 
 /// GET /api/status response
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct ServerStatus {
     pub version: String,
+    /// Device protocol version, see `PROTOCOL_VERSION`
+    #[serde(default)]
+    pub protocol_version: u32,
     pub uptime_secs: u64,
     pub devices_online: usize,
     pub devices_total: usize,
 }
 
+/// Result of one `omcli doctor` check
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// One line of the `omcli doctor` report; GET /api/doctor returns the server's share
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DoctorCheck {
+    /// Short id, e.g. `apns`, `relay`, `mdns`
+    pub name: String,
+    pub status: CheckStatus,
+    pub message: String,
+    /// What to do about a warning or failure
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+}
+
+impl DoctorCheck {
+    pub fn pass(name: &str, message: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status: CheckStatus::Pass,
+            message: message.into(),
+            hint: None,
+        }
+    }
+
+    pub fn warn(name: &str, message: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            status: CheckStatus::Warn,
+            hint: Some(hint.into()),
+            ..Self::pass(name, message)
+        }
+    }
+
+    pub fn fail(name: &str, message: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            status: CheckStatus::Fail,
+            hint: Some(hint.into()),
+            ..Self::pass(name, message)
+        }
+    }
+}
+
+/// A config.toml key a reload changed. Secrets are shown as `"********"`.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ConfigChange {
+    pub key: String,
+    pub old: Option<serde_json::Value>,
+    pub new: Option<serde_json::Value>,
+}
+
+/// POST /api/admin/reload response
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ReloadResponse {
+    pub changed: Vec<ConfigChange>,
+    /// Changed keys that only take effect when `omcli serve` restarts
+    pub restart_required: Vec<String>,
+}
+
 /// POST /api/devices/pair body
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct PairRequest {
     pub code: String,
+    /// Accept a pairing that replaces the token of an already paired device
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub confirm: bool,
+    /// SHA-256 (hex) of the CLI's X25519 public key, forwarded to the device
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub e2e_commitment: Option<String>,
 }
 
 /// POST /api/devices/pair response
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct PairResponse {
     pub device_id: String,
     pub name: String,
+    /// The device's X25519 public key when it has end-to-end encryption enabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub e2e_public_key: Option<String>,
 }
 
-/// Events broadcast to CLI WS clients
+// --- End-to-end encryption ---
+
+/// Command that reveals the CLI's public key to a device after pairing
+pub const E2E_KEY_COMMAND: &str = "e2e.key";
+
+/// Command params or response data sealed for one device.
+///
+/// `e2e` is base64 of `nonce (12) || ciphertext || tag (16)`, AES-256-GCM under a
+/// key both ends derive from X25519. The server routes it without reading it.
 #[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SealedEnvelope {
+    pub e2e: String,
+    /// Params sealed for a schedule are bound to its id rather than to the id
+    /// of each command it sends
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<String>,
+}
+
+impl SealedEnvelope {
+    /// The envelope if `value` is one, i.e. an object with `e2e` and at most
+    /// `schedule` besides it.
+    pub fn from_value(value: &serde_json::Value) -> Option<Self> {
+        let obj = value.as_object()?;
+        if obj.keys().any(|k| k != "e2e" && k != "schedule") {
+            return None;
+        }
+        Some(Self {
+            e2e: obj.get("e2e")?.as_str()?.to_string(),
+            schedule: match obj.get("schedule") {
+                Some(id) => Some(id.as_str()?.to_string()),
+                None => None,
+            },
+        })
+    }
+}
+
+pub fn is_sealed(value: &serde_json::Value) -> bool {
+    SealedEnvelope::from_value(value).is_some()
+}
+
+/// Named API key with a restricted scope (persisted in keys.json).
+///
+/// Only a hash of the secret is stored; the secret itself is shown once on creation.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    /// SHA-256 of the secret, hex encoded
+    pub key_hash: String,
+    /// First characters of the secret, to recognise it in listings
+    pub prefix: String,
+    #[serde(flatten)]
+    pub scope: KeyScope,
+    pub created_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+/// What a scoped API key may do. Empty lists mean no restriction.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, ToSchema)]
+pub struct KeyScope {
+    /// Allowed commands; a family like `notify` allows every `notify.*`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub commands: Vec<String>,
+    /// Allowed device ids
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub devices: Vec<String>,
+    /// Only GET requests and the event stream
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub read_only: bool,
+}
+
+/// GET /api/keys response item — never includes the secret
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ApiKeyInfo {
+    pub id: String,
+    pub name: String,
+    pub prefix: String,
+    #[serde(flatten)]
+    pub scope: KeyScope,
+    pub created_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    #[serde(default)]
+    pub expired: bool,
+}
+
+/// POST /api/keys body
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CreateKeyRequest {
+    pub name: String,
+    /// Device ids, aliases or name prefixes are accepted and stored as ids
+    #[serde(flatten)]
+    pub scope: KeyScope,
+    /// Lifetime in seconds; omitted keys never expire
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_secs: Option<u64>,
+}
+
+/// POST /api/keys response — the only time the secret is returned
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CreateKeyResponse {
+    #[serde(flatten)]
+    pub info: ApiKeyInfo,
+    pub key: String,
+}
+
+/// POST /api/keys/enroll body — the scope of the key the code will mint
+#[derive(Debug, Serialize, Deserialize, Default, ToSchema)]
+pub struct CreateEnrollmentRequest {
+    /// Key name; defaults to the name the connecting machine sends
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub scope: KeyScope,
+    /// Lifetime of the minted key in seconds; omitted keys never expire
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_secs: Option<u64>,
+}
+
+/// POST /api/keys/enroll response
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct EnrollmentInfo {
+    pub code: String,
+    pub expires_at: u64,
+    /// Address the server advertises, for `omcli connect <url> <code>`
+    pub url: String,
+    /// SHA-256 of the server certificate when it serves TLS
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_fingerprint: Option<String>,
+}
+
+impl EnrollmentInfo {
+    /// The command to run on the machine being enrolled
+    pub fn connect_command(&self) -> String {
+        match &self.tls_fingerprint {
+            Some(fp) => format!("omcli connect {} {} --fingerprint {}", self.url, self.code, fp),
+            None => format!("omcli connect {} {}", self.url, self.code),
+        }
+    }
+}
+
+/// POST /api/enroll body (unauthenticated; the code is the credential)
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct EnrollRequest {
+    pub code: String,
+    /// Name of the connecting machine, used as the key name if the code has none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+/// Events broadcast to CLI WS clients
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 pub struct ClientEvent {
     pub event: String,
     pub device_id: String,
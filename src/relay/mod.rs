@@ -41,9 +41,19 @@ pub async fn relay(port: Option<u16>, bind: Option<String>) {
         .route("/relay/health", get(api::health_handler))
         .with_state(state);
 
+    let tls = relay_config.tls.as_ref().map(|tls_config| {
+        crate::tls::load(tls_config, "relay", std::slice::from_ref(&bind)).unwrap_or_else(|e| {
+            eprintln!("Error: {e}");
+            std::process::exit(1);
+        })
+    });
+
     let addr = format!("{}:{}", bind, port);
     println!("omcli relay v{}", env!("CARGO_PKG_VERSION"));
     println!("Listening on {}", addr);
+    if let Some(tls) = &tls {
+        println!("TLS certificate SHA-256: {}", tls.fingerprint);
+    }
 
     let listener = tokio::net::TcpListener::bind(&addr)
         .await
@@ -51,13 +61,34 @@ pub async fn relay(port: Option<u16>, bind: Option<String>) {
 
     info!("Push relay started on {}", addr);
 
-    axum::serve(listener, app)
-        .with_graceful_shutdown(async {
-            tokio::signal::ctrl_c()
+    match tls {
+        Some(tls) => {
+            let handle = axum_server::Handle::new();
+            let shutdown = handle.clone();
+            tokio::spawn(async move {
+                tokio::signal::ctrl_c()
+                    .await
+                    .expect("Failed to listen for ctrl+c");
+                info!("Shutting down relay...");
+                shutdown.graceful_shutdown(None);
+            });
+            axum_server::from_tcp(listener.into_std().expect("Failed to bind address"))
+                .acceptor(axum_server::tls_openssl::OpenSSLAcceptor::new(tls.config))
+                .handle(handle)
+                .serve(app.into_make_service())
                 .await
-                .expect("Failed to listen for ctrl+c");
-            info!("Shutting down relay...");
-        })
-        .await
-        .expect("Relay server error");
+                .expect("Relay server error");
+        }
+        None => {
+            axum::serve(listener, app)
+                .with_graceful_shutdown(async {
+                    tokio::signal::ctrl_c()
+                        .await
+                        .expect("Failed to listen for ctrl+c");
+                    info!("Shutting down relay...");
+                })
+                .await
+                .expect("Relay server error");
+        }
+    }
 }
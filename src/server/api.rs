@@ -1,120 +1,720 @@
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
+    extract::{ConnectInfo, Path, Query, State},
+    Extension,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
     Json,
 };
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::sync::oneshot;
+use utoipa::IntoParams;
 use uuid::Uuid;
 
 use tracing::info;
 
 use crate::config;
 use crate::protocol::*;
-use crate::server::state::AppState;
+use crate::server::auth::{self, Caller};
+use crate::server::error::ApiError;
+use crate::server::{doctor, enroll, metrics, pairing, queue, reload, scheduler};
+use crate::server::state::{now_secs, AppState, DeviceConnection};
 
+/// Maximum time to wait for a device response over WebSocket
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long `POST /api/devices/{id}/ping` waits for the pong
+const PING_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CommandQuery {
+    /// Return immediately with the command id instead of waiting for the device
+    #[serde(default, rename = "async")]
+    pub async_mode: bool,
+}
+
+#[utoipa::path(
+    post, path = "/api/command", tag = "commands",
+    summary = "Send a command to one device, or to several with `device_ids` or `group`",
+    params(CommandQuery),
+    request_body = CommandRequest,
+    responses(
+        (status = 200, description = "The device's response, or per-device results for a fan-out",
+            body = super::openapi::CommandResult),
+        (status = 202, description = "Accepted in async mode, or queued for an offline device",
+            body = CommandStatus),
+    )
+)]
 pub async fn post_command(
     State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+    Query(query): Query<CommandQuery>,
     Json(req): Json<CommandRequest>,
-) -> Result<Json<CommandResponse>, (StatusCode, String)> {
-    let connections = state.connections.read().await;
+) -> Result<Response, ApiError> {
+    submit_command(&state, &caller, req, &caller.source(), query.async_mode).await
+}
 
-    // Find target device — check connected first
-    let device_id = if let Some(id) = &req.device_id {
-        id.clone()
-    } else {
-        // Try connected devices first
-        let connected: Vec<_> = connections
-            .iter()
-            .filter(|(_, c)| c.authenticated)
-            .collect();
-        match connected.len() {
-            0 => {
-                // No connected devices — try to find a single paired device for APNs fallback
-                let devices = state.devices.read().await;
-                if devices.len() == 1 {
-                    devices.keys().next().unwrap().clone()
-                } else {
-                    drop(devices);
-                    drop(connections);
-                    return Err((StatusCode::NOT_FOUND, "No devices connected".into()));
+/// Track and deliver a command, waiting for the result unless `async_mode` is set.
+async fn submit_command(
+    state: &Arc<AppState>,
+    caller: &Caller,
+    mut req: CommandRequest,
+    source: &str,
+    async_mode: bool,
+) -> Result<Response, ApiError> {
+    caller.check_command(&req.command)?;
+    // The key exchange belongs to pairing, which is admin-only too
+    if req.command == E2E_KEY_COMMAND {
+        caller.require_admin()?;
+    }
+    check_params(&req.command, &req.params, req.custom)?;
+    if req.device_ids.is_some() || req.group.is_some() {
+        return submit_fanout(state, caller, req, source, async_mode).await;
+    }
+
+    // Resolve up front so the key's device scope is checked against the real target
+    let device_id = resolve_device(state, req.device_id.as_deref()).await?;
+    caller.check_device(&device_id)?;
+    req.device_id = Some(device_id);
+
+    let (cmd_id, device_id, status) = start_command(state, &req, source).await?;
+
+    if async_mode {
+        info!("Accepted async command {} ({}) for {}", cmd_id, req.command, device_id);
+        let state = state.clone();
+        tokio::spawn(async move {
+            let _ = dispatch_command(&state, &cmd_id, &device_id, req).await;
+        });
+        return Ok((StatusCode::ACCEPTED, Json(status)).into_response());
+    }
+
+    match dispatch_command(state, &cmd_id, &device_id, req).await? {
+        Some(resp) => Ok(Json(resp).into_response()),
+        // Device is offline and the command was queued — report it like an async submission
+        None => {
+            let status = state.command_status(&cmd_id).await;
+            Ok((StatusCode::ACCEPTED, Json(status)).into_response())
+        }
+    }
+}
+
+/// Send one command to several devices in parallel and collect per-device results.
+async fn submit_fanout(
+    state: &Arc<AppState>,
+    caller: &Caller,
+    req: CommandRequest,
+    source: &str,
+    async_mode: bool,
+) -> Result<Response, ApiError> {
+    if req.id.is_some() {
+        return Err(ApiError::new(
+            ErrorCode::InvalidRequest,
+            "'id' only applies to a command for a single device",
+        ));
+    }
+    let targets = resolve_targets(state, &req).await?;
+    for device_id in &targets {
+        caller.check_device(device_id)?;
+    }
+    info!("Fan-out {} to {} device(s)", req.command, targets.len());
+
+    let mut tasks = tokio::task::JoinSet::new();
+    for (index, device_id) in targets.into_iter().enumerate() {
+        let single = CommandRequest {
+            id: None,
+            command: req.command.clone(),
+            params: req.params.clone(),
+            device_id: Some(device_id.clone()),
+            device_ids: None,
+            group: None,
+            ttl_secs: req.ttl_secs,
+            custom: req.custom,
+        };
+        let state = state.clone();
+        let source = source.to_string();
+        tasks.spawn(async move {
+            let mut result = DeviceResult {
+                device_id: device_id.clone(),
+                response: None,
+                pending: None,
+                error: None,
+            };
+            let (cmd_id, device_id, status) = match start_command(&state, &single, &source).await
+            {
+                Ok(started) => started,
+                Err(e) => {
+                    result.error = Some(e.info());
+                    return (index, result);
                 }
+            };
+            if async_mode {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    let _ = dispatch_command(&state, &cmd_id, &device_id, single).await;
+                });
+                result.pending = Some(status);
+                return (index, result);
             }
-            1 => connected[0].0.clone(),
-            _ => {
-                return Err((
-                    StatusCode::BAD_REQUEST,
-                    "Multiple devices connected, specify --device".into(),
-                ));
+            match dispatch_command(&state, &cmd_id, &device_id, single).await {
+                Ok(Some(resp)) => result.response = Some(resp),
+                Ok(None) => result.pending = state.command_status(&cmd_id).await,
+                Err(e) => result.error = Some(e.info()),
+            }
+            (index, result)
+        });
+    }
+
+    let mut results: Vec<_> = tasks.join_all().await;
+    results.sort_by_key(|(index, _)| *index);
+    let results = results.into_iter().map(|(_, r)| r).collect();
+
+    let code = if async_mode {
+        StatusCode::ACCEPTED
+    } else {
+        StatusCode::OK
+    };
+    Ok((code, Json(FanoutResponse { results })).into_response())
+}
+
+/// Expand `device_ids` / `group` into a de-duplicated list of paired device ids.
+async fn resolve_targets(
+    state: &Arc<AppState>,
+    req: &CommandRequest,
+) -> Result<Vec<String>, ApiError> {
+    let devices = state.devices.read().await;
+    let mut targets: Vec<String> = Vec::new();
+
+    for query in req.device_ids.iter().flatten() {
+        let id = find_device(&devices, query)?;
+        if !targets.contains(&id) {
+            targets.push(id);
+        }
+    }
+
+    if let Some(group) = &req.group {
+        let mut members: Vec<_> = devices
+            .values()
+            .filter(|d| d.groups.contains(group))
+            .collect();
+        if members.is_empty() {
+            return Err(ApiError::new(
+                ErrorCode::GroupNotFound,
+                format!("No devices in group '{}'", group),
+            ));
+        }
+        members.sort_by_key(|d| d.paired_at);
+        for d in members {
+            if !targets.contains(&d.id) {
+                targets.push(d.id.clone());
             }
         }
+    }
+
+    if targets.is_empty() {
+        return Err(ApiError::new(
+            ErrorCode::InvalidRequest,
+            "No target devices given",
+        ));
+    }
+    Ok(targets)
+}
+
+/// Resolve the target device and register the command in the `queued` state.
+pub(crate) async fn start_command(
+    state: &Arc<AppState>,
+    req: &CommandRequest,
+    source: &str,
+) -> Result<(String, String, CommandStatus), ApiError> {
+    let device_id = resolve_device(state, req.device_id.as_deref()).await?;
+    check_supported(state, &device_id, &req.command).await?;
+    let cmd_id = match &req.id {
+        Some(id) => {
+            check_client_id("Command", id, state.commands.read().await.contains_key(id))?;
+            id.clone()
+        }
+        None => Uuid::new_v4().to_string(),
+    };
+    let status = state.track_command(&cmd_id, &device_id, req, source).await;
+    Ok((cmd_id, device_id, status))
+}
+
+/// A command or schedule id picked by the client: short, URL-safe and not in use.
+fn check_client_id(kind: &str, id: &str, taken: bool) -> Result<(), ApiError> {
+    let valid = !id.is_empty()
+        && id.len() <= 64
+        && id.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+    if !valid {
+        return Err(ApiError::new(
+            ErrorCode::InvalidRequest,
+            format!("{kind} id must be 1 to 64 letters, digits, '-' or '_'"),
+        ));
+    }
+    if taken {
+        return Err(ApiError::new(
+            ErrorCode::InvalidRequest,
+            format!("{kind} id {id} is already in use"),
+        ));
+    }
+    Ok(())
+}
+
+/// Refuse a command the device did not list in its hello, before it is tracked or queued.
+async fn check_supported(state: &AppState, device_id: &str, command: &str) -> Result<(), ApiError> {
+    let devices = state.devices.read().await;
+    let Some(device) = devices.get(device_id) else {
+        return Ok(());
     };
+    if device.metadata.supports(command) {
+        return Ok(());
+    }
+    Err(ApiError::new(
+        ErrorCode::CommandNotSupported,
+        format!("{} does not support {}", device.name, command),
+    )
+    .with_details(serde_json::json!({
+        "device_id": device_id,
+        "supported": device.metadata.commands,
+    })))
+}
+
+/// Refuse a command that is not in the catalog, or whose params do not match it,
+/// unless the caller asked for a custom passthrough.
+fn check_params(name: &str, params: &serde_json::Value, custom: bool) -> Result<(), ApiError> {
+    if custom {
+        return Ok(());
+    }
+    let spec = command::find(name).ok_or_else(|| {
+        ApiError::new(
+            ErrorCode::UnknownCommand,
+            format!("Unknown command {name}; send it with \"custom\": true to pass it through"),
+        )
+        .with_details(serde_json::json!({
+            "commands": command::COMMANDS.iter().map(|c| c.name).collect::<Vec<_>>(),
+        }))
+    })?;
+    spec.check(params).map_err(ApiError::from)
+}
+
+/// GET /api/commands/schema — JSON Schema of the params of every known command.
+#[utoipa::path(
+    get, path = "/api/commands/schema", tag = "commands",
+    summary = "JSON Schema of the params of every known command, keyed by command name",
+    responses((status = 200, body = HashMap<String, serde_json::Value>))
+)]
+pub async fn command_schema() -> Json<serde_json::Value> {
+    let schemas: serde_json::Map<_, _> = command::COMMANDS
+        .iter()
+        .map(|c| (c.name.to_string(), c.json_schema()))
+        .collect();
+    Json(serde_json::Value::Object(schemas))
+}
+
+#[utoipa::path(
+    get, path = "/api/commands/{id}", tag = "commands",
+    summary = "State of a recent command",
+    params(("id" = String, Path, description = "Command id")),
+    responses((status = 200, body = CommandStatus))
+)]
+pub async fn get_command(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+    Path(id): Path<String>,
+) -> Result<Json<CommandStatus>, ApiError> {
+    state
+        .command_status(&id)
+        .await
+        .filter(|s| caller.can_see_device(&s.device_id))
+        .map(Json)
+        .ok_or_else(|| ApiError::new(ErrorCode::CommandNotFound, format!("Command {} not found", id)))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct HistoryQuery {
+    /// Device id, alias or name prefix
+    pub device: Option<String>,
+    /// Command name, or a family like `alarm`
+    pub command: Option<String>,
+    /// Unix seconds
+    pub since: Option<u64>,
+    /// Most entries to return
+    #[serde(default = "default_history_limit")]
+    #[param(default = 50)]
+    pub limit: usize,
+}
+
+fn default_history_limit() -> usize {
+    50
+}
+
+/// GET /api/commands — finished commands from the history log, newest first.
+#[utoipa::path(
+    get, path = "/api/commands", tag = "commands",
+    summary = "Finished commands from the history log, newest first",
+    params(HistoryQuery),
+    responses((status = 200, body = Vec<HistoryEntry>))
+)]
+pub async fn list_commands(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+    Query(query): Query<HistoryQuery>,
+) -> Json<Vec<HistoryEntry>> {
+    // Removed devices still have history, so fall back to the raw id
+    let device = match query.device {
+        Some(d) => Some(find_device(&*state.devices.read().await, &d).unwrap_or(d)),
+        None => None,
+    };
+    let entries = state
+        .history
+        .read()
+        .await
+        .iter()
+        .rev()
+        .filter(|e| caller.can_see_device(&e.device_id))
+        .filter(|e| device.as_ref().is_none_or(|d| &e.device_id == d))
+        .filter(|e| {
+            query.command.as_ref().is_none_or(|c| {
+                // `alarm` matches every `alarm.*` command
+                &e.command == c || e.command.starts_with(&format!("{c}."))
+            })
+        })
+        .filter(|e| query.since.is_none_or(|t| e.created_at >= t))
+        .take(query.limit)
+        .cloned()
+        .collect();
+    Json(entries)
+}
+
+/// POST /api/commands/{id}/replay — resend a command from the history log.
+#[utoipa::path(
+    post, path = "/api/commands/{id}/replay", tag = "commands",
+    summary = "Resend a command from the history log",
+    params(("id" = String, Path, description = "Command id"), CommandQuery),
+    responses(
+        (status = 200, description = "The device's response", body = CommandResponse),
+        (status = 202, description = "Accepted in async mode, or queued", body = CommandStatus),
+    )
+)]
+pub async fn replay_command(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+    Path(id): Path<String>,
+    Query(query): Query<CommandQuery>,
+) -> Result<Response, ApiError> {
+    let entry = state
+        .history
+        .read()
+        .await
+        .iter()
+        .rfind(|e| e.id == id && caller.can_see_device(&e.device_id))
+        .cloned()
+        .ok_or_else(|| {
+            ApiError::new(
+                ErrorCode::CommandNotFound,
+                format!("Command {} not found in history", id),
+            )
+        })?;
+
+    if is_sealed(&entry.params) {
+        // The device only opens them for the command id they were sealed for
+        return Err(ApiError::new(
+            ErrorCode::InvalidRequest,
+            format!("Command {id} was end-to-end encrypted and cannot be replayed; send it again"),
+        ));
+    }
 
+    info!("Replaying command {} ({})", id, entry.command);
+    let req = CommandRequest {
+        id: None,
+        command: entry.command,
+        params: entry.params,
+        device_id: Some(entry.device_id),
+        device_ids: None,
+        group: None,
+        ttl_secs: None,
+        // It was checked when first sent, and the catalog may have changed since
+        custom: true,
+    };
+    submit_command(&state, &caller, req, &format!("replay:{id}"), query.async_mode).await
+}
+
+/// Pick the target device: explicit id, the single connected device, or the single paired one.
+pub(crate) async fn resolve_device(
+    state: &Arc<AppState>,
+    device_id: Option<&str>,
+) -> Result<String, ApiError> {
+    if let Some(query) = device_id {
+        return find_device(&*state.devices.read().await, query);
+    }
+
+    // A default device always wins
+    if let Some(d) = state.devices.read().await.values().find(|d| d.is_default) {
+        return Ok(d.id.clone());
+    }
+
+    // Try connected devices first
+    let connections = state.connections.read().await;
+    let connected: Vec<_> = connections
+        .iter()
+        .filter(|(_, c)| c.authenticated)
+        .collect();
+    match connected.len() {
+        0 => {
+            // No connected devices — try to find a single paired device for APNs fallback
+            let devices = state.devices.read().await;
+            if devices.len() == 1 {
+                Ok(devices.keys().next().unwrap().clone())
+            } else {
+                Err(ApiError::new(ErrorCode::DeviceOffline, "No devices connected"))
+            }
+        }
+        1 => Ok(connected[0].0.clone()),
+        _ => Err(ApiError::new(
+            ErrorCode::DeviceAmbiguous,
+            "Multiple devices connected, specify --device or set a default device",
+        )
+        .with_details(serde_json::json!({
+            "connected": connected.iter().map(|(id, _)| id.as_str()).collect::<Vec<_>>(),
+        }))),
+    }
+}
+
+/// Match a device by exact id, alias, or unique case-insensitive name prefix.
+pub(crate) fn find_device(
+    devices: &HashMap<String, Device>,
+    query: &str,
+) -> Result<String, ApiError> {
+    // Would prefix-match every name
+    if query.trim().is_empty() {
+        return Err(ApiError::new(
+            ErrorCode::InvalidRequest,
+            "Device id, alias or name cannot be empty",
+        ));
+    }
+    if devices.contains_key(query) {
+        return Ok(query.to_string());
+    }
+    if let Some(d) = devices.values().find(|d| d.alias.as_deref() == Some(query)) {
+        return Ok(d.id.clone());
+    }
+
+    let needle = query.to_lowercase();
+    let matches: Vec<_> = devices
+        .values()
+        .filter(|d| d.name.to_lowercase().starts_with(&needle))
+        .collect();
+    match matches.len() {
+        1 => Ok(matches[0].id.clone()),
+        0 => Err(ApiError::new(
+            ErrorCode::DeviceNotFound,
+            format!("Device {} not found", query),
+        )),
+        _ => {
+            let names: Vec<_> = matches.iter().map(|d| d.name.as_str()).collect();
+            let ids: Vec<_> = matches.iter().map(|d| d.id.as_str()).collect();
+            Err(ApiError::new(
+                ErrorCode::DeviceAmbiguous,
+                format!("'{}' matches several devices: {}", query, names.join(", ")),
+            )
+            .with_details(serde_json::json!({ "matches": ids })))
+        }
+    }
+}
+
+fn device_info(device: &Device, conn: Option<&DeviceConnection>) -> DeviceInfo {
+    let online = conn.is_some_and(|c| c.authenticated);
+    DeviceInfo {
+        id: device.id.clone(),
+        name: device.name.clone(),
+        online,
+        paired_at: device.paired_at,
+        groups: device.groups.clone(),
+        alias: device.alias.clone(),
+        is_default: device.is_default,
+        connected: conn.is_some(),
+        connected_since: conn.map(|c| c.connected_since),
+        last_seen: match conn.filter(|c| c.authenticated) {
+            Some(c) => Some(c.presence.last_seen()),
+            None => device.last_seen,
+        },
+        remote_addr: conn.map(|c| c.remote_addr.to_string()),
+        rtt_ms: conn.and_then(|c| c.presence.rtt_ms()),
+        has_push_token: device.push_token.is_some(),
+        has_voip_token: device.voip_token.is_some(),
+        revoked_at: device.revoked_at,
+        e2e: device.e2e_public_key.is_some(),
+        metadata: device.metadata.clone(),
+    }
+}
+
+/// Deliver a tracked command and record its final state.
+///
+/// Returns `Ok(None)` when the device is offline and the command was queued for later delivery.
+pub(crate) async fn dispatch_command(
+    state: &Arc<AppState>,
+    cmd_id: &str,
+    device_id: &str,
+    req: CommandRequest,
+) -> Result<Option<CommandResponse>, ApiError> {
+    let result = deliver_command(state, cmd_id, device_id, req).await;
+
+    match &result {
+        Ok(None) => {}
+        Ok(Some(resp)) => {
+            if let Some(via) = resp
+                .data
+                .as_ref()
+                .and_then(|d| d.get("delivered_via"))
+                .and_then(|v| v.as_str())
+            {
+                state.set_delivery(cmd_id, via).await;
+            }
+            let final_state = if resp.status != "ok" {
+                CommandState::Failed
+            } else if resp.data.as_ref().and_then(|d| d.get("delivered_via")).is_some() {
+                CommandState::Acknowledged
+            } else {
+                CommandState::Completed
+            };
+            state
+                .update_command(cmd_id, final_state, Some(resp.clone()))
+                .await;
+        }
+        Err(err) => {
+            let final_state = if err.code == ErrorCode::Timeout {
+                CommandState::Expired
+            } else {
+                CommandState::Failed
+            };
+            let resp = CommandResponse {
+                id: cmd_id.to_string(),
+                status: "error".into(),
+                data: None,
+                error: Some(err.message.clone()),
+                error_code: Some(err.code.to_string()),
+            };
+            state.update_command(cmd_id, final_state, Some(resp)).await;
+        }
+    }
+
+    result
+}
+
+async fn deliver_command(
+    state: &Arc<AppState>,
+    cmd_id: &str,
+    device_id: &str,
+    req: CommandRequest,
+) -> Result<Option<CommandResponse>, ApiError> {
     // Check if device is connected and authenticated
-    let is_connected = connections
-        .get(&device_id)
+    let is_connected = state
+        .connections
+        .read()
+        .await
+        .get(device_id)
         .map(|c| c.authenticated)
         .unwrap_or(false);
 
-    // If device is not connected, try APNs fallback for alarm/sleep commands only
+    // If device is not connected, try APNs fallback for alarm/sleep/notify commands,
+    // everything else waits in the offline queue
     if !is_connected {
-        drop(connections);
-        info!("Device {} offline, attempting push fallback for {}", device_id, req.command);
-        if req.command.starts_with("alarm.") || req.command.starts_with("sleep.") || req.command.starts_with("notify.") {
-            return try_apns_fallback(&state, &device_id, &req.command, &req.params).await;
-        }
-        return Err((
-            StatusCode::CONFLICT,
-            format!("Device {} is not connected. Open the app and try again.", device_id),
-        ));
+        match state.devices.read().await.get(device_id) {
+            None => {
+                return Err(ApiError::new(
+                    ErrorCode::DeviceNotFound,
+                    format!("Device {} not found", device_id),
+                ))
+            }
+            Some(d) if d.revoked_at.is_some() => {
+                return Err(ApiError::new(
+                    ErrorCode::DeviceRevoked,
+                    format!("Device {} was revoked and must pair again", device_id),
+                ))
+            }
+            Some(_) => {}
+        }
+        // A sealed notification can't be turned into a push the server can't read
+        let pushable = req.command.starts_with("alarm.")
+            || req.command.starts_with("sleep.")
+            || (req.command.starts_with("notify.") && !is_sealed(&req.params));
+        if pushable {
+            info!("Device {} offline, attempting push fallback for {}", device_id, req.command);
+            return try_apns_fallback(state, cmd_id, device_id, &req.command, &req.params)
+                .await
+                .map(Some);
+        }
+        let queued_at = now_secs();
+        let source = state
+            .command_status(cmd_id)
+            .await
+            .map(|s| s.source)
+            .unwrap_or_else(|| "api".into());
+        let cmd = QueuedCommand {
+            id: cmd_id.to_string(),
+            command: req.command,
+            params: req.params,
+            source,
+            queued_at,
+            expires_at: req.ttl_secs.map(|ttl| queued_at + ttl),
+        };
+        queue::enqueue(state, device_id, cmd).await;
+        // The device may have authenticated since the check above, with its
+        // reconnect flush already done; deliver now rather than next time
+        if state
+            .connections
+            .read()
+            .await
+            .get(device_id)
+            .is_some_and(|c| c.authenticated)
+        {
+            queue::flush(state, device_id).await;
+        }
+        return Ok(None);
     }
 
     info!("Device {} online, sending {} via WebSocket", device_id, req.command);
-    let cmd_id = Uuid::new_v4().to_string();
     let server_msg = ServerMessage::Command {
-        id: cmd_id.clone(),
-        command: req.command,
+        id: cmd_id.to_string(),
+        command: req.command.clone(),
         params: req.params,
     };
 
     // Create oneshot channel for response
     let (tx, rx) = oneshot::channel();
-    drop(connections); // release read lock before write lock
     state
         .pending_commands
         .write()
         .await
-        .insert(cmd_id.clone(), tx);
+        .insert(cmd_id.to_string(), tx);
 
     // Send command to device
+    state.update_command(cmd_id, CommandState::Sent, None).await;
+    state.set_delivery(cmd_id, "websocket").await;
     {
         let connections = state.connections.read().await;
-        if let Some(conn) = connections.get(&device_id) {
-            conn.tx.send(server_msg).map_err(|_| {
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "Failed to send to device".into(),
-                )
-            })?;
+        if let Some(conn) = connections.get(device_id) {
+            conn.tx
+                .send(server_msg)
+                .map_err(|_| ApiError::new(ErrorCode::Internal, "Failed to send to device"))?;
         }
     }
 
-    // Wait for response with timeout (30s)
-    match tokio::time::timeout(std::time::Duration::from_secs(30), rx).await {
-        Ok(Ok(resp)) => Ok(Json(resp)),
+    // Wait for response with timeout
+    match tokio::time::timeout(COMMAND_TIMEOUT, rx).await {
+        Ok(Ok(resp)) => Ok(Some(resp)),
         Ok(Err(_)) => {
-            state.pending_commands.write().await.remove(&cmd_id);
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Response channel closed".into(),
-            ))
+            state.pending_commands.write().await.remove(cmd_id);
+            Err(ApiError::new(ErrorCode::Internal, "Response channel closed"))
         }
         Err(_) => {
-            state.pending_commands.write().await.remove(&cmd_id);
-            Err((
-                StatusCode::GATEWAY_TIMEOUT,
-                "Device did not respond in time".into(),
+            state.pending_commands.write().await.remove(cmd_id);
+            state.metrics.command_timed_out(&req.command);
+            Err(ApiError::new(
+                ErrorCode::Timeout,
+                "Device did not respond in time",
             ))
         }
     }
@@ -122,22 +722,29 @@ pub async fn post_command(
 
 async fn try_apns_fallback(
     state: &Arc<AppState>,
+    cmd_id: &str,
     device_id: &str,
     command: &str,
     params: &serde_json::Value,
-) -> Result<Json<CommandResponse>, (StatusCode, String)> {
+) -> Result<CommandResponse, ApiError> {
+    let settings = state.settings().await;
+
     // If local APNs is configured, use it directly
-    if let Some(apns) = state.apns.as_ref() {
-        return try_local_apns(apns, state, device_id, command, params).await;
+    if let Some(apns) = settings.apns.as_ref() {
+        let result = try_local_apns(apns, state, cmd_id, device_id, command, params).await;
+        record_push(state, "apns", command, &result);
+        return result;
     }
 
     // Otherwise fall back to relay
-    if let Some(relay_url) = state.relay_url.as_ref() {
-        return send_via_relay(relay_url, state, device_id, command, params).await;
+    if let Some(relay_url) = settings.relay_url() {
+        let result = send_via_relay(relay_url, state, cmd_id, device_id, command, params).await;
+        record_push(state, "relay", command, &result);
+        return result;
     }
 
-    Err((
-        StatusCode::NOT_FOUND,
+    Err(ApiError::new(
+        ErrorCode::PushNotConfigured,
         format!(
             "Device {} not connected and no push configured (set [apns] or relay_url)",
             device_id
@@ -145,40 +752,60 @@ async fn try_apns_fallback(
     ))
 }
 
+/// Count a push that was handed to the provider; a device without a token
+/// never got that far.
+fn record_push(
+    state: &AppState,
+    provider: &'static str,
+    command: &str,
+    result: &Result<CommandResponse, ApiError>,
+) {
+    match result {
+        Ok(_) => state.metrics.push(provider, command, true),
+        Err(e) if e.code == ErrorCode::PushFailed => state.metrics.push(provider, command, false),
+        Err(_) => {}
+    }
+}
+
 async fn try_local_apns(
     apns: &crate::server::apns::ApnsClient,
     state: &Arc<AppState>,
+    cmd_id: &str,
     device_id: &str,
     command: &str,
     params: &serde_json::Value,
-) -> Result<Json<CommandResponse>, (StatusCode, String)> {
+) -> Result<CommandResponse, ApiError> {
     let devices = state.devices.read().await;
-    let device = devices.get(device_id).ok_or((
-        StatusCode::NOT_FOUND,
-        format!("Device {} not found", device_id),
-    ))?;
+    let device = devices.get(device_id).ok_or_else(|| {
+        ApiError::new(
+            ErrorCode::DeviceNotFound,
+            format!("Device {} not found", device_id),
+        )
+    })?;
 
     // Notify commands: send a visible APNs alert
     if command.starts_with("notify.") {
-        let push_token = device.push_token.as_ref().ok_or((
-            StatusCode::BAD_REQUEST,
-            format!("Device {} has no push token registered", device_id),
-        ))?;
+        let push_token = device.push_token.as_ref().ok_or_else(|| {
+            ApiError::new(
+                ErrorCode::NoPushToken,
+                format!("Device {} has no push token registered", device_id),
+            )
+        })?;
         info!("Sending notify push to device {} (token {}...)", device_id, &push_token[..8]);
         let push_token = push_token.clone();
         drop(devices);
 
         apns.send_notify_push(&push_token, params)
             .await
-            .map_err(|e| (StatusCode::BAD_GATEWAY, e))?;
+            .map_err(|e| ApiError::new(ErrorCode::PushFailed, e))?;
 
-        return Ok(Json(CommandResponse {
-            id: Uuid::new_v4().to_string(),
+        return Ok(CommandResponse {
+            id: cmd_id.to_string(),
             status: "ok".into(),
             data: Some(serde_json::json!({"delivered_via": "apns"})),
             error: None,
             error_code: None,
-        }));
+        });
     }
 
     // Prefer VoIP push for alarm.start (bypasses DND via CallKit)
@@ -190,26 +817,28 @@ async fn try_local_apns(
 
             apns.send_voip_push(&voip_token, command, params)
                 .await
-                .map_err(|e| (StatusCode::BAD_GATEWAY, e))?;
+                .map_err(|e| ApiError::new(ErrorCode::PushFailed, e))?;
 
             info!("VoIP push sent successfully");
-            return Ok(Json(CommandResponse {
-                id: Uuid::new_v4().to_string(),
+            return Ok(CommandResponse {
+                id: cmd_id.to_string(),
                 status: "ok".into(),
                 data: Some(serde_json::json!({"delivered_via": "voip"})),
                 error: None,
                 error_code: None,
-            }));
+            });
         }
     }
 
-    let push_token = device.push_token.as_ref().ok_or((
-        StatusCode::BAD_REQUEST,
-        format!(
-            "Device {} not connected and has no push token registered",
-            device_id
-        ),
-    ))?;
+    let push_token = device.push_token.as_ref().ok_or_else(|| {
+        ApiError::new(
+            ErrorCode::NoPushToken,
+            format!(
+                "Device {} not connected and has no push token registered",
+                device_id
+            ),
+        )
+    })?;
 
     let push_token = push_token.clone();
     drop(devices);
@@ -217,29 +846,32 @@ async fn try_local_apns(
     info!("Sending regular APNs push to device {} (token {}...)", device_id, &push_token[..8]);
     apns.send_alarm_push(&push_token, command, params)
         .await
-        .map_err(|e| (StatusCode::BAD_GATEWAY, e))?;
+        .map_err(|e| ApiError::new(ErrorCode::PushFailed, e))?;
 
-    Ok(Json(CommandResponse {
-        id: Uuid::new_v4().to_string(),
+    Ok(CommandResponse {
+        id: cmd_id.to_string(),
         status: "ok".into(),
         data: Some(serde_json::json!({"delivered_via": "apns"})),
         error: None,
         error_code: None,
-    }))
+    })
 }
 
 async fn send_via_relay(
     relay_url: &str,
     state: &Arc<AppState>,
+    cmd_id: &str,
     device_id: &str,
     command: &str,
     params: &serde_json::Value,
-) -> Result<Json<CommandResponse>, (StatusCode, String)> {
+) -> Result<CommandResponse, ApiError> {
     let devices = state.devices.read().await;
-    let device = devices.get(device_id).ok_or((
-        StatusCode::NOT_FOUND,
-        format!("Device {} not found", device_id),
-    ))?;
+    let device = devices.get(device_id).ok_or_else(|| {
+        ApiError::new(
+            ErrorCode::DeviceNotFound,
+            format!("Device {} not found", device_id),
+        )
+    })?;
 
     let client = reqwest::Client::new();
 
@@ -262,20 +894,25 @@ async fn send_via_relay(
                 }))
                 .send()
                 .await
-                .map_err(|e| (StatusCode::BAD_GATEWAY, format!("Relay request failed: {e}")))?;
+                .map_err(|e| {
+                    ApiError::new(ErrorCode::PushFailed, format!("Relay request failed: {e}"))
+                })?;
 
             if !resp.status().is_success() {
                 let body = resp.text().await.unwrap_or_default();
-                return Err((StatusCode::BAD_GATEWAY, format!("Relay error: {body}")));
+                return Err(ApiError::new(
+                    ErrorCode::PushFailed,
+                    format!("Relay error: {body}"),
+                ));
             }
 
-            return Ok(Json(CommandResponse {
-                id: Uuid::new_v4().to_string(),
+            return Ok(CommandResponse {
+                id: cmd_id.to_string(),
                 status: "ok".into(),
                 data: Some(serde_json::json!({"delivered_via": "relay_voip"})),
                 error: None,
                 error_code: None,
-            }));
+            });
         }
     }
 
@@ -293,10 +930,12 @@ async fn send_via_relay(
         )
     };
 
-    let push_token = device.push_token.as_ref().ok_or((
-        StatusCode::BAD_REQUEST,
-        format!("Device {} has no push token registered", device_id),
-    ))?;
+    let push_token = device.push_token.as_ref().ok_or_else(|| {
+        ApiError::new(
+            ErrorCode::NoPushToken,
+            format!("Device {} has no push token registered", device_id),
+        )
+    })?;
 
     info!("Relay push to device {} via {}", device_id, relay_url);
     let push_token = push_token.clone();
@@ -311,61 +950,345 @@ async fn send_via_relay(
         }))
         .send()
         .await
-        .map_err(|e| (StatusCode::BAD_GATEWAY, format!("Relay request failed: {e}")))?;
+        .map_err(|e| ApiError::new(ErrorCode::PushFailed, format!("Relay request failed: {e}")))?;
 
     if !resp.status().is_success() {
         let body = resp.text().await.unwrap_or_default();
-        return Err((StatusCode::BAD_GATEWAY, format!("Relay error: {body}")));
+        return Err(ApiError::new(
+            ErrorCode::PushFailed,
+            format!("Relay error: {body}"),
+        ));
     }
 
-    Ok(Json(CommandResponse {
-        id: Uuid::new_v4().to_string(),
+    Ok(CommandResponse {
+        id: cmd_id.to_string(),
         status: "ok".into(),
         data: Some(serde_json::json!({"delivered_via": "relay"})),
         error: None,
         error_code: None,
-    }))
+    })
+}
+
+#[utoipa::path(
+    get, path = "/api/schedules", tag = "schedules",
+    summary = "Schedules visible to the key",
+    responses((status = 200, body = Vec<Schedule>))
+)]
+pub async fn list_schedules(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+) -> Json<Vec<Schedule>> {
+    let mut list: Vec<_> = state
+        .schedules
+        .read()
+        .await
+        .values()
+        .filter(|s| schedule_visible(&caller, s))
+        .cloned()
+        .collect();
+    list.sort_by_key(|s| s.next_run.unwrap_or(u64::MAX));
+    Json(list)
+}
+
+/// Schedules without a device are only visible to keys allowed every device.
+fn schedule_visible(caller: &Caller, schedule: &Schedule) -> bool {
+    match &schedule.device_id {
+        Some(id) => caller.can_see_device(id),
+        None => caller.all_devices(),
+    }
+}
+
+#[utoipa::path(
+    post, path = "/api/schedules", tag = "schedules",
+    summary = "Schedule a command once (`at`) or repeatedly (`cron`)",
+    request_body = ScheduleRequest,
+    responses((status = 200, body = Schedule))
+)]
+pub async fn create_schedule(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+    Json(mut req): Json<ScheduleRequest>,
+) -> Result<Json<Schedule>, ApiError> {
+    caller.check_command(&req.command)?;
+    check_params(&req.command, &req.params, req.custom)?;
+    if !caller.all_devices() {
+        // Device-scoped keys pin the target now instead of whatever is default at run time
+        let device_id = resolve_device(&state, req.device_id.as_deref()).await?;
+        caller.check_device(&device_id)?;
+        req.device_id = Some(device_id);
+    }
+
+    let now = now_secs();
+    let next_run = match (&req.at, &req.cron) {
+        (Some(at), None) => {
+            if *at <= now {
+                return Err(ApiError::new(
+                    ErrorCode::InvalidRequest,
+                    "Scheduled time is in the past",
+                ));
+            }
+            *at
+        }
+        (None, Some(expr)) => {
+            scheduler::next_cron_run(expr, now)
+                .map_err(|e| ApiError::new(ErrorCode::InvalidRequest, e))?
+        }
+        // First run at `at`, then every time the cron fires
+        (Some(at), Some(expr)) => {
+            scheduler::parse_cron(expr).map_err(|e| ApiError::new(ErrorCode::InvalidRequest, e))?;
+            if *at <= now {
+                return Err(ApiError::new(
+                    ErrorCode::InvalidRequest,
+                    "Scheduled time is in the past",
+                ));
+            }
+            *at
+        }
+        (None, None) => {
+            return Err(ApiError::new(
+                ErrorCode::InvalidRequest,
+                "Specify 'at', 'cron' or both",
+            ));
+        }
+    };
+
+    let id = match req.id {
+        Some(id) => {
+            check_client_id("Schedule", &id, state.schedules.read().await.contains_key(&id))?;
+            id
+        }
+        None => Uuid::new_v4().to_string(),
+    };
+    // Every run sends the same envelope, which the device opens for this id only
+    if let Some(envelope) = SealedEnvelope::from_value(&req.params) {
+        if envelope.schedule.as_deref() != Some(id.as_str()) {
+            return Err(ApiError::new(
+                ErrorCode::InvalidRequest,
+                "Sealed params of a schedule must be sealed for its id",
+            ));
+        }
+    }
+    let schedule = Schedule {
+        id,
+        command: req.command,
+        params: req.params,
+        device_id: req.device_id,
+        at: req.at,
+        cron: req.cron,
+        created_at: now,
+        next_run: Some(next_run),
+        last_run: None,
+        key_id: caller.key_id().map(String::from),
+    };
+
+    let mut schedules = state.schedules.write().await;
+    schedules.insert(schedule.id.clone(), schedule.clone());
+    if let Err(e) = scheduler::save(&schedules) {
+        schedules.remove(&schedule.id);
+        return Err(ApiError::new(ErrorCode::Internal, e));
+    }
+    info!("Created schedule {} for {}", schedule.id, schedule.command);
+
+    Ok(Json(schedule))
+}
+
+#[utoipa::path(
+    delete, path = "/api/schedules/{id}", tag = "schedules",
+    summary = "Remove a schedule",
+    params(("id" = String, Path, description = "Schedule id")),
+    responses((status = 204, description = "Removed"))
+)]
+pub async fn delete_schedule(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    let mut schedules = state.schedules.write().await;
+    let Some(schedule) = schedules.get(&id).filter(|s| schedule_visible(&caller, s)) else {
+        return Err(ApiError::new(
+            ErrorCode::ScheduleNotFound,
+            format!("Schedule {} not found", id),
+        ));
+    };
+    caller.check_command(&schedule.command)?;
+    let schedule = schedule.clone();
+    schedules.remove(&id);
+    if let Err(e) = scheduler::save(&schedules) {
+        schedules.insert(id, schedule);
+        return Err(ApiError::new(ErrorCode::Internal, e));
+    }
+    Ok(StatusCode::NO_CONTENT)
 }
 
+#[utoipa::path(
+    get, path = "/api/status", tag = "server",
+    summary = "Server version, uptime and device counts",
+    responses((status = 200, body = ServerStatus))
+)]
 pub async fn get_status(State(state): State<Arc<AppState>>) -> Json<ServerStatus> {
     let connections = state.connections.read().await;
     let devices = state.devices.read().await;
     let online = connections.iter().filter(|(_, c)| c.authenticated).count();
     Json(ServerStatus {
         version: env!("CARGO_PKG_VERSION").to_string(),
+        protocol_version: PROTOCOL_VERSION,
         uptime_secs: state.start_time.elapsed().as_secs(),
         devices_online: online,
         devices_total: devices.len(),
     })
 }
 
-pub async fn get_devices(State(state): State<Arc<AppState>>) -> Json<Vec<DeviceInfo>> {
-    let devices = state.devices.read().await;
+#[utoipa::path(
+    get, path = "/api/doctor", tag = "server",
+    summary = "Push, device and mDNS checks only the running server can make (admin)",
+    responses((status = 200, body = Vec<DoctorCheck>))
+)]
+pub async fn get_doctor(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+) -> Result<Json<Vec<DoctorCheck>>, ApiError> {
+    caller.require_admin()?;
+    Ok(Json(doctor::server_checks(&state).await))
+}
+
+#[utoipa::path(
+    post, path = "/api/admin/reload", tag = "server",
+    summary = "Re-read config.toml without restarting (admin)",
+    responses((status = 200, body = ReloadResponse))
+)]
+pub async fn reload_config(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+) -> Result<Json<ReloadResponse>, ApiError> {
+    caller.require_admin()?;
+    Ok(Json(reload::reload(&state).await?))
+}
+
+/// GET /metrics — Prometheus text format, for any key not limited to some
+/// devices, so a read-only key can scrape it.
+#[utoipa::path(
+    get, path = "/metrics", tag = "server",
+    summary = "Prometheus metrics: devices, commands, latency, pushes, pairing",
+    responses((
+        status = 200, body = String, content_type = "text/plain",
+        description = "Prometheus text exposition format"
+    ))
+)]
+pub async fn get_metrics(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+) -> Result<impl IntoResponse, ApiError> {
+    caller.require_all_devices()?;
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")],
+        metrics::render(&state).await,
+    ))
+}
+
+#[utoipa::path(
+    get, path = "/api/devices", tag = "devices",
+    summary = "Paired devices visible to the key",
+    responses((status = 200, body = Vec<DeviceInfo>))
+)]
+pub async fn get_devices(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+) -> Json<Vec<DeviceInfo>> {
     let connections = state.connections.read().await;
+    let devices = state.devices.read().await;
     let list = devices
         .values()
-        .map(|d| {
-            let online = connections
-                .get(&d.id)
-                .map(|c| c.authenticated)
-                .unwrap_or(false);
-            DeviceInfo {
-                id: d.id.clone(),
-                name: d.name.clone(),
-                online,
-                paired_at: d.paired_at,
-            }
-        })
+        .filter(|d| caller.can_see_device(&d.id))
+        .map(|d| device_info(d, connections.get(&d.id)))
         .collect();
     Json(list)
 }
 
+#[utoipa::path(
+    post, path = "/api/devices/pair", tag = "devices",
+    summary = "Pair the device showing this code (admin)",
+    request_body = PairRequest,
+    responses((status = 200, body = PairResponse))
+)]
 pub async fn pair_device(
     State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
     Json(req): Json<PairRequest>,
-) -> Result<Json<PairResponse>, (StatusCode, String)> {
-    let pending = state.pending_pairings.write().await.remove(&req.code);
-    let pending = pending.ok_or((StatusCode::NOT_FOUND, "Invalid pairing code".into()))?;
+) -> Result<Json<PairResponse>, ApiError> {
+    caller.require_admin()?;
+    let result = pair(&state, addr, req).await;
+    let outcome = match &result {
+        Ok(_) => "paired",
+        Err(e) => e.code.as_str(),
+    };
+    state.metrics.pairing_attempt(outcome);
+    result.map(Json)
+}
+
+async fn pair(
+    state: &Arc<AppState>,
+    addr: SocketAddr,
+    req: PairRequest,
+) -> Result<PairResponse, ApiError> {
+    pairing::check_client(state, addr.ip()).await?;
+
+    let pending = {
+        let mut pairings = state.pending_pairings.write().await;
+        match pairings.get(&req.code) {
+            Some(p) if p.expires_at > now_secs() => {
+                if p.replaces_existing && !req.confirm {
+                    let paired_name = state
+                        .devices
+                        .read()
+                        .await
+                        .get(&p.device_id)
+                        .map(|d| d.name.clone())
+                        .unwrap_or_else(|| p.name.clone());
+                    return Err(ApiError::new(
+                        ErrorCode::PairingConfirmationRequired,
+                        format!(
+                            "{} ({}) is already paired but this connection failed to \
+                             authenticate as it. Pairing replaces its token; confirm only if \
+                             you are holding that device",
+                            paired_name, p.device_id
+                        ),
+                    )
+                    .with_details(serde_json::json!({
+                        "device_id": p.device_id,
+                        "paired_name": paired_name,
+                        "name": p.name,
+                    })));
+                }
+                pairings.remove(&req.code)
+            }
+            _ => None,
+        }
+    };
+    let Some(pending) = pending else {
+        pairing::record_failure(state, addr.ip()).await;
+        return Err(ApiError::new(
+            ErrorCode::InvalidPairingCode,
+            "Invalid or expired pairing code",
+        ));
+    };
+
+    // The code belongs to one socket; a reconnect gets a new code
+    let same_session = state
+        .connections
+        .read()
+        .await
+        .get(&pending.device_id)
+        .is_some_and(|c| c.session_id == pending.session_id);
+    if !same_session {
+        return Err(ApiError::new(
+            ErrorCode::DeviceOffline,
+            format!(
+                "{} disconnected before pairing finished; pair with the code it shows now",
+                pending.name
+            ),
+        ));
+    }
 
     let token = Uuid::new_v4().to_string();
     let now = SystemTime::now()
@@ -373,13 +1296,32 @@ pub async fn pair_device(
         .unwrap()
         .as_secs();
 
+    // Re-pairing a known device keeps its alias, groups and default flag
+    let previous = state.devices.read().await.get(&pending.device_id).cloned();
+    let (e2e_public_key, metadata) = state
+        .connections
+        .read()
+        .await
+        .get(&pending.device_id)
+        .map(|c| (c.e2e_public_key.clone(), c.metadata.clone()))
+        .unwrap_or_default();
     let device = Device {
         id: pending.device_id.clone(),
         name: pending.name.clone(),
-        token: token.clone(),
+        token_hash: auth::hash_device_token(&token),
+        previous_token_hash: None,
+        previous_token_expires: None,
+        legacy_token: None,
         paired_at: now,
         push_token: None,
         voip_token: None,
+        groups: previous.as_ref().map(|d| d.groups.clone()).unwrap_or_default(),
+        alias: previous.as_ref().and_then(|d| d.alias.clone()),
+        is_default: previous.as_ref().is_some_and(|d| d.is_default),
+        last_seen: Some(now),
+        revoked_at: None,
+        e2e_public_key: e2e_public_key.clone(),
+        metadata,
     };
 
     // Save device to state
@@ -396,16 +1338,28 @@ pub async fn pair_device(
     }
 
     // Mark connection as authenticated and send token to device
-    {
+    let authenticated = {
         let mut connections = state.connections.write().await;
-        if let Some(conn) = connections.get_mut(&pending.device_id) {
+        let conn = connections
+            .get_mut(&pending.device_id)
+            .filter(|c| c.session_id == pending.session_id);
+        if let Some(conn) = conn {
             conn.authenticated = true;
             let _ = conn.tx.send(ServerMessage::AuthResult {
                 success: true,
                 token: Some(token),
                 error: None,
+                // Only a device with a key of its own can take part in the exchange
+                e2e_commitment: req.e2e_commitment.filter(|_| e2e_public_key.is_some()),
             });
+            true
+        } else {
+            false
         }
+    };
+    // Commands queued while it waited for the code, as on a reconnect
+    if authenticated {
+        queue::flush(state, &pending.device_id).await;
     }
 
     // Broadcast event
@@ -415,24 +1369,159 @@ pub async fn pair_device(
         data: None,
     });
 
-    Ok(Json(PairResponse {
+    Ok(PairResponse {
         device_id: pending.device_id,
         name: pending.name,
-    }))
+        e2e_public_key,
+    })
 }
 
+#[utoipa::path(
+    get, path = "/api/devices/{id}", tag = "devices",
+    summary = "One device",
+    params(("id" = String, Path, description = "Device id, alias or unique name prefix")),
+    responses((status = 200, body = DeviceInfo))
+)]
+pub async fn get_device(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+    Path(query): Path<String>,
+) -> Result<Json<DeviceInfo>, ApiError> {
+    let connections = state.connections.read().await;
+    let devices = state.devices.read().await;
+    let id = find_device(&devices, &query)?;
+    if !caller.can_see_device(&id) {
+        return Err(ApiError::new(
+            ErrorCode::DeviceNotFound,
+            format!("Device {} not found", query),
+        ));
+    }
+    let info = device_info(&devices[&id], connections.get(&id));
+    Ok(Json(info))
+}
+
+/// POST /api/devices/{id}/ping — WebSocket ping to a connected device, answered
+/// with the round trip.
+#[utoipa::path(
+    post, path = "/api/devices/{id}/ping", tag = "devices",
+    summary = "Ping a connected device over its WebSocket",
+    params(("id" = String, Path, description = "Device id, alias or unique name prefix")),
+    responses((status = 200, body = PingResponse))
+)]
+pub async fn ping_device(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+    Path(query): Path<String>,
+) -> Result<Json<PingResponse>, ApiError> {
+    let id = find_device(&*state.devices.read().await, &query)?;
+    if !caller.can_see_device(&id) {
+        return Err(ApiError::new(
+            ErrorCode::DeviceNotFound,
+            format!("Device {} not found", query),
+        ));
+    }
+    let (reply_tx, reply_rx) = oneshot::channel();
+    let sent = state
+        .connections
+        .read()
+        .await
+        .get(&id)
+        .filter(|c| c.authenticated)
+        .is_some_and(|c| c.probe_tx.send(reply_tx).is_ok());
+    if !sent {
+        return Err(ApiError::new(
+            ErrorCode::DeviceOffline,
+            format!("Device {} is not connected", id),
+        ));
+    }
+    match tokio::time::timeout(PING_TIMEOUT, reply_rx).await {
+        Ok(Ok(rtt_ms)) => Ok(Json(PingResponse { device_id: id, rtt_ms })),
+        // The socket closed before the pong
+        Ok(Err(_)) => Err(ApiError::new(
+            ErrorCode::DeviceOffline,
+            format!("Device {} disconnected", id),
+        )),
+        Err(_) => Err(ApiError::new(
+            ErrorCode::Timeout,
+            format!("Device {} did not answer the ping within {}s", id, PING_TIMEOUT.as_secs()),
+        )),
+    }
+}
+
+/// POST /api/devices/{id}/revoke — invalidate the token and force re-pairing.
+///
+/// The device record (alias, groups, history) is kept; a connected device is
+/// sent a fresh pairing code right away.
+#[utoipa::path(
+    post, path = "/api/devices/{id}/revoke", tag = "devices",
+    summary = "Invalidate the device token and force re-pairing (admin)",
+    params(("id" = String, Path, description = "Device id, alias or unique name prefix")),
+    responses((status = 200, body = DeviceInfo))
+)]
+pub async fn revoke_device(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+    Path(id): Path<String>,
+) -> Result<Json<DeviceInfo>, ApiError> {
+    caller.require_admin()?;
+    let mut devices = state.devices.write().await;
+    let id = find_device(&devices, &id)?;
+    let device = devices.get_mut(&id).unwrap();
+    device.token_hash = String::new();
+    device.previous_token_hash = None;
+    device.previous_token_expires = None;
+    device.push_token = None;
+    device.voip_token = None;
+    device.revoked_at = Some(now_secs());
+    info!("Revoked device {}", id);
+
+    let name = device.name.clone();
+    let devices_vec: Vec<_> = devices.values().cloned().collect();
+    let _ = config::save_devices(&devices_vec);
+    drop(devices);
+
+    let connected = state.connections.read().await.contains_key(&id);
+    if connected {
+        pairing::start_pairing(&state, &id, &name, false).await;
+    }
+
+    let connections = state.connections.read().await;
+    let devices = state.devices.read().await;
+    let device = devices.get(&id).ok_or_else(|| {
+        ApiError::new(ErrorCode::DeviceNotFound, format!("Device {} not found", id))
+    })?;
+    Ok(Json(device_info(device, connections.get(&id))))
+}
+
+#[utoipa::path(
+    delete, path = "/api/devices/{id}", tag = "devices",
+    summary = "Forget a device and drop its queued commands (admin)",
+    params(("id" = String, Path, description = "Device id, alias or unique name prefix")),
+    responses((status = 204, description = "Removed"))
+)]
 pub async fn delete_device(
     State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
     Path(id): Path<String>,
-) -> Result<StatusCode, (StatusCode, String)> {
+) -> Result<StatusCode, ApiError> {
+    caller.require_admin()?;
+    let id = find_device(&*state.devices.read().await, &id)?;
     let removed = state.devices.write().await.remove(&id);
     if removed.is_none() {
-        return Err((StatusCode::NOT_FOUND, "Device not found".into()));
+        return Err(ApiError::new(ErrorCode::DeviceNotFound, "Device not found"));
     }
 
     // Disconnect if connected
     state.connections.write().await.remove(&id);
 
+    // Drop anything still queued for it
+    {
+        let mut queue = state.queue.write().await;
+        if queue.remove(&id).is_some() {
+            queue::save(&queue);
+        }
+    }
+
     // Persist
     {
         let devices: Vec<_> = state.devices.read().await.values().cloned().collect();
@@ -441,3 +1530,403 @@ pub async fn delete_device(
 
     Ok(StatusCode::NO_CONTENT)
 }
+
+#[utoipa::path(
+    post, path = "/api/devices/{id}/groups", tag = "devices",
+    summary = "Add the device to a group (admin)",
+    params(("id" = String, Path, description = "Device id, alias or unique name prefix")),
+    request_body = GroupRequest,
+    responses((status = 200, body = DeviceInfo))
+)]
+pub async fn add_device_group(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+    Path(id): Path<String>,
+    Json(req): Json<GroupRequest>,
+) -> Result<Json<DeviceInfo>, ApiError> {
+    caller.require_admin()?;
+    let group = req.group.trim().to_string();
+    if group.is_empty() {
+        return Err(ApiError::new(
+            ErrorCode::InvalidRequest,
+            "Group name cannot be empty",
+        ));
+    }
+    update_device_groups(&state, &id, |groups| {
+        if !groups.contains(&group) {
+            groups.push(group);
+        }
+    })
+    .await
+}
+
+#[utoipa::path(
+    delete, path = "/api/devices/{id}/groups/{group}", tag = "devices",
+    summary = "Remove the device from a group (admin)",
+    params(("id" = String, Path, description = "Device id, alias or unique name prefix"), ("group" = String, Path, description = "Group name")),
+    responses((status = 200, body = DeviceInfo))
+)]
+pub async fn remove_device_group(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+    Path((id, group)): Path<(String, String)>,
+) -> Result<Json<DeviceInfo>, ApiError> {
+    caller.require_admin()?;
+    update_device_groups(&state, &id, |groups| groups.retain(|g| g != &group)).await
+}
+
+async fn update_device_groups(
+    state: &Arc<AppState>,
+    id: &str,
+    change: impl FnOnce(&mut Vec<String>),
+) -> Result<Json<DeviceInfo>, ApiError> {
+    let mut devices = state.devices.write().await;
+    let id = find_device(&devices, id)?;
+    let device = devices.get_mut(&id).unwrap();
+    change(&mut device.groups);
+    device.groups.sort();
+    let device = device.clone();
+
+    let devices_vec: Vec<_> = devices.values().cloned().collect();
+    let _ = config::save_devices(&devices_vec);
+    // Connections are locked before devices, never while holding them
+    drop(devices);
+    Ok(Json(device_info(&device, state.connections.read().await.get(&id))))
+}
+
+#[utoipa::path(
+    patch, path = "/api/devices/{id}", tag = "devices",
+    summary = "Rename the device, set its alias or make it the default (admin)",
+    params(("id" = String, Path, description = "Device id, alias or unique name prefix")),
+    request_body = DeviceUpdate,
+    responses((status = 200, body = DeviceInfo))
+)]
+pub async fn update_device(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+    Path(id): Path<String>,
+    Json(req): Json<DeviceUpdate>,
+) -> Result<Json<DeviceInfo>, ApiError> {
+    caller.require_admin()?;
+    let mut devices = state.devices.write().await;
+    let id = find_device(&devices, &id)?;
+
+    if let Some(name) = &req.name {
+        if name.trim().is_empty() {
+            return Err(ApiError::new(ErrorCode::InvalidRequest, "Name cannot be empty"));
+        }
+    }
+    if let Some(alias) = req.alias.as_deref().filter(|a| !a.is_empty()) {
+        let taken = devices
+            .values()
+            .any(|d| d.id != id && (d.id == alias || d.alias.as_deref() == Some(alias)));
+        if taken {
+            return Err(ApiError::new(
+                ErrorCode::AliasTaken,
+                format!("Alias '{}' is already used by another device", alias),
+            ));
+        }
+    }
+
+    if req.is_default == Some(true) {
+        for d in devices.values_mut() {
+            d.is_default = false;
+        }
+    }
+
+    let device = devices.get_mut(&id).unwrap();
+    if let Some(name) = req.name {
+        device.name = name.trim().to_string();
+    }
+    if let Some(alias) = req.alias {
+        device.alias = Some(alias).filter(|a| !a.is_empty());
+    }
+    if let Some(is_default) = req.is_default {
+        device.is_default = is_default;
+    }
+    info!("Updated device {}", id);
+    let device = device.clone();
+
+    let devices_vec: Vec<_> = devices.values().cloned().collect();
+    let _ = config::save_devices(&devices_vec);
+    // Connections are locked before devices, never while holding them
+    drop(devices);
+    Ok(Json(device_info(&device, state.connections.read().await.get(&id))))
+}
+
+fn key_info(key: &ApiKey) -> ApiKeyInfo {
+    ApiKeyInfo {
+        id: key.id.clone(),
+        name: key.name.clone(),
+        prefix: key.prefix.clone(),
+        scope: key.scope.clone(),
+        created_at: key.created_at,
+        expires_at: key.expires_at,
+        expired: key.expires_at.is_some_and(|t| t <= now_secs()),
+    }
+}
+
+#[utoipa::path(
+    get, path = "/api/keys", tag = "keys",
+    summary = "Scoped API keys, without their secrets (admin)",
+    responses((status = 200, body = Vec<ApiKeyInfo>))
+)]
+pub async fn list_keys(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+) -> Result<Json<Vec<ApiKeyInfo>>, ApiError> {
+    caller.require_admin()?;
+    let keys = state.api_keys.read().await;
+    let mut list: Vec<_> = keys.values().map(key_info).collect();
+    list.sort_by_key(|k| k.created_at);
+    Ok(Json(list))
+}
+
+/// POST /api/keys — create a scoped key. The secret is only returned here.
+#[utoipa::path(
+    post, path = "/api/keys", tag = "keys",
+    summary = "Create a scoped key; the secret is only returned here (admin)",
+    request_body = CreateKeyRequest,
+    responses((status = 200, body = CreateKeyResponse))
+)]
+pub async fn create_key(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+    Json(req): Json<CreateKeyRequest>,
+) -> Result<Json<CreateKeyResponse>, ApiError> {
+    caller.require_admin()?;
+    let name = req.name.trim().to_string();
+    if name.is_empty() {
+        return Err(ApiError::new(ErrorCode::InvalidRequest, "Key name cannot be empty"));
+    }
+
+    // Store device ids so renames and alias changes don't widen or break the scope
+    let mut scope = req.scope;
+    {
+        let devices = state.devices.read().await;
+        let mut ids = Vec::new();
+        for query in &scope.devices {
+            let id = find_device(&devices, query)?;
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+        scope.devices = ids;
+    }
+
+    let mut keys = state.api_keys.write().await;
+    if keys.values().any(|k| k.name == name) {
+        return Err(ApiError::new(
+            ErrorCode::InvalidRequest,
+            format!("An API key named '{}' already exists", name),
+        ));
+    }
+    mint_key(&mut keys, name, scope, req.ttl_secs).map(Json)
+}
+
+/// Create a key, add it to `keys` and save keys.json. The name must be free.
+fn mint_key(
+    keys: &mut HashMap<String, ApiKey>,
+    name: String,
+    scope: KeyScope,
+    ttl_secs: Option<u64>,
+) -> Result<CreateKeyResponse, ApiError> {
+    let secret: String = {
+        use rand::{distributions::Alphanumeric, Rng};
+        let random: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect();
+        format!("omk_{random}")
+    };
+    let now = now_secs();
+    let key = ApiKey {
+        id: Uuid::new_v4().to_string(),
+        name,
+        key_hash: auth::hash_key(&secret),
+        prefix: secret[..8].to_string(),
+        scope,
+        created_at: now,
+        expires_at: ttl_secs.map(|ttl| now + ttl),
+    };
+    info!("Created API key '{}' ({})", key.name, key.id);
+
+    let info = key_info(&key);
+    keys.insert(key.id.clone(), key);
+    let list: Vec<_> = keys.values().cloned().collect();
+    config::save_api_keys(&list)
+        .map_err(|e| ApiError::new(ErrorCode::Internal, e))?;
+
+    Ok(CreateKeyResponse { info, key: secret })
+}
+
+/// POST /api/keys/enroll — issue a short-lived code for `omcli connect`.
+#[utoipa::path(
+    post, path = "/api/keys/enroll", tag = "keys",
+    summary = "Issue a short-lived enrollment code for `omcli connect` (admin)",
+    request_body = CreateEnrollmentRequest,
+    responses((status = 200, body = EnrollmentInfo))
+)]
+pub async fn create_enrollment(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+    Json(req): Json<CreateEnrollmentRequest>,
+) -> Result<Json<EnrollmentInfo>, ApiError> {
+    caller.require_admin()?;
+    enroll::create(&state, req).await.map(Json)
+}
+
+/// POST /api/enroll — exchange an enrollment code for a new scoped key.
+/// Unauthenticated; wrong codes count towards the same limit as pairing codes.
+#[utoipa::path(
+    post, path = "/api/enroll", tag = "keys",
+    summary = "Exchange an enrollment code for a new scoped key",
+    security(()),
+    request_body = EnrollRequest,
+    responses((status = 200, body = CreateKeyResponse))
+)]
+pub async fn enroll(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(req): Json<EnrollRequest>,
+) -> Result<Json<CreateKeyResponse>, ApiError> {
+    pairing::check_client(&state, addr.ip()).await?;
+    let Some(enrollment) = enroll::redeem(&state, &req.code).await else {
+        pairing::record_client_failure(&state, addr.ip()).await;
+        return Err(ApiError::new(
+            ErrorCode::InvalidEnrollmentCode,
+            "Invalid or expired enrollment code",
+        ));
+    };
+
+    let base = enrollment
+        .request
+        .name
+        .or_else(|| req.name.map(|n| n.trim().to_string()).filter(|n| !n.is_empty()))
+        .unwrap_or_else(|| "enrolled".to_string());
+    let mut keys = state.api_keys.write().await;
+    // Two machines with the same hostname each get their own key
+    let name = (1..)
+        .map(|n| if n == 1 { base.clone() } else { format!("{base}-{n}") })
+        .find(|name| !keys.values().any(|k| &k.name == name))
+        .unwrap();
+    info!("Enrolled {} as API key '{}'", addr.ip(), name);
+    let scope = enrollment.request.scope;
+    mint_key(&mut keys, name, scope, enrollment.request.ttl_secs).map(Json)
+}
+
+/// DELETE /api/keys/{id} — accepts the key id or its name.
+#[utoipa::path(
+    delete, path = "/api/keys/{id}", tag = "keys",
+    summary = "Revoke a scoped key (admin)",
+    params(("id" = String, Path, description = "Key id or name")),
+    responses((status = 204, description = "Revoked"))
+)]
+pub async fn revoke_key(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    caller.require_admin()?;
+    let mut keys = state.api_keys.write().await;
+    let key_id = keys
+        .values()
+        .find(|k| k.id == id || k.name == id)
+        .map(|k| k.id.clone())
+        .ok_or_else(|| ApiError::new(ErrorCode::KeyNotFound, format!("API key {} not found", id)))?;
+    let key = keys.remove(&key_id).unwrap();
+    info!("Revoked API key '{}' ({})", key.name, key.id);
+
+    let list: Vec<_> = keys.values().cloned().collect();
+    config::save_api_keys(&list)
+        .map_err(|e| ApiError::new(ErrorCode::Internal, e))?;
+    drop(keys);
+
+    // Its schedules would not run anymore anyway
+    let mut schedules = state.schedules.write().await;
+    let before = schedules.len();
+    schedules.retain(|_, s| s.key_id.as_deref() != Some(key.id.as_str()));
+    if schedules.len() < before {
+        info!("Removed {} schedule(s) of API key '{}'", before - schedules.len(), key.name);
+        scheduler::save(&schedules).map_err(|e| ApiError::new(ErrorCode::Internal, e))?;
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn devices(list: &[(&str, &str, Option<&str>)]) -> HashMap<String, Device> {
+        list.iter()
+            .map(|(id, name, alias)| {
+                let device: Device = serde_json::from_value(serde_json::json!({
+                    "id": id,
+                    "name": name,
+                    "alias": alias,
+                    "paired_at": 0,
+                }))
+                .unwrap();
+                (id.to_string(), device)
+            })
+            .collect()
+    }
+
+    fn fixture() -> HashMap<String, Device> {
+        devices(&[
+            ("4f1c-phone", "iPhone 15", Some("phone")),
+            ("9a2b-ipad", "iPad Air", None),
+            ("c3d4-old", "iPhone SE", None),
+        ])
+    }
+
+    #[test]
+    fn finds_by_id_alias_and_name_prefix() {
+        let devices = fixture();
+        assert_eq!(find_device(&devices, "9a2b-ipad").unwrap(), "9a2b-ipad");
+        assert_eq!(find_device(&devices, "phone").unwrap(), "4f1c-phone");
+        assert_eq!(find_device(&devices, "ipad").unwrap(), "9a2b-ipad");
+        assert_eq!(find_device(&devices, "IPHONE S").unwrap(), "c3d4-old");
+    }
+
+    #[test]
+    fn id_and_alias_win_over_name_prefixes() {
+        let devices = devices(&[
+            ("ipad", "iPhone", None),
+            ("x", "iPad Pro", None),
+            ("y", "iPad mini", Some("ipad mini")),
+        ]);
+        // Also a prefix of "iPad Pro" and "iPad mini"
+        assert_eq!(find_device(&devices, "ipad").unwrap(), "ipad");
+        assert_eq!(find_device(&devices, "ipad mini").unwrap(), "y");
+    }
+
+    #[test]
+    fn ambiguous_prefix_lists_the_matches() {
+        let err = find_device(&fixture(), "iphone").unwrap_err();
+        assert_eq!(err.code, ErrorCode::DeviceAmbiguous);
+        let mut matches: Vec<_> = err.details.unwrap()["matches"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|v| v.as_str().unwrap().to_string())
+            .collect();
+        matches.sort();
+        assert_eq!(matches, ["4f1c-phone", "c3d4-old"]);
+    }
+
+    #[test]
+    fn blank_query_is_refused() {
+        for query in ["", "  "] {
+            let err = find_device(&fixture(), query).unwrap_err();
+            assert_eq!(err.code, ErrorCode::InvalidRequest);
+        }
+    }
+
+    #[test]
+    fn unknown_device_is_not_found() {
+        let err = find_device(&fixture(), "android").unwrap_err();
+        assert_eq!(err.code, ErrorCode::DeviceNotFound);
+    }
+}
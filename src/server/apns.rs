@@ -21,6 +21,20 @@ struct AlarmPayload<'a> {
 
 impl ApnsClient {
     pub fn new(config: &ApnsConfig) -> Result<Self, String> {
+        let missing: Vec<_> = [
+            ("key_path", &config.key_path),
+            ("key_id", &config.key_id),
+            ("team_id", &config.team_id),
+            ("bundle_id", &config.bundle_id),
+        ]
+        .into_iter()
+        .filter(|(_, value)| value.is_empty())
+        .map(|(name, _)| name)
+        .collect();
+        if !missing.is_empty() {
+            return Err(format!("APNs config is missing {}", missing.join(", ")));
+        }
+
         let mut key_file = std::fs::File::open(&config.key_path)
             .map_err(|e| format!("Failed to open APNs key file '{}': {e}", config.key_path))?;
 
@@ -1,27 +1,300 @@
-use axum::{extract::Request, http::StatusCode, middleware::Next, response::Response};
+use axum::{
+    extract::Request,
+    http::Method,
+    middleware::Next,
+    response::Response,
+};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
 use std::sync::Arc;
+use subtle::ConstantTimeEq;
 
-use crate::server::state::AppState;
+use crate::protocol::{ApiKey, Device, ErrorCode};
+use crate::server::error::ApiError;
+use crate::server::state::{now_secs, AppState};
+
+/// Who made a request, inserted into the request extensions by `auth_middleware`
+#[derive(Clone)]
+pub enum Caller {
+    /// The `api_key` from config.toml
+    Admin,
+    /// A scoped key from keys.json
+    Key(ApiKey),
+}
+
+impl Caller {
+    /// `source` recorded on commands submitted by this caller
+    pub fn source(&self) -> String {
+        match self {
+            Caller::Admin => "api".to_string(),
+            Caller::Key(key) => format!("key:{}", key.name),
+        }
+    }
+
+    /// Id of the scoped key, `None` for the admin key
+    pub fn key_id(&self) -> Option<&str> {
+        match self {
+            Caller::Admin => None,
+            Caller::Key(key) => Some(&key.id),
+        }
+    }
+
+    pub fn require_admin(&self) -> Result<(), ApiError> {
+        match self {
+            Caller::Admin => Ok(()),
+            Caller::Key(key) => Err(forbidden(key, "this endpoint needs the admin key")),
+        }
+    }
+
+    /// Server-wide data, open to the admin key and keys not limited to some devices
+    pub fn require_all_devices(&self) -> Result<(), ApiError> {
+        match self {
+            Caller::Key(key) if !self.all_devices() => {
+                Err(forbidden(key, "this endpoint covers every device"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn check_command(&self, command: &str) -> Result<(), ApiError> {
+        match self {
+            Caller::Key(key) if key.scope.read_only => {
+                Err(forbidden(key, "the key is read-only"))
+            }
+            Caller::Key(key) if !command_allowed(&key.scope.commands, command) => Err(forbidden(
+                key,
+                &format!("command '{}' is not allowed", command),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn check_device(&self, device_id: &str) -> Result<(), ApiError> {
+        match self {
+            Caller::Key(key) if !self.can_see_device(device_id) => Err(forbidden(
+                key,
+                &format!("device {} is not allowed", device_id),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn can_see_device(&self, device_id: &str) -> bool {
+        self.all_devices()
+            || matches!(self, Caller::Key(key) if key.scope.devices.iter().any(|d| d == device_id))
+    }
+
+    /// Not restricted to a list of devices
+    pub fn all_devices(&self) -> bool {
+        match self {
+            Caller::Admin => true,
+            Caller::Key(key) => key.scope.devices.is_empty(),
+        }
+    }
+}
+
+/// `notify` allows every `notify.*` command; an empty list allows all.
+fn command_allowed(allowed: &[String], command: &str) -> bool {
+    allowed.is_empty()
+        || allowed
+            .iter()
+            .any(|c| c == command || command.starts_with(&format!("{c}.")))
+}
+
+fn forbidden(key: &ApiKey, reason: &str) -> ApiError {
+    ApiError::new(
+        ErrorCode::Forbidden,
+        format!("API key '{}' is not allowed to do this: {}", key.name, reason),
+    )
+}
+
+pub fn hash_key(secret: &str) -> String {
+    format!("{:x}", Sha256::digest(secret.as_bytes()))
+}
+
+/// Hash a device token with a fresh random salt, as `<salt hex>$<sha256 hex>`.
+pub fn hash_device_token(token: &str) -> String {
+    let mut salt = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let salt: String = salt.iter().map(|b| format!("{b:02x}")).collect();
+    format!("{salt}${}", salted_hash(&salt, token))
+}
+
+fn salted_hash(salt: &str, token: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(token.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// Compare a presented token with a stored hash in constant time.
+pub fn verify_device_token(token: &str, stored: &str) -> bool {
+    let Some((salt, hash)) = stored.split_once('$') else {
+        return false;
+    };
+    salted_hash(salt, token).as_bytes().ct_eq(hash.as_bytes()).into()
+}
+
+/// Whether `token` is the device's current token, or its previous one within the grace window.
+pub fn device_token_matches(device: &Device, token: &str, now: u64) -> bool {
+    if device.revoked_at.is_some() || token.is_empty() {
+        return false;
+    }
+    let current = verify_device_token(token, &device.token_hash);
+    let previous = device.previous_token_expires.is_some_and(|t| t > now)
+        && device
+            .previous_token_hash
+            .as_deref()
+            .is_some_and(|h| verify_device_token(token, h));
+    current | previous
+}
+
+/// Make `token` the device's current token after it authenticated with `used`.
+///
+/// Only the current token moves to `previous_*`, valid until `grace_until`. A
+/// previous token that was used keeps its expiry: a leaked copy can't renew
+/// itself by logging in, nor push the current token out of the grace slot.
+pub fn rotate_device_token(device: &mut Device, used: &str, token: &str, grace_until: u64) {
+    let was_current = verify_device_token(used, &device.token_hash);
+    let current = std::mem::replace(&mut device.token_hash, hash_device_token(token));
+    if was_current {
+        device.previous_token_hash = Some(current);
+        device.previous_token_expires = Some(grace_until);
+    }
+}
+
+/// Resolve a bearer token to the admin key or a live scoped key.
+pub async fn authenticate(state: &AppState, token: &str) -> Result<Caller, ApiError> {
+    let admin = state.settings().await;
+    if bool::from(token.as_bytes().ct_eq(admin.api_key().as_bytes())) {
+        return Ok(Caller::Admin);
+    }
+    let hash = hash_key(token);
+    let keys = state.api_keys.read().await;
+    let key = keys
+        .values()
+        .find(|k| k.key_hash == hash)
+        .ok_or_else(|| ApiError::new(ErrorCode::Unauthorized, "Invalid API key"))?;
+    if key.expires_at.is_some_and(|t| t <= now_secs()) {
+        return Err(ApiError::new(
+            ErrorCode::Unauthorized,
+            format!("API key '{}' has expired", key.name),
+        ));
+    }
+    Ok(Caller::Key(key.clone()))
+}
 
 pub async fn auth_middleware(
     state: axum::extract::State<Arc<AppState>>,
-    req: Request,
+    mut req: Request,
     next: Next,
-) -> Result<Response, StatusCode> {
+) -> Result<Response, ApiError> {
     let auth_header = req
         .headers()
         .get("authorization")
         .and_then(|v| v.to_str().ok());
 
-    match auth_header {
-        Some(header) if header.starts_with("Bearer ") => {
-            let token = &header[7..];
-            if token == state.api_key {
-                Ok(next.run(req).await)
-            } else {
-                Err(StatusCode::UNAUTHORIZED)
-            }
+    let token = match auth_header {
+        Some(header) if header.starts_with("Bearer ") => &header[7..],
+        _ => {
+            return Err(ApiError::new(
+                ErrorCode::Unauthorized,
+                "Missing Authorization: Bearer <api_key> header",
+            ))
+        }
+    };
+
+    let caller = authenticate(&state, token).await?;
+    if let Caller::Key(key) = &caller {
+        if key.scope.read_only && req.method() != Method::GET {
+            return Err(forbidden(key, "the key is read-only"));
         }
-        _ => Err(StatusCode::UNAUTHORIZED),
+    }
+    req.extensions_mut().insert(caller);
+    Ok(next.run(req).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(token: &str) -> Device {
+        let mut device: Device = serde_json::from_value(serde_json::json!({
+            "id": "device-1",
+            "name": "iPhone",
+            "paired_at": 0,
+        }))
+        .unwrap();
+        device.token_hash = hash_device_token(token);
+        device
+    }
+
+    #[test]
+    fn hash_is_salted_and_verifies() {
+        let a = hash_device_token("secret");
+        let b = hash_device_token("secret");
+        assert_ne!(a, b);
+        assert!(verify_device_token("secret", &a));
+        assert!(verify_device_token("secret", &b));
+        assert!(!verify_device_token("other", &a));
+    }
+
+    #[test]
+    fn verify_rejects_malformed_hashes() {
+        assert!(!verify_device_token("secret", ""));
+        assert!(!verify_device_token("secret", "no-salt-separator"));
+        assert!(!verify_device_token("secret", "salt$"));
+    }
+
+    #[test]
+    fn current_token_matches() {
+        let device = device("current");
+        assert!(device_token_matches(&device, "current", 100));
+        assert!(!device_token_matches(&device, "other", 100));
+        assert!(!device_token_matches(&device, "", 100));
+    }
+
+    #[test]
+    fn previous_token_matches_until_it_expires() {
+        let mut device = device("old");
+        rotate_device_token(&mut device, "old", "new", 200);
+        assert!(device_token_matches(&device, "new", 100));
+        assert!(device_token_matches(&device, "old", 100));
+        assert!(device_token_matches(&device, "old", 199));
+        assert!(!device_token_matches(&device, "old", 200));
+        assert!(device_token_matches(&device, "new", 200));
+    }
+
+    #[test]
+    fn revoked_device_matches_nothing() {
+        let mut device = device("old");
+        rotate_device_token(&mut device, "old", "new", 200);
+        device.revoked_at = Some(50);
+        assert!(!device_token_matches(&device, "new", 100));
+        assert!(!device_token_matches(&device, "old", 100));
+    }
+
+    #[test]
+    fn using_the_previous_token_does_not_extend_it() {
+        let mut device = device("a");
+        rotate_device_token(&mut device, "a", "b", 200);
+        // The device never got `b` and comes back with `a`
+        rotate_device_token(&mut device, "a", "c", 500);
+        assert_eq!(device.previous_token_expires, Some(200));
+        assert!(device_token_matches(&device, "a", 150));
+        assert!(!device_token_matches(&device, "a", 200));
+        assert!(!device_token_matches(&device, "b", 150));
+        assert!(device_token_matches(&device, "c", 300));
+    }
+
+    #[test]
+    fn rotating_from_the_current_token_starts_a_new_grace_window() {
+        let mut device = device("a");
+        rotate_device_token(&mut device, "a", "b", 200);
+        rotate_device_token(&mut device, "b", "c", 500);
+        assert_eq!(device.previous_token_expires, Some(500));
+        assert!(device_token_matches(&device, "b", 400));
+        assert!(!device_token_matches(&device, "a", 150));
+        assert!(device_token_matches(&device, "c", 400));
     }
 }
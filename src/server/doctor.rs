@@ -0,0 +1,163 @@
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::protocol::{DoctorCheck, PROTOCOL_VERSION};
+use crate::server::apns::ApnsClient;
+use crate::server::state::{AppState, MdnsStatus};
+
+/// How long the relay gets to answer `/relay/health`
+const RELAY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The checks only the running server can answer, for GET /api/doctor.
+pub(crate) async fn server_checks(state: &AppState) -> Vec<DoctorCheck> {
+    let settings = state.settings().await;
+    let apns = match (&settings.apns, &settings.apns_error) {
+        (_, Some(e)) => Err(e.as_str()),
+        (apns, None) => Ok(apns.is_some()),
+    };
+    let mut checks = push_checks(apns, settings.relay_url()).await;
+    checks.extend(device_checks(state).await);
+    checks.push(mdns_check(&state.mdns));
+    checks
+}
+
+/// APNs and relay checks straight from config.toml, for when the server
+/// cannot be asked.
+pub async fn local_push_checks(config: &Config) -> Vec<DoctorCheck> {
+    let apns = config.apns.as_ref().map(ApnsClient::new).transpose();
+    let apns = match &apns {
+        Ok(client) => Ok(client.is_some()),
+        Err(e) => Err(e.as_str()),
+    };
+    push_checks(apns, config.server.relay_url.as_deref()).await
+}
+
+/// `apns` is whether a client was built from `[apns]`, or why building it failed.
+async fn push_checks(apns: Result<bool, &str>, relay_url: Option<&str>) -> Vec<DoctorCheck> {
+    let mut checks = Vec::new();
+    match apns {
+        Ok(true) => checks.push(DoctorCheck::pass("apns", "APNs key loaded and signed a token")),
+        Ok(false) => {}
+        Err(e) => checks.push(DoctorCheck::fail(
+            "apns",
+            e,
+            "Check apns.key_path, apns.key_id and apns.team_id (`omcli config`); the key is \
+             the .p8 file from developer.apple.com",
+        )),
+    }
+
+    match relay_url {
+        Some(url) => checks.push(relay_check(url, apns == Ok(true)).await),
+        None if apns == Ok(false) => checks.push(DoctorCheck::fail(
+            "push",
+            "Neither [apns] nor server.relay_url is configured",
+            "Offline devices cannot be woken: set up [apns] or `omcli config set relay_url <url>`",
+        )),
+        None => {}
+    }
+    checks
+}
+
+/// The relay is only used without working APNs, so a dead one is then just a warning.
+async fn relay_check(url: &str, apns_ok: bool) -> DoctorCheck {
+    let client = reqwest::Client::builder().timeout(RELAY_TIMEOUT).build();
+    let result = match client {
+        Ok(client) => client
+            .get(format!("{}/relay/health", url.trim_end_matches('/')))
+            .send()
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|resp| match resp.status() {
+                s if s.is_success() => Ok(()),
+                s => Err(format!("HTTP {s}")),
+            }),
+        Err(e) => Err(e.to_string()),
+    };
+    match result {
+        Ok(()) if apns_ok => DoctorCheck::pass(
+            "relay",
+            format!("Relay {url} is healthy (unused while APNs works)"),
+        ),
+        Ok(()) => DoctorCheck::pass("relay", format!("Relay {url} is healthy")),
+        Err(e) => {
+            let message = format!("Relay {url} did not answer /relay/health: {e}");
+            let hint = "Check that `omcli relay` runs there and that server.relay_url is right";
+            if apns_ok {
+                DoctorCheck::warn("relay", message, hint)
+            } else {
+                DoctorCheck::fail("relay", message, hint)
+            }
+        }
+    }
+}
+
+/// Devices without the tokens that reach them while the app is closed.
+async fn device_checks(state: &AppState) -> Vec<DoctorCheck> {
+    let devices = state.devices.read().await;
+    let mut active: Vec<_> = devices.values().filter(|d| d.revoked_at.is_none()).collect();
+    if active.is_empty() {
+        return vec![DoctorCheck::warn(
+            "devices",
+            "No paired devices",
+            "Open the app, connect it to this server and run `omcli pair <code>`",
+        )];
+    }
+    active.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let hint = "Open the app on the device with notifications allowed, so it registers its tokens";
+    let mut checks = Vec::new();
+    for device in &active {
+        let missing = match (&device.push_token, &device.voip_token) {
+            (Some(_), Some(_)) => continue,
+            (None, None) => "push or VoIP token: it cannot be reached while the app is closed",
+            (None, Some(_)) => "push token: notifications cannot reach it while the app is closed",
+            (Some(_), None) => "VoIP token: alarms cannot ring it while the app is closed",
+        };
+        checks.push(DoctorCheck::warn(
+            "devices",
+            format!("{} ({}) has no {}", device.name, device.id, missing),
+            hint,
+        ));
+    }
+    if checks.is_empty() {
+        checks.push(DoctorCheck::pass(
+            "devices",
+            format!("{} device(s) have push and VoIP tokens", active.len()),
+        ));
+    }
+
+    // Older apps are refused when they connect, so only newer ones can show up here
+    for device in active.iter().filter(|d| d.metadata.protocol_version() > PROTOCOL_VERSION) {
+        checks.push(DoctorCheck::warn(
+            "protocol",
+            format!(
+                "{} ({}) speaks protocol version {}, this server only {}",
+                device.name,
+                device.id,
+                device.metadata.protocol_version(),
+                PROTOCOL_VERSION
+            ),
+            "Upgrade omcli on the server; the app may be missing features until then",
+        ));
+    }
+    checks
+}
+
+fn mdns_check(mdns: &MdnsStatus) -> DoctorCheck {
+    match mdns {
+        MdnsStatus::Registered(name) => {
+            DoctorCheck::pass("mdns", format!("Advertised on the LAN as \"{name}\""))
+        }
+        MdnsStatus::Skipped => DoctorCheck::warn(
+            "mdns",
+            "Not advertised: the server is bound to localhost",
+            "Devices cannot reach it; use `omcli serve --bind 0.0.0.0` unless that is intended",
+        ),
+        MdnsStatus::Failed(e) => DoctorCheck::warn(
+            "mdns",
+            e.clone(),
+            "The app cannot discover the server; enter its URL in the app or allow multicast \
+             on UDP port 5353",
+        ),
+    }
+}
@@ -0,0 +1,87 @@
+use rand::Rng;
+use tracing::info;
+
+use crate::protocol::{CreateEnrollmentRequest, EnrollmentInfo, ErrorCode};
+use crate::server::api::find_device;
+use crate::server::error::ApiError;
+use crate::server::state::{now_secs, AppState, PendingEnrollment};
+
+/// How long an enrollment code can be exchanged for a key
+pub const ENROLLMENT_TTL_SECS: u64 = 600;
+
+/// Code alphabet without 0/O and 1/I, so codes survive being read aloud
+const ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const CODE_LEN: usize = 8;
+
+/// Issue a single-use code that `POST /api/enroll` exchanges for a scoped key.
+pub(crate) async fn create(
+    state: &AppState,
+    mut request: CreateEnrollmentRequest,
+) -> Result<EnrollmentInfo, ApiError> {
+    if let Some(name) = &request.name {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(ApiError::new(ErrorCode::InvalidRequest, "Key name cannot be empty"));
+        }
+        if state.api_keys.read().await.values().any(|k| k.name == name) {
+            return Err(ApiError::new(
+                ErrorCode::InvalidRequest,
+                format!("An API key named '{}' already exists", name),
+            ));
+        }
+        request.name = Some(name.to_string());
+    }
+
+    // Resolve now, like POST /api/keys, so the code mints exactly the scope shown
+    {
+        let devices = state.devices.read().await;
+        let mut ids = Vec::new();
+        for query in &request.scope.devices {
+            let id = find_device(&devices, query)?;
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+        request.scope.devices = ids;
+    }
+
+    let now = now_secs();
+    let expires_at = now + ENROLLMENT_TTL_SECS;
+    let mut enrollments = state.pending_enrollments.write().await;
+    enrollments.retain(|_, e| e.expires_at > now);
+    let code = loop {
+        let code: String = (0..CODE_LEN)
+            .map(|_| ALPHABET[rand::thread_rng().gen_range(0..ALPHABET.len())] as char)
+            .collect();
+        if !enrollments.contains_key(&code) {
+            break code;
+        }
+    };
+    enrollments.insert(
+        code.clone(),
+        PendingEnrollment {
+            request,
+            expires_at,
+        },
+    );
+    info!("Created enrollment code, valid for {}s", ENROLLMENT_TTL_SECS);
+
+    Ok(EnrollmentInfo {
+        code: format!("{}-{}", &code[..4], &code[4..]),
+        expires_at,
+        url: state.public_url.clone(),
+        tls_fingerprint: state.tls_fingerprint.clone(),
+    })
+}
+
+/// Take the enrollment for `code` if it exists and has not expired.
+/// Dashes, spaces and case are ignored.
+pub(crate) async fn redeem(state: &AppState, code: &str) -> Option<PendingEnrollment> {
+    let code: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    let enrollment = state.pending_enrollments.write().await.remove(&code)?;
+    (enrollment.expires_at > now_secs()).then_some(enrollment)
+}
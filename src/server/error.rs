@@ -0,0 +1,146 @@
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+
+use crate::protocol::{ApiErrorBody, ApiErrorInfo, ErrorCode};
+
+/// Largest plain-text error body rewrapped by `json_errors`
+const MAX_PLAIN_ERROR_BYTES: usize = 16 * 1024;
+
+/// REST error, rendered as `{"error": {"code", "message", "details"}}`
+#[derive(Debug)]
+pub struct ApiError {
+    pub code: ErrorCode,
+    pub message: String,
+    pub details: Option<serde_json::Value>,
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            details: None,
+        }
+    }
+
+    pub fn with_details(mut self, details: serde_json::Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    pub fn status(&self) -> StatusCode {
+        status_for(self.code)
+    }
+
+    pub fn info(&self) -> ApiErrorInfo {
+        ApiErrorInfo {
+            code: self.code,
+            message: self.message.clone(),
+            details: self.details.clone(),
+        }
+    }
+}
+
+impl From<ApiErrorInfo> for ApiError {
+    fn from(info: ApiErrorInfo) -> Self {
+        Self {
+            code: info.code,
+            message: info.message,
+            details: info.details,
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        let body = ApiErrorBody { error: self.info() };
+        (status, Json(body)).into_response()
+    }
+}
+
+fn status_for(code: ErrorCode) -> StatusCode {
+    match code {
+        ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+        ErrorCode::Forbidden => StatusCode::FORBIDDEN,
+        ErrorCode::InvalidRequest
+        | ErrorCode::DeviceAmbiguous
+        | ErrorCode::NoPushToken => StatusCode::BAD_REQUEST,
+        ErrorCode::NotFound
+        | ErrorCode::DeviceNotFound
+        | ErrorCode::GroupNotFound
+        | ErrorCode::CommandNotFound
+        | ErrorCode::ScheduleNotFound
+        | ErrorCode::KeyNotFound
+        | ErrorCode::InvalidPairingCode
+        | ErrorCode::InvalidEnrollmentCode => StatusCode::NOT_FOUND,
+        ErrorCode::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+        ErrorCode::InvalidConfig
+        | ErrorCode::CommandNotSupported
+        | ErrorCode::UnknownCommand
+        | ErrorCode::InvalidParams => StatusCode::UNPROCESSABLE_ENTITY,
+        ErrorCode::DeviceOffline
+        | ErrorCode::DeviceRevoked
+        | ErrorCode::AliasTaken
+        | ErrorCode::PairingConfirmationRequired => StatusCode::CONFLICT,
+        ErrorCode::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+        ErrorCode::PushFailed => StatusCode::BAD_GATEWAY,
+        ErrorCode::PushNotConfigured => StatusCode::SERVICE_UNAVAILABLE,
+        ErrorCode::Timeout => StatusCode::GATEWAY_TIMEOUT,
+        ErrorCode::Internal | ErrorCode::Unknown => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// Rewrap plain-text errors produced outside the handlers (extractor
+/// rejections, unknown routes) in the JSON envelope.
+pub async fn json_errors(resp: Response) -> Response {
+    let status = resp.status();
+    let is_json = resp
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
+    if !(status.is_client_error() || status.is_server_error()) || is_json {
+        return resp;
+    }
+
+    let code = match status {
+        StatusCode::UNAUTHORIZED => ErrorCode::Unauthorized,
+        StatusCode::FORBIDDEN => ErrorCode::Forbidden,
+        StatusCode::NOT_FOUND => ErrorCode::NotFound,
+        StatusCode::METHOD_NOT_ALLOWED => ErrorCode::MethodNotAllowed,
+        StatusCode::TOO_MANY_REQUESTS => ErrorCode::RateLimited,
+        s if s.is_client_error() => ErrorCode::InvalidRequest,
+        _ => ErrorCode::Internal,
+    };
+    let (_, body) = resp.into_parts();
+    let text = axum::body::to_bytes(body, MAX_PLAIN_ERROR_BYTES)
+        .await
+        .map(|b| String::from_utf8_lossy(&b).into_owned())
+        .unwrap_or_default();
+    let message = if text.is_empty() {
+        status.canonical_reason().unwrap_or("Error").to_string()
+    } else {
+        text
+    };
+
+    let mut resp = ApiError::new(code, message).into_response();
+    *resp.status_mut() = status;
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unreachable_devices_are_not_404() {
+        // 404 means the id is wrong; an offline device or missing push setup is not
+        assert_eq!(status_for(ErrorCode::DeviceOffline), StatusCode::CONFLICT);
+        assert_eq!(status_for(ErrorCode::PushNotConfigured), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(status_for(ErrorCode::DeviceNotFound), StatusCode::NOT_FOUND);
+    }
+}
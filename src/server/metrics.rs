@@ -0,0 +1,297 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::protocol::command;
+use crate::protocol::CommandState;
+use crate::server::state::AppState;
+
+/// Upper bounds of the command latency buckets, in seconds. Pushes and
+/// WebSocket round trips land in the low ones, commands that waited in the
+/// offline queue in the high ones.
+const LATENCY_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 300.0];
+
+/// Counters for GET /metrics. Gauges (devices, pending commands) are read from
+/// the state when scraped instead.
+#[derive(Default)]
+pub struct Metrics {
+    /// Finished commands by command, final state and delivery path
+    commands: Mutex<BTreeMap<(String, String, String), u64>>,
+    latency: Mutex<BTreeMap<String, Histogram>>,
+    timeouts: Mutex<BTreeMap<String, u64>>,
+    /// Push attempts by provider, command and result
+    pushes: Mutex<BTreeMap<(&'static str, String, &'static str), u64>>,
+    /// `POST /api/devices/pair` by result: `paired` or the error code
+    pairing_attempts: Mutex<BTreeMap<String, u64>>,
+    client_events_dropped: AtomicU64,
+}
+
+#[derive(Default)]
+struct Histogram {
+    /// Observations per bucket of `LATENCY_BUCKETS`, the last one past them all
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        if self.buckets.is_empty() {
+            self.buckets = vec![0; LATENCY_BUCKETS.len() + 1];
+        }
+        let i = LATENCY_BUCKETS
+            .iter()
+            .position(|&le| value <= le)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[i] += 1;
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// Commands outside the catalog share one label, so custom names cannot grow
+/// the series without bound.
+fn command_label(name: &str) -> String {
+    match command::find(name) {
+        Some(spec) => spec.name.to_string(),
+        None => "custom".to_string(),
+    }
+}
+
+impl Metrics {
+    pub fn command_finished(
+        &self,
+        command: &str,
+        state: CommandState,
+        via: Option<&str>,
+        latency_ms: u64,
+    ) {
+        let command = command_label(command);
+        let state = serde_json::to_value(state)
+            .ok()
+            .and_then(|v| v.as_str().map(String::from))
+            .unwrap_or_default();
+        let via = via.unwrap_or("none").to_string();
+        *self
+            .commands
+            .lock()
+            .unwrap()
+            .entry((command.clone(), state, via))
+            .or_default() += 1;
+        self.latency
+            .lock()
+            .unwrap()
+            .entry(command)
+            .or_default()
+            .observe(latency_ms as f64 / 1000.0);
+    }
+
+    /// A device did not answer a command sent over its WebSocket in time.
+    pub fn command_timed_out(&self, command: &str) {
+        *self.timeouts.lock().unwrap().entry(command_label(command)).or_default() += 1;
+    }
+
+    /// `provider` is `apns` or `relay`.
+    pub fn push(&self, provider: &'static str, command: &str, ok: bool) {
+        let result = if ok { "success" } else { "failure" };
+        *self
+            .pushes
+            .lock()
+            .unwrap()
+            .entry((provider, command_label(command), result))
+            .or_default() += 1;
+    }
+
+    pub fn pairing_attempt(&self, result: &str) {
+        *self.pairing_attempts.lock().unwrap().entry(result.to_string()).or_default() += 1;
+    }
+
+    /// An event stream subscriber fell behind and missed `count` events.
+    pub fn client_events_dropped(&self, count: u64) {
+        self.client_events_dropped.fetch_add(count, Ordering::Relaxed);
+    }
+}
+
+/// The Prometheus text exposition of the server.
+pub(crate) async fn render(state: &AppState) -> String {
+    let connected = state
+        .connections
+        .read()
+        .await
+        .values()
+        .filter(|c| c.authenticated)
+        .count();
+    let paired = state
+        .devices
+        .read()
+        .await
+        .values()
+        .filter(|d| d.revoked_at.is_none())
+        .count();
+    let pending = state.pending_commands.read().await.len();
+    let queued: usize = state.queue.read().await.values().map(Vec::len).sum();
+    let metrics = &state.metrics;
+
+    let mut out = String::new();
+    header(&mut out, "omcli_build_info", "gauge", "Version of the running server");
+    let _ = writeln!(
+        out,
+        "omcli_build_info{{version=\"{}\"}} 1",
+        env!("CARGO_PKG_VERSION")
+    );
+    gauge(
+        &mut out,
+        "omcli_uptime_seconds",
+        "Seconds since the server started",
+        state.start_time.elapsed().as_secs(),
+    );
+    gauge(
+        &mut out,
+        "omcli_devices_connected",
+        "Devices with an authenticated WebSocket",
+        connected as u64,
+    );
+    gauge(
+        &mut out,
+        "omcli_devices_paired",
+        "Paired devices that are not revoked",
+        paired as u64,
+    );
+    gauge(
+        &mut out,
+        "omcli_commands_pending",
+        "Commands sent over a WebSocket and waiting for the answer",
+        pending as u64,
+    );
+    gauge(
+        &mut out,
+        "omcli_commands_queued",
+        "Commands waiting in the offline queue",
+        queued as u64,
+    );
+
+    header(
+        &mut out,
+        "omcli_commands_total",
+        "counter",
+        "Finished commands by command, final state and delivery path",
+    );
+    for ((command, state, via), n) in metrics.commands.lock().unwrap().iter() {
+        let labels = labels(&[("command", command), ("state", state), ("via", via)]);
+        let _ = writeln!(out, "omcli_commands_total{labels} {n}");
+    }
+
+    header(
+        &mut out,
+        "omcli_command_duration_seconds",
+        "histogram",
+        "Time from accepting a command to its final state",
+    );
+    for (command, h) in metrics.latency.lock().unwrap().iter() {
+        let mut cumulative = 0;
+        for (i, count) in h.buckets.iter().enumerate() {
+            cumulative += count;
+            let le = LATENCY_BUCKETS.get(i).map_or("+Inf".to_string(), |le| le.to_string());
+            let labels = labels(&[("command", command), ("le", &le)]);
+            let _ = writeln!(out, "omcli_command_duration_seconds_bucket{labels} {cumulative}");
+        }
+        let labels = labels(&[("command", command)]);
+        let _ = writeln!(out, "omcli_command_duration_seconds_sum{labels} {}", h.sum);
+        let _ = writeln!(out, "omcli_command_duration_seconds_count{labels} {}", h.count);
+    }
+
+    header(
+        &mut out,
+        "omcli_command_timeouts_total",
+        "counter",
+        "Commands a connected device did not answer in time",
+    );
+    for (command, n) in metrics.timeouts.lock().unwrap().iter() {
+        let labels = labels(&[("command", command)]);
+        let _ = writeln!(out, "omcli_command_timeouts_total{labels} {n}");
+    }
+
+    header(
+        &mut out,
+        "omcli_pushes_total",
+        "counter",
+        "Pushes to offline devices by provider (apns, relay), command and result",
+    );
+    for ((provider, command, result), n) in metrics.pushes.lock().unwrap().iter() {
+        let labels = labels(&[("provider", provider), ("command", command), ("result", result)]);
+        let _ = writeln!(out, "omcli_pushes_total{labels} {n}");
+    }
+
+    header(
+        &mut out,
+        "omcli_pairing_attempts_total",
+        "counter",
+        "Pairing attempts by result: paired or the error code",
+    );
+    for (result, n) in metrics.pairing_attempts.lock().unwrap().iter() {
+        let labels = labels(&[("result", result)]);
+        let _ = writeln!(out, "omcli_pairing_attempts_total{labels} {n}");
+    }
+
+    header(
+        &mut out,
+        "omcli_client_events_dropped_total",
+        "counter",
+        "Events /ws/client subscribers missed because they fell behind",
+    );
+    let _ = writeln!(
+        out,
+        "omcli_client_events_dropped_total {}",
+        metrics.client_events_dropped.load(Ordering::Relaxed)
+    );
+    out
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: u64) {
+    header(out, name, "gauge", help);
+    let _ = writeln!(out, "{name} {value}");
+}
+
+/// `{a="x",b="y"}` with the values escaped
+fn labels(pairs: &[(&str, &str)]) -> String {
+    let pairs: Vec<_> = pairs
+        .iter()
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{name}=\"{value}\"")
+        })
+        .collect();
+    format!("{{{}}}", pairs.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_are_escaped() {
+        assert_eq!(
+            labels(&[("command", "alarm.start")]),
+            r#"{command="alarm.start"}"#
+        );
+        assert_eq!(
+            labels(&[("a", r#"say "hi""#), ("b", r"C:\tmp"), ("c", "two\nlines")]),
+            r#"{a="say \"hi\"",b="C:\\tmp",c="two\nlines"}"#
+        );
+    }
+
+    #[test]
+    fn custom_commands_share_a_label() {
+        assert_eq!(command_label("alarm.start"), "alarm.start");
+        assert_eq!(command_label("my.command"), "custom");
+    }
+}
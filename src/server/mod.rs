@@ -1,25 +1,68 @@
 mod api;
 pub mod apns;
 mod auth;
+pub mod doctor;
+mod enroll;
+mod error;
+mod metrics;
+mod openapi;
+mod pairing;
+mod queue;
+mod reload;
+mod scheduler;
 pub mod state;
 mod ws_client;
 mod ws_device;
 
-use axum::{
-    middleware,
-    routing::{delete, get, post},
-    Router,
-};
+use axum::{middleware, Router};
 use qrcode::QrCode;
-use std::collections::HashMap;
-use std::net::{IpAddr, UdpSocket};
+use std::net::{IpAddr, SocketAddr, UdpSocket};
 use std::sync::Arc;
 use tower_http::cors::CorsLayer;
 use tracing::{info, warn};
+use utoipa_axum::{router::OpenApiRouter, routes};
 
-use crate::config::{self, Config};
-use apns::ApnsClient;
-use state::AppState;
+use crate::config::Config;
+use state::{AppState, MdnsStatus, Persisted, Settings};
+
+pub use openapi::document as openapi;
+
+/// REST routes that need an API key. Each handler declares its OpenAPI
+/// operation, and `routes!` mounts it at that path.
+fn api_routes() -> OpenApiRouter<Arc<AppState>> {
+    OpenApiRouter::new()
+        .routes(routes!(api::post_command))
+        .routes(routes!(api::list_commands))
+        .routes(routes!(api::command_schema))
+        .routes(routes!(api::get_command))
+        .routes(routes!(api::replay_command))
+        .routes(routes!(api::list_schedules, api::create_schedule))
+        .routes(routes!(api::delete_schedule))
+        .routes(routes!(api::get_status))
+        .routes(routes!(api::get_doctor))
+        .routes(routes!(api::reload_config))
+        .routes(routes!(api::get_metrics))
+        .routes(routes!(api::get_devices))
+        .routes(routes!(api::pair_device))
+        .routes(routes!(api::get_device, api::delete_device, api::update_device))
+        .routes(routes!(api::revoke_device))
+        .routes(routes!(api::ping_device))
+        .routes(routes!(api::add_device_group))
+        .routes(routes!(api::remove_device_group))
+        .routes(routes!(api::list_keys, api::create_key))
+        .routes(routes!(api::revoke_key))
+        .routes(routes!(api::create_enrollment))
+}
+
+/// WebSocket routes (auth handled inside handlers), enrollment, where the code
+/// is the credential, and the API description itself.
+fn public_routes() -> OpenApiRouter<Arc<AppState>> {
+    OpenApiRouter::new()
+        .routes(routes!(ws_device::ws_device_handler))
+        .routes(routes!(ws_client::ws_client_handler))
+        .routes(routes!(api::enroll))
+        .routes(routes!(openapi::get_openapi))
+}
 
 fn is_localhost(bind: &str) -> bool {
     match bind.parse::<IpAddr>() {
@@ -29,14 +72,14 @@ fn is_localhost(bind: &str) -> bool {
     }
 }
 
-fn register_mdns(port: u16) -> Option<mdns_sd::ServiceDaemon> {
-    let mdns = match mdns_sd::ServiceDaemon::new() {
-        Ok(d) => d,
-        Err(e) => {
-            warn!("Failed to start mDNS daemon: {}", e);
-            return None;
-        }
-    };
+/// `fingerprint` is the SHA-256 of the TLS certificate when serving WSS.
+/// Returns the daemon and the instance name it registered.
+fn register_mdns(
+    port: u16,
+    fingerprint: Option<&str>,
+) -> Result<(mdns_sd::ServiceDaemon, String), String> {
+    let mdns = mdns_sd::ServiceDaemon::new()
+        .map_err(|e| format!("Failed to start mDNS daemon: {}", e))?;
 
     let raw_host = hostname::get()
         .ok()
@@ -49,36 +92,34 @@ fn register_mdns(port: u16) -> Option<mdns_sd::ServiceDaemon> {
     let instance_name = format!("omcli on {}", host);
 
     let service_type = "_omcli._tcp.local.";
-    let properties = [
+    let mut properties = vec![
         ("path", "/ws/device"),
         ("version", env!("CARGO_PKG_VERSION")),
+        ("scheme", if fingerprint.is_some() { "wss" } else { "ws" }),
     ];
+    if let Some(fp) = fingerprint {
+        properties.push(("fp", fp));
+    }
 
     // Use a distinct hostname to avoid conflicting with macOS mDNSResponder's
     // own A/AAAA records for this machine's hostname.
     let service_host = format!("omcli-{}.local.", host);
-    let service_info = match mdns_sd::ServiceInfo::new(
+    let service_info = mdns_sd::ServiceInfo::new(
         service_type,
         &instance_name,
         &service_host,
         "",
         port,
         &properties[..],
-    ) {
-        Ok(info) => info.enable_addr_auto(),
-        Err(e) => {
-            warn!("Failed to create mDNS service info: {}", e);
-            return None;
-        }
-    };
+    )
+    .map_err(|e| format!("Failed to create mDNS service info: {}", e))?
+    .enable_addr_auto();
 
-    if let Err(e) = mdns.register(service_info) {
-        warn!("Failed to register mDNS service: {}", e);
-        return None;
-    }
+    mdns.register(service_info)
+        .map_err(|e| format!("Failed to register mDNS service: {}", e))?;
 
     info!("mDNS: registered as \"{}\" on port {}", instance_name, port);
-    Some(mdns)
+    Ok((mdns, instance_name))
 }
 
 fn resolve_display_host(bind: &str) -> String {
@@ -144,97 +185,164 @@ fn module_dark(colors: &[qrcode::Color], width: usize, x: i32, y: i32) -> bool {
     colors[y as usize * width + x as usize] == qrcode::Color::Dark
 }
 
-pub async fn serve(port: u16, bind: String, no_qr: bool, host: Option<String>) {
-    tracing_subscriber::fmt::init();
+/// Graceful shutdown for the TLS listener, which is driven by a handle instead of a future.
+async fn shutdown_on_ctrl_c(handle: axum_server::Handle) {
+    tokio::signal::ctrl_c()
+        .await
+        .expect("Failed to listen for ctrl+c");
+    info!("Shutting down...");
+    handle.graceful_shutdown(None);
+}
 
-    let config = Config::load_or_create(port, &bind);
+pub async fn serve(
+    port: Option<u16>,
+    bind: Option<String>,
+    no_qr: bool,
+    host: Option<String>,
+    enroll: bool,
+) {
+    tracing_subscriber::fmt::init();
 
-    // Load saved devices
-    let saved = config::load_devices();
-    let devices: HashMap<String, _> = saved.into_iter().map(|d| (d.id.clone(), d)).collect();
+    let config = Config::load_or_create(port, bind.as_deref()).unwrap_or_else(|e| {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    });
+    let port = config.server.port;
+    let bind = config.server.bind.clone();
 
+    let persisted = Persisted::load();
     info!(
-        "Loaded {} saved device(s)",
-        devices.len()
+        "Loaded {} saved device(s), {} API key(s)",
+        persisted.devices.len(),
+        persisted.api_keys.len()
     );
 
-    // Initialize APNs client if configured
-    let apns = config.apns.as_ref().and_then(|apns_config| {
-        match ApnsClient::new(apns_config) {
-            Ok(client) => Some(client),
+    let display_host = host.clone().unwrap_or_else(|| resolve_display_host(&bind));
+    let tls = config.server.tls.as_ref().map(|tls_config| {
+        let mut hosts = vec![bind.clone(), display_host.clone()];
+        if let Some(name) = hostname::get().ok().and_then(|h| h.into_string().ok()) {
+            hosts.push(format!("{}.local", name.trim_end_matches(".local")));
+            hosts.push(name);
+        }
+        crate::tls::load(tls_config, "server", &hosts).unwrap_or_else(|e| {
+            eprintln!("Error: {e}");
+            std::process::exit(1);
+        })
+    });
+    let fingerprint = tls.as_ref().map(|t| t.fingerprint.clone());
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    let public_url = format!("{}://{}:{}", scheme, display_host, port);
+
+    // Register mDNS service if not binding to localhost
+    let (mdns, mdns_status) = if is_localhost(&bind) {
+        info!("Binding to localhost — skipping mDNS registration");
+        (None, MdnsStatus::Skipped)
+    } else {
+        match register_mdns(port, fingerprint.as_deref()) {
+            Ok((daemon, instance_name)) => (Some(daemon), MdnsStatus::Registered(instance_name)),
             Err(e) => {
-                warn!("APNs not available: {e}");
-                None
+                warn!("{e}");
+                (None, MdnsStatus::Failed(e))
             }
         }
-    });
+    };
 
+    // Initialize APNs client if configured
+    let settings = Settings::new(config.clone());
+    if let Some(e) = &settings.apns_error {
+        warn!("APNs not available: {e}");
+    }
     let state = Arc::new(AppState::new(
-        config.server.api_key.clone(),
-        devices,
+        settings,
+        persisted,
         Config::data_dir(),
-        apns,
-        config.server.relay_url.clone(),
+        public_url,
+        fingerprint.clone(),
+        mdns_status,
     ));
 
-    // Authenticated REST routes
-    let api_routes = Router::new()
-        .route("/api/command", post(api::post_command))
-        .route("/api/status", get(api::get_status))
-        .route("/api/devices", get(api::get_devices))
-        .route("/api/devices/pair", post(api::pair_device))
-        .route("/api/devices/{id}", delete(api::delete_device))
-        .layer(middleware::from_fn_with_state(
-            state.clone(),
-            auth::auth_middleware,
-        ));
-
-    // WebSocket routes (auth handled inside handlers)
-    let ws_routes = Router::new()
-        .route("/ws/device", get(ws_device::ws_device_handler))
-        .route("/ws/client", get(ws_client::ws_client_handler));
+    queue::restore(&state).await;
+    tokio::spawn(queue::run_sweeper(state.clone()));
+    tokio::spawn(scheduler::run(state.clone()));
+    tokio::spawn(pairing::run_sweeper(state.clone()));
+    #[cfg(unix)]
+    tokio::spawn(reload::run_on_sighup(state.clone()));
+
+    let api_routes = Router::from(api_routes()).layer(middleware::from_fn_with_state(
+        state.clone(),
+        auth::auth_middleware,
+    ));
 
     let app = Router::new()
         .merge(api_routes)
-        .merge(ws_routes)
+        .merge(Router::from(public_routes()))
+        .layer(middleware::map_response(error::json_errors))
         .layer(CorsLayer::permissive())
-        .with_state(state);
+        .with_state(state.clone());
+
 
     let addr = format!("{}:{}", bind, port);
     println!("omcli server v{}", env!("CARGO_PKG_VERSION"));
     println!("Listening on {}", addr);
     println!("API key: {}", config.server.api_key);
+    if let Some(fp) = &fingerprint {
+        println!("TLS certificate SHA-256: {}", fp);
+    }
 
     // Print QR code for device connection (skip for localhost or --no-qr)
     if !no_qr && (host.is_some() || !is_localhost(&bind)) {
-        let display_host = host.unwrap_or_else(|| resolve_display_host(&bind));
-        let ws_url = format!("ws://{}:{}/ws/device", display_host, port);
+        let ws_url = match &fingerprint {
+            // The app pins the certificate from the `fp` query parameter
+            Some(fp) => format!("wss://{}:{}/ws/device?fp={}", display_host, port, fp),
+            None => format!("ws://{}:{}/ws/device", display_host, port),
+        };
         print_qr_code(&ws_url);
         println!("  Scan QR or enter: {}", ws_url);
         println!();
     }
 
-    // Register mDNS service if not binding to localhost
-    let mdns = if is_localhost(&bind) {
-        info!("Binding to localhost — skipping mDNS registration");
-        None
-    } else {
-        register_mdns(port)
-    };
+    if enroll {
+        match enroll::create(&state, Default::default()).await {
+            Ok(e) => {
+                println!(
+                    "Enrollment code (valid {} min): {}",
+                    enroll::ENROLLMENT_TTL_SECS / 60,
+                    e.code
+                );
+                println!("  On another machine: {}", e.connect_command());
+                println!();
+            }
+            Err(e) => warn!("Failed to create enrollment code: {}", e.message),
+        }
+    }
 
     let listener = tokio::net::TcpListener::bind(&addr)
         .await
         .expect("Failed to bind address");
 
-    axum::serve(listener, app)
-        .with_graceful_shutdown(async {
-            tokio::signal::ctrl_c()
+    match tls {
+        Some(tls) => {
+            let handle = axum_server::Handle::new();
+            tokio::spawn(shutdown_on_ctrl_c(handle.clone()));
+            axum_server::from_tcp(listener.into_std().expect("Failed to bind address"))
+                .acceptor(axum_server::tls_openssl::OpenSSLAcceptor::new(tls.config))
+                .handle(handle)
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
                 .await
-                .expect("Failed to listen for ctrl+c");
-            info!("Shutting down...");
-        })
-        .await
-        .expect("Server error");
+                .expect("Server error");
+        }
+        None => {
+            axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+                .with_graceful_shutdown(async {
+                    tokio::signal::ctrl_c()
+                        .await
+                        .expect("Failed to listen for ctrl+c");
+                    info!("Shutting down...");
+                })
+                .await
+                .expect("Server error");
+        }
+    }
 
     // Unregister mDNS on shutdown
     if let Some(mdns) = mdns {
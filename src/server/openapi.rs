@@ -0,0 +1,101 @@
+use axum::Json;
+use serde::Serialize;
+use utoipa::openapi::security::{Http, HttpAuthScheme, SecurityScheme};
+use utoipa::openapi::{Content, Ref, RefOr, Response};
+use utoipa::{Modify, OpenApi, ToSchema};
+use utoipa_axum::router::OpenApiRouter;
+
+use crate::protocol::{ApiErrorBody, ClientEvent, CommandResponse, FanoutResponse};
+
+/// Document-wide parts; the paths come from the handlers `serve` mounts.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "omcli",
+        description = "Control paired phones from the command line, scripts and agents. \
+                       Every non-2xx response has the `ApiErrorBody` shape; branch on `error.code`.",
+        license(name = "MIT")
+    ),
+    security(("api_key" = [])),
+    components(schemas(ApiErrorBody, ClientEvent)),
+    modifiers(&ApiKeyAuth),
+    tags(
+        (name = "commands", description = "Send commands to devices and follow them"),
+        (name = "schedules", description = "Commands the server sends later or on a cron schedule"),
+        (name = "devices", description = "Paired devices, pairing and groups"),
+        (name = "keys", description = "Scoped API keys and enrollment codes"),
+        (name = "server", description = "Status, diagnostics and config"),
+        (name = "events", description = "WebSocket endpoints"),
+    )
+)]
+struct ApiDoc;
+
+/// The device's response, or one result per device for a fan-out
+// Only describes the 200 answer of `POST /api/command`; handlers build either part
+#[derive(Serialize, ToSchema)]
+#[serde(untagged)]
+#[allow(dead_code)]
+pub(super) enum CommandResult {
+    Single(CommandResponse),
+    Fanout(FanoutResponse),
+}
+
+struct ApiKeyAuth;
+
+impl Modify for ApiKeyAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+        );
+    }
+}
+
+/// The OpenAPI 3 document of every route `serve` mounts.
+pub fn document() -> utoipa::openapi::OpenApi {
+    let mut doc = OpenApiRouter::with_openapi(ApiDoc::openapi())
+        .merge(super::api_routes())
+        .merge(super::public_routes())
+        .into_openapi();
+    add_error_responses(&mut doc);
+    doc
+}
+
+/// Every operation can fail with the error envelope, so it is declared once here
+/// instead of on each handler.
+fn add_error_responses(doc: &mut utoipa::openapi::OpenApi) {
+    let error = Response::builder()
+        .description("Error; `error.code` says which")
+        .content(
+            "application/json",
+            Content::new(Some(Ref::from_schema_name("ApiErrorBody"))),
+        )
+        .build();
+    for item in doc.paths.paths.values_mut() {
+        let operations = [
+            &mut item.get,
+            &mut item.post,
+            &mut item.put,
+            &mut item.patch,
+            &mut item.delete,
+        ];
+        for operation in operations.into_iter().flatten() {
+            operation
+                .responses
+                .responses
+                .insert("default".to_string(), RefOr::T(error.clone()));
+        }
+    }
+}
+
+/// GET /api/openapi.json — unauthenticated, so client generators can fetch it.
+#[utoipa::path(
+    get, path = "/api/openapi.json", tag = "server",
+    summary = "This OpenAPI document",
+    security(()),
+    responses((status = 200, body = serde_json::Value))
+)]
+pub async fn get_openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(document())
+}
@@ -0,0 +1,239 @@
+use rand::Rng;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::protocol::{ErrorCode, ServerMessage};
+use crate::server::error::ApiError;
+use crate::server::state::{now_secs, AppState, PendingPairing};
+
+/// Failed `POST /api/devices/pair` attempts allowed per client address within the window
+const MAX_CLIENT_FAILURES: usize = 5;
+const CLIENT_FAILURE_WINDOW_SECS: u64 = 300;
+
+/// Wrong guesses tolerated while a code is outstanding before it is replaced
+const MAX_FAILURES_PER_CODE: u32 = 10;
+
+/// How often expired codes are reissued or dropped
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Issue a pairing code for a connected device and send it over its socket.
+/// Does nothing when the device has no registered connection.
+///
+/// `replaces_existing` marks a pairing for a device that is still paired (its
+/// token failed to authenticate); completing it needs explicit confirmation.
+pub(crate) async fn start_pairing(
+    state: &Arc<AppState>,
+    device_id: &str,
+    name: &str,
+    replaces_existing: bool,
+) {
+    let ttl = state.settings().await.config.server.pairing_code_ttl_secs;
+    let mut connections = state.connections.write().await;
+    let Some(conn) = connections.get_mut(device_id) else {
+        return;
+    };
+
+    let code = {
+        let mut pairings = state.pending_pairings.write().await;
+        // One outstanding code per device
+        pairings.retain(|_, p| p.device_id != device_id);
+        let code = loop {
+            let code = format!("{:06}", rand::thread_rng().gen_range(100_000..999_999u32));
+            if !pairings.contains_key(&code) {
+                break code;
+            }
+        };
+        pairings.insert(
+            code.clone(),
+            PendingPairing {
+                device_id: device_id.to_string(),
+                name: name.to_string(),
+                session_id: conn.session_id,
+                expires_at: now_secs() + ttl,
+                failed_attempts: 0,
+                replaces_existing,
+            },
+        );
+        code
+    };
+    info!("Pairing code for {}: {}", device_id, code);
+
+    conn.authenticated = false;
+    let _ = conn.tx.send(ServerMessage::PairingCode { code });
+}
+
+/// Reject clients that recently sent too many wrong pairing or enrollment codes.
+pub(crate) async fn check_client(state: &AppState, client: IpAddr) -> Result<(), ApiError> {
+    let now = now_secs();
+    let failures = state.pair_failures.read().await;
+    let recent: Vec<_> = failures
+        .get(&client)
+        .into_iter()
+        .flatten()
+        .filter(|&&t| now.saturating_sub(t) < CLIENT_FAILURE_WINDOW_SECS)
+        .collect();
+    if recent.len() < MAX_CLIENT_FAILURES {
+        return Ok(());
+    }
+    let retry_after = recent
+        .iter()
+        .map(|&&t| t + CLIENT_FAILURE_WINDOW_SECS)
+        .min()
+        .unwrap_or(now)
+        .saturating_sub(now);
+    Err(ApiError::new(
+        ErrorCode::RateLimited,
+        format!("Too many failed attempts, try again in {retry_after}s"),
+    )
+    .with_details(serde_json::json!({ "retry_after_secs": retry_after })))
+}
+
+/// Count a wrong code against the client and against every outstanding code.
+///
+/// Codes that reach `MAX_FAILURES_PER_CODE` are replaced, so guessing has to
+/// start over no matter how many clients take part.
+pub(crate) async fn record_failure(state: &Arc<AppState>, client: IpAddr) {
+    record_client_failure(state, client).await;
+
+    let burned: Vec<_> = {
+        let mut pairings = state.pending_pairings.write().await;
+        for p in pairings.values_mut() {
+            p.failed_attempts += 1;
+        }
+        let codes: Vec<_> = pairings
+            .iter()
+            .filter(|(_, p)| p.failed_attempts >= MAX_FAILURES_PER_CODE)
+            .map(|(code, _)| code.clone())
+            .collect();
+        codes
+            .into_iter()
+            .filter_map(|code| pairings.remove(&code))
+            .collect()
+    };
+    for p in burned {
+        warn!("Too many wrong pairing codes, replacing the code for {}", p.device_id);
+        start_pairing(state, &p.device_id, &p.name, p.replaces_existing).await;
+    }
+}
+
+/// Count a wrong pairing or enrollment code against the client.
+pub(crate) async fn record_client_failure(state: &AppState, client: IpAddr) {
+    let now = now_secs();
+    let mut failures = state.pair_failures.write().await;
+    let list = failures.entry(client).or_default();
+    list.retain(|&t| now.saturating_sub(t) < CLIENT_FAILURE_WINDOW_SECS);
+    list.push(now);
+}
+
+/// Reissue expired codes to devices that are still waiting and drop the rest.
+pub async fn run_sweeper(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        let now = now_secs();
+
+        let expired: Vec<_> = {
+            let mut pairings = state.pending_pairings.write().await;
+            let codes: Vec<_> = pairings
+                .iter()
+                .filter(|(_, p)| p.expires_at <= now)
+                .map(|(code, _)| code.clone())
+                .collect();
+            codes
+                .into_iter()
+                .filter_map(|code| pairings.remove(&code))
+                .collect()
+        };
+        for p in expired {
+            let waiting = state
+                .connections
+                .read()
+                .await
+                .get(&p.device_id)
+                .is_some_and(|c| c.session_id == p.session_id && !c.authenticated);
+            if waiting {
+                start_pairing(&state, &p.device_id, &p.name, p.replaces_existing).await;
+            }
+        }
+
+        state.pair_failures.write().await.retain(|_, list| {
+            list.retain(|&t| now.saturating_sub(t) < CLIENT_FAILURE_WINDOW_SECS);
+            !list.is_empty()
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> Arc<AppState> {
+        Arc::new(AppState::for_tests())
+    }
+
+    fn pending(device_id: &str) -> PendingPairing {
+        PendingPairing {
+            device_id: device_id.into(),
+            name: "iPhone".into(),
+            session_id: 1,
+            expires_at: now_secs() + 300,
+            failed_attempts: 0,
+            replaces_existing: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn client_is_limited_after_too_many_failures() {
+        let state = state();
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+        for _ in 0..MAX_CLIENT_FAILURES - 1 {
+            record_client_failure(&state, client).await;
+        }
+        assert!(check_client(&state, client).await.is_ok());
+
+        record_client_failure(&state, client).await;
+        let err = check_client(&state, client).await.unwrap_err();
+        assert_eq!(err.code, ErrorCode::RateLimited);
+        let retry_after = err.details.unwrap()["retry_after_secs"].as_u64().unwrap();
+        assert!(retry_after > 0 && retry_after <= CLIENT_FAILURE_WINDOW_SECS);
+        assert!(check_client(&state, other).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn old_failures_fall_out_of_the_window() {
+        let state = state();
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+        let old = now_secs() - CLIENT_FAILURE_WINDOW_SECS;
+        state
+            .pair_failures
+            .write()
+            .await
+            .insert(client, vec![old; MAX_CLIENT_FAILURES]);
+        assert!(check_client(&state, client).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn codes_are_replaced_after_too_many_wrong_guesses() {
+        let state = state();
+        state
+            .pending_pairings
+            .write()
+            .await
+            .insert("123456".into(), pending("device-1"));
+        // Spread over many clients, so the per-client limit never applies
+        for i in 0..MAX_FAILURES_PER_CODE - 1 {
+            record_failure(&state, IpAddr::from([192, 0, 2, i as u8])).await;
+        }
+        assert_eq!(
+            state.pending_pairings.read().await["123456"].failed_attempts,
+            MAX_FAILURES_PER_CODE - 1
+        );
+
+        record_failure(&state, "198.51.100.1".parse().unwrap()).await;
+        // The device is not connected in this test, so no new code is issued
+        assert!(state.pending_pairings.read().await.is_empty());
+    }
+}
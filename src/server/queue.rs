@@ -0,0 +1,152 @@
+use std::sync::Arc;
+use std::time::Duration;
+use std::collections::HashMap;
+use tracing::{info, warn};
+
+use crate::config;
+use crate::protocol::*;
+use crate::server::state::{now_secs, AppState, CommandRecord};
+
+/// How often expired entries are swept from the offline queue
+const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Write the queue to queue.json. A failure is logged: the queue in memory is
+/// still right, and the next change writes it again.
+pub(crate) fn save(queue: &HashMap<String, Vec<QueuedCommand>>) {
+    if let Err(e) = config::save_queue(queue) {
+        warn!("Failed to save the offline queue: {e}");
+    }
+}
+
+/// Persist a command for an offline device. It is delivered on the next successful auth.
+pub async fn enqueue(state: &Arc<AppState>, device_id: &str, cmd: QueuedCommand) {
+    info!("Queueing {} ({}) for offline device {}", cmd.id, cmd.command, device_id);
+    let mut queue = state.queue.write().await;
+    queue.entry(device_id.to_string()).or_default().push(cmd);
+    save(&queue);
+}
+
+/// Remove a command once the device has responded to it.
+pub async fn dequeue(state: &Arc<AppState>, device_id: &str, cmd_id: &str) {
+    let mut queue = state.queue.write().await;
+    let Some(entries) = queue.get_mut(device_id) else {
+        return;
+    };
+    let before = entries.len();
+    entries.retain(|c| c.id != cmd_id);
+    if entries.len() == before {
+        return;
+    }
+    if entries.is_empty() {
+        queue.remove(device_id);
+    }
+    save(&queue);
+}
+
+/// Send every queued command to a freshly authenticated device, oldest first.
+///
+/// Entries stay in the queue until the device responds, so a disconnect
+/// before the response causes redelivery on the next connection.
+pub async fn flush(state: &Arc<AppState>, device_id: &str) {
+    expire(state).await;
+
+    let pending = state
+        .queue
+        .read()
+        .await
+        .get(device_id)
+        .cloned()
+        .unwrap_or_default();
+    if pending.is_empty() {
+        return;
+    }
+
+    info!("Delivering {} queued command(s) to {}", pending.len(), device_id);
+    let connections = state.connections.read().await;
+    let Some(conn) = connections.get(device_id).filter(|c| c.authenticated) else {
+        return;
+    };
+    for cmd in pending {
+        let msg = ServerMessage::Command {
+            id: cmd.id.clone(),
+            command: cmd.command,
+            params: cmd.params,
+        };
+        if conn.tx.send(msg).is_err() {
+            break;
+        }
+        state.update_command(&cmd.id, CommandState::Sent, None).await;
+        state.set_delivery(&cmd.id, "websocket").await;
+    }
+}
+
+/// Drop queued commands whose TTL has passed and mark them expired.
+pub async fn expire(state: &Arc<AppState>) {
+    let now = now_secs();
+    let mut expired = Vec::new();
+    {
+        let mut queue = state.queue.write().await;
+        for entries in queue.values_mut() {
+            entries.retain(|c| {
+                let alive = c.expires_at.is_none_or(|t| t > now);
+                if !alive {
+                    expired.push(c.id.clone());
+                }
+                alive
+            });
+        }
+        if expired.is_empty() {
+            return;
+        }
+        queue.retain(|_, entries| !entries.is_empty());
+        save(&queue);
+    }
+
+    for id in expired {
+        info!("Queued command {} expired", id);
+        let resp = CommandResponse {
+            id: id.clone(),
+            status: "error".into(),
+            data: None,
+            error: Some("Device did not come online before the command expired".into()),
+            error_code: Some("TIMEOUT".into()),
+        };
+        state
+            .update_command(&id, CommandState::Expired, Some(resp))
+            .await;
+    }
+}
+
+/// Recreate status records for commands queued before a restart.
+pub async fn restore(state: &Arc<AppState>) {
+    let queue = state.queue.read().await.clone();
+    for (device_id, entries) in queue {
+        for cmd in entries {
+            let record = CommandRecord {
+                status: CommandStatus {
+                    id: cmd.id,
+                    command: cmd.command,
+                    device_id: device_id.clone(),
+                    source: cmd.source,
+                    state: CommandState::Queued,
+                    delivered_via: None,
+                    created_at: cmd.queued_at,
+                    updated_at: cmd.queued_at,
+                    response: None,
+                },
+                params: cmd.params,
+                started_ms: cmd.queued_at * 1000,
+            };
+            state.insert_command(record).await;
+        }
+    }
+}
+
+/// Periodically expire queued commands so their status is accurate while the device stays offline.
+pub async fn run_sweeper(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        expire(&state).await;
+    }
+}
@@ -0,0 +1,95 @@
+use std::sync::Arc;
+use tracing::{info, warn};
+
+use crate::config::schema::{self, FIELDS};
+use crate::config::Config;
+use crate::protocol::{ConfigChange, ErrorCode, ReloadResponse};
+use crate::server::error::ApiError;
+use crate::server::state::{AppState, Settings};
+
+/// Keys read once at startup: the listener, certificate and mDNS record stay as they are.
+const RESTART_KEYS: &[&str] = &["server.port", "server.bind", "server.tls."];
+
+/// Re-read config.toml and swap in new settings. If the file does not load or
+/// its APNs key fails, the running settings are kept. Connections are untouched.
+pub(crate) async fn reload(state: &AppState) -> Result<ReloadResponse, ApiError> {
+    let invalid = |e: String| ApiError::new(ErrorCode::InvalidConfig, e);
+    let config = Config::load().map_err(invalid)?;
+    let settings = Settings::new(config);
+    if let Some(e) = &settings.apns_error {
+        return Err(invalid(format!("APNs: {e}")));
+    }
+
+    let new_table = toml::Table::try_from(&settings.config)
+        .map_err(|e| invalid(format!("Failed to serialize config: {e}")))?;
+    let old = state.replace_settings(settings).await;
+    let old_table = toml::Table::try_from(&old.config).unwrap_or_default();
+
+    let mut response = ReloadResponse {
+        changed: Vec::new(),
+        restart_required: Vec::new(),
+    };
+    // The relay runs as its own process and reads [relay] itself
+    for field in FIELDS.iter().filter(|f| !f.key.starts_with("relay.")) {
+        let before = schema::get(&old_table, field.key);
+        let after = schema::get(&new_table, field.key);
+        if before == after {
+            continue;
+        }
+        let show = |v: Option<&toml::Value>| {
+            v.map(|v| match field.secret {
+                true => serde_json::json!("********"),
+                false => serde_json::to_value(v).unwrap_or_default(),
+            })
+        };
+        let change = ConfigChange {
+            key: field.key.to_string(),
+            old: show(before),
+            new: show(after),
+        };
+        if RESTART_KEYS.iter().any(|k| field.key.starts_with(k)) {
+            warn!("Config reload: {} changed, restart to apply", field.key);
+            response.restart_required.push(field.key.to_string());
+        } else {
+            info!(
+                "Config reload: {}: {} -> {}",
+                field.key,
+                describe(&change.old),
+                describe(&change.new)
+            );
+        }
+        response.changed.push(change);
+    }
+    if response.changed.is_empty() {
+        info!("Config reload: no changes");
+    }
+    Ok(response)
+}
+
+fn describe(value: &Option<serde_json::Value>) -> String {
+    match value {
+        None => "(unset)".to_string(),
+        Some(serde_json::Value::String(s)) => s.clone(),
+        Some(v) => v.to_string(),
+    }
+}
+
+/// Reload on every SIGHUP until the server stops.
+#[cfg(unix)]
+pub(crate) async fn run_on_sighup(state: Arc<AppState>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(e) => {
+            warn!("Cannot listen for SIGHUP, reload with POST /api/admin/reload: {e}");
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        info!("SIGHUP received, reloading config");
+        if let Err(e) = reload(&state).await {
+            warn!("Config reload failed, keeping the running config: {}", e.message);
+        }
+    }
+}
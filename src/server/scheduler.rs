@@ -0,0 +1,295 @@
+use chrono::{Local, TimeZone};
+use croner::Cron;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::config;
+use crate::protocol::*;
+use crate::server::api;
+use crate::server::auth::Caller;
+use crate::server::state::{now_secs, AppState};
+
+/// How often the scheduler checks for due schedules
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+pub fn parse_cron(expr: &str) -> Result<Cron, String> {
+    Cron::new(expr)
+        .parse()
+        .map_err(|e| format!("Invalid cron expression '{expr}': {e}"))
+}
+
+/// Next time a cron expression fires strictly after `after` (unix seconds), in server local time.
+pub fn next_cron_run(expr: &str, after: u64) -> Result<u64, String> {
+    let cron = parse_cron(expr)?;
+    let start = Local
+        .timestamp_opt(after as i64, 0)
+        .single()
+        .ok_or("Invalid timestamp")?;
+    let next = cron
+        .find_next_occurrence(&start, false)
+        .map_err(|e| format!("Cron expression '{expr}' never fires: {e}"))?;
+    Ok(next.timestamp() as u64)
+}
+
+/// Write the schedules to schedules.json.
+pub(crate) fn save(schedules: &HashMap<String, Schedule>) -> Result<(), String> {
+    let list: Vec<_> = schedules.values().cloned().collect();
+    config::save_schedules(&list)
+}
+
+/// When a schedule runs next after the server starts at `now`. Cron schedules
+/// resume from now, except that a first run set with `at` is kept; one-shots
+/// missed while the server was down fire once.
+fn next_run_on_start(schedule: &Schedule, now: u64) -> Option<u64> {
+    match &schedule.cron {
+        Some(_) if schedule.at.is_some() && schedule.last_run.is_none() => schedule.next_run,
+        Some(expr) => next_cron_run(expr, now).ok(),
+        None => schedule.next_run,
+    }
+}
+
+pub async fn run(state: Arc<AppState>) {
+    {
+        let now = now_secs();
+        let mut schedules = state.schedules.write().await;
+        for schedule in schedules.values_mut() {
+            schedule.next_run = next_run_on_start(schedule, now);
+        }
+        if let Err(e) = save(&schedules) {
+            warn!("Failed to save schedules: {e}");
+        }
+    }
+
+    let mut interval = tokio::time::interval(TICK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let now = now_secs();
+
+        let due: Vec<Schedule> = state
+            .schedules
+            .read()
+            .await
+            .values()
+            .filter(|s| s.next_run.is_some_and(|t| t <= now))
+            .cloned()
+            .collect();
+        if due.is_empty() {
+            continue;
+        }
+
+        {
+            let mut schedules = state.schedules.write().await;
+            for schedule in &due {
+                match &schedule.cron {
+                    Some(expr) => {
+                        if let Some(s) = schedules.get_mut(&schedule.id) {
+                            s.last_run = Some(now);
+                            s.next_run = next_cron_run(expr, now).ok();
+                        }
+                    }
+                    None => {
+                        schedules.remove(&schedule.id);
+                    }
+                }
+            }
+            // The runs go ahead anyway; a stale file only repeats them after a restart
+            if let Err(e) = save(&schedules) {
+                warn!("Failed to save schedules: {e}");
+            }
+        }
+
+        for schedule in due {
+            tokio::spawn(fire(state.clone(), schedule));
+        }
+    }
+}
+
+/// A schedule created with a scoped key runs only while that key exists, has
+/// not expired and still allows the command and device.
+async fn check_key(state: &AppState, schedule: &Schedule) -> Result<(), String> {
+    let Some(key_id) = &schedule.key_id else {
+        return Ok(());
+    };
+    let Some(key) = state.api_keys.read().await.get(key_id).cloned() else {
+        return Err(format!("API key {key_id} no longer exists"));
+    };
+    if key.expires_at.is_some_and(|t| t <= now_secs()) {
+        return Err(format!("API key '{}' has expired", key.name));
+    }
+    let caller = Caller::Key(key);
+    caller
+        .check_command(&schedule.command)
+        .map_err(|e| e.message)?;
+    match &schedule.device_id {
+        Some(id) => caller.check_device(id).map_err(|e| e.message),
+        None => caller.require_all_devices().map_err(|e| e.message),
+    }
+}
+
+/// Send a scheduled command through the same path as POST /api/command.
+async fn fire(state: Arc<AppState>, schedule: Schedule) {
+    if let Err(e) = check_key(&state, &schedule).await {
+        warn!("Schedule {} skipped: {}", schedule.id, e);
+        return;
+    }
+    info!("Schedule {} firing {}", schedule.id, schedule.command);
+    let req = CommandRequest {
+        id: None,
+        command: schedule.command,
+        params: schedule.params,
+        device_id: schedule.device_id,
+        device_ids: None,
+        group: None,
+        ttl_secs: None,
+        // Checked when the schedule was created
+        custom: true,
+    };
+
+    let source = format!("schedule:{}", schedule.id);
+    let (cmd_id, device_id, _) = match api::start_command(&state, &req, &source).await {
+        Ok(started) => started,
+        Err(e) => {
+            warn!("Schedule {} skipped: {}", schedule.id, e.message);
+            return;
+        }
+    };
+    match api::dispatch_command(&state, &cmd_id, &device_id, req).await {
+        Ok(_) => info!("Schedule {} delivered as command {}", schedule.id, cmd_id),
+        Err(e) => warn!("Schedule {} failed: {}", schedule.id, e.message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(at: Option<u64>, cron: Option<&str>, last_run: Option<u64>) -> Schedule {
+        Schedule {
+            id: "s".into(),
+            command: "alarm.start".into(),
+            params: serde_json::Value::Null,
+            device_id: None,
+            at,
+            cron: cron.map(String::from),
+            created_at: 0,
+            next_run: at,
+            last_run,
+            key_id: None,
+        }
+    }
+
+    #[test]
+    fn cron_fires_strictly_after_the_given_time() {
+        let start = Local
+            .with_ymd_and_hms(2030, 1, 4, 7, 0, 0)
+            .unwrap()
+            .timestamp() as u64;
+        // Friday 07:00 is not its own next run; Monday 07:00 is
+        let next = next_cron_run("0 7 * * 1-5", start).unwrap();
+        let expected = Local
+            .with_ymd_and_hms(2030, 1, 7, 7, 0, 0)
+            .unwrap()
+            .timestamp() as u64;
+        assert_eq!(next, expected);
+        assert_eq!(next_cron_run("*/5 * * * *", start).unwrap(), start + 300);
+    }
+
+    #[test]
+    fn cron_rejects_bad_expressions() {
+        for expr in ["", "0 7 * *", "61 * * * *", "0 7 * * mon-xyz"] {
+            assert!(next_cron_run(expr, 0).is_err(), "{expr}");
+        }
+    }
+
+    #[test]
+    fn start_keeps_a_pending_first_run() {
+        let now = 1_000_000;
+        let s = schedule(Some(now + 3600), Some("0 7 * * *"), None);
+        assert_eq!(next_run_on_start(&s, now), Some(now + 3600));
+        // Missed while the server was down: it fires once, like a one-shot
+        let s = schedule(Some(now - 60), Some("0 7 * * *"), None);
+        assert_eq!(next_run_on_start(&s, now), Some(now - 60));
+    }
+
+    #[test]
+    fn start_resumes_cron_from_now_after_the_first_run() {
+        let now = 1_000_000;
+        let expected = next_cron_run("0 7 * * *", now).ok();
+        let s = schedule(Some(now - 60), Some("0 7 * * *"), Some(now - 60));
+        assert_eq!(next_run_on_start(&s, now), expected);
+        let s = schedule(None, Some("0 7 * * *"), None);
+        assert_eq!(next_run_on_start(&s, now), expected);
+    }
+
+    async fn state_with_key(expires_at: Option<u64>) -> AppState {
+        let state = AppState::for_tests();
+        let key: ApiKey = serde_json::from_value(serde_json::json!({
+            "id": "k1",
+            "name": "ci",
+            "key_hash": "",
+            "prefix": "omk_",
+            "commands": ["alarm"],
+            "devices": ["phone"],
+            "created_at": 0,
+            "expires_at": expires_at,
+        }))
+        .unwrap();
+        state.api_keys.write().await.insert(key.id.clone(), key);
+        state
+    }
+
+    fn keyed(command: &str, device_id: Option<&str>) -> Schedule {
+        Schedule {
+            command: command.into(),
+            device_id: device_id.map(String::from),
+            key_id: Some("k1".into()),
+            ..schedule(None, Some("0 7 * * *"), None)
+        }
+    }
+
+    #[tokio::test]
+    async fn keyed_schedule_runs_while_the_key_allows_it() {
+        let state = state_with_key(None).await;
+        assert!(check_key(&state, &keyed("alarm.start", Some("phone")))
+            .await
+            .is_ok());
+        assert!(check_key(&state, &keyed("camera.snap", Some("phone")))
+            .await
+            .is_err());
+        assert!(check_key(&state, &keyed("alarm.start", Some("ipad")))
+            .await
+            .is_err());
+        assert!(check_key(&state, &keyed("alarm.start", None))
+            .await
+            .is_err());
+        // Schedules of the admin key are not checked
+        let admin = Schedule {
+            key_id: None,
+            ..keyed("camera.snap", None)
+        };
+        assert!(check_key(&state, &admin).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn keyed_schedule_stops_with_the_key() {
+        let state = state_with_key(Some(now_secs() - 1)).await;
+        let err = check_key(&state, &keyed("alarm.start", Some("phone")))
+            .await
+            .unwrap_err();
+        assert!(err.contains("expired"), "{err}");
+
+        state.api_keys.write().await.clear();
+        let err = check_key(&state, &keyed("alarm.start", Some("phone")))
+            .await
+            .unwrap_err();
+        assert!(err.contains("no longer exists"), "{err}");
+    }
+
+    #[test]
+    fn start_keeps_one_shots() {
+        let s = schedule(Some(10), None, None);
+        assert_eq!(next_run_on_start(&s, 1_000_000), Some(10));
+    }
+}
@@ -1,59 +1,431 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use tokio::sync::{broadcast, mpsc, oneshot, RwLock};
 
-use crate::protocol::{ClientEvent, CommandResponse, Device, ServerMessage};
+use tracing::{info, warn};
+
+use crate::config::{self, Config};
+use crate::protocol::{
+    ApiKey, ClientEvent, CommandRequest, CommandResponse, CommandState, CommandStatus,
+    CreateEnrollmentRequest, Device, DeviceMetadata, HistoryEntry, QueuedCommand, Schedule,
+    ServerMessage,
+};
 use crate::server::apns::ApnsClient;
+use crate::server::auth;
+use crate::server::metrics::Metrics;
 
 pub type SharedState = Arc<AppState>;
 
+/// How long finished command records stay queryable via GET /api/commands/{id}
+const COMMAND_RETENTION_SECS: u64 = 3600;
+
+/// Finished commands kept in memory for GET /api/commands and replays
+const HISTORY_KEPT: usize = 10_000;
+
 pub struct DeviceConnection {
     pub device_id: String,
+    /// Identifies the socket, so a newer connection for the same device can't be
+    /// torn down by an older one closing
+    pub session_id: u64,
     pub name: String,
     pub authenticated: bool,
+    /// Unix seconds when the WebSocket was opened
+    pub connected_since: u64,
+    /// End-to-end key from the hello, stored on the device when it pairs
+    pub e2e_public_key: Option<String>,
+    /// From the hello; copied to the device when it pairs
+    pub metadata: DeviceMetadata,
+    /// Peer address of the socket (a reverse proxy's, if there is one)
+    pub remote_addr: SocketAddr,
+    pub presence: Arc<Presence>,
     pub tx: mpsc::UnboundedSender<ServerMessage>,
+    /// Asks the socket to ping now; answered with the round trip in milliseconds
+    pub probe_tx: mpsc::UnboundedSender<oneshot::Sender<u64>>,
+}
+
+/// Liveness of one device socket, updated by its loop without taking the
+/// connections lock
+pub struct Presence {
+    /// Unix seconds of the last frame from the device
+    last_seen: AtomicU64,
+    /// Round trip of the last answered ping, `u64::MAX` until there is one
+    rtt_ms: AtomicU64,
+}
+
+impl Default for Presence {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Presence {
+    pub fn new() -> Self {
+        Self {
+            last_seen: AtomicU64::new(now_secs()),
+            rtt_ms: AtomicU64::new(u64::MAX),
+        }
+    }
+
+    pub fn touch(&self) {
+        self.last_seen.store(now_secs(), Ordering::Relaxed);
+    }
+
+    pub fn last_seen(&self) -> u64 {
+        self.last_seen.load(Ordering::Relaxed)
+    }
+
+    pub fn set_rtt_ms(&self, rtt_ms: u64) {
+        self.rtt_ms.store(rtt_ms, Ordering::Relaxed);
+    }
+
+    pub fn rtt_ms(&self) -> Option<u64> {
+        Some(self.rtt_ms.load(Ordering::Relaxed)).filter(|ms| *ms != u64::MAX)
+    }
+}
+
+/// In-flight or recently finished command
+pub struct CommandRecord {
+    pub status: CommandStatus,
+    pub params: serde_json::Value,
+    /// Unix millis when the command was accepted, for latency in the history log
+    pub started_ms: u64,
 }
 
 pub struct PendingPairing {
     pub device_id: String,
     pub name: String,
+    /// Socket the code was sent to; pairing only completes for that session
+    pub session_id: u64,
+    /// Unix seconds after which the code is no longer accepted
+    pub expires_at: u64,
+    /// Wrong codes submitted while this one was outstanding
+    pub failed_attempts: u32,
+    /// The device is still paired; its token failed to authenticate
+    pub replaces_existing: bool,
+}
+
+/// Enrollment code waiting for `omcli connect`
+pub struct PendingEnrollment {
+    /// Scope of the key to mint, device ids already resolved
+    pub request: CreateEnrollmentRequest,
+    /// Unix seconds after which the code is no longer accepted
+    pub expires_at: u64,
+}
+
+/// Everything restored from the data dir at startup
+pub struct Persisted {
+    pub devices: HashMap<String, Device>,
+    pub queue: HashMap<String, Vec<QueuedCommand>>,
+    pub schedules: HashMap<String, Schedule>,
+    pub api_keys: HashMap<String, ApiKey>,
+    pub history: Vec<HistoryEntry>,
+}
+
+impl Persisted {
+    pub fn load() -> Self {
+        let mut devices = config::load_devices();
+        // devices.json from older versions holds plaintext tokens
+        let mut migrated = false;
+        for device in &mut devices {
+            if let Some(token) = device.legacy_token.take() {
+                if device.token_hash.is_empty() && !token.is_empty() {
+                    device.token_hash = auth::hash_device_token(&token);
+                }
+                migrated = true;
+            }
+        }
+        if migrated {
+            match config::save_devices(&devices) {
+                Ok(()) => info!("Replaced plaintext device tokens with hashes"),
+                Err(e) => warn!("Failed to save hashed device tokens: {e}"),
+            }
+        }
+
+        Self {
+            devices: devices.into_iter().map(|d| (d.id.clone(), d)).collect(),
+            queue: config::load_queue().unwrap_or_else(|e| {
+                warn!("Starting with an empty offline queue: {e}");
+                HashMap::new()
+            }),
+            schedules: config::load_schedules()
+                .into_iter()
+                .map(|s| (s.id.clone(), s))
+                .collect(),
+            api_keys: config::load_api_keys()
+                .into_iter()
+                .map(|k| (k.id.clone(), k))
+                .collect(),
+            history: config::load_history(HISTORY_KEPT),
+        }
+    }
 }
 
 pub struct AppState {
+    /// Lock before `devices` when holding both
     pub connections: RwLock<HashMap<String, DeviceConnection>>,
     pub devices: RwLock<HashMap<String, Device>>,
     pub pending_pairings: RwLock<HashMap<String, PendingPairing>>,
+    /// Enrollment codes by code, single use
+    pub pending_enrollments: RwLock<HashMap<String, PendingEnrollment>>,
+    /// Recent failed pairing or enrollment attempts per client address (unix seconds)
+    pub pair_failures: RwLock<HashMap<IpAddr, Vec<u64>>>,
+    /// Replaced as a whole by a config reload, see `settings()`
+    settings: RwLock<Arc<Settings>>,
+    next_session_id: AtomicU64,
     pub pending_commands: RwLock<HashMap<String, oneshot::Sender<CommandResponse>>>,
-    pub api_key: String,
+    pub commands: RwLock<HashMap<String, CommandRecord>>,
+    pub queue: RwLock<HashMap<String, Vec<QueuedCommand>>>,
+    pub schedules: RwLock<HashMap<String, Schedule>>,
+    /// The newest finished commands, oldest first, so reading the history does
+    /// not scan history.jsonl
+    pub history: RwLock<VecDeque<HistoryEntry>>,
+    /// Scoped keys created with `omcli keys create`
+    pub api_keys: RwLock<HashMap<String, ApiKey>>,
     pub client_tx: broadcast::Sender<ClientEvent>,
     pub start_time: Instant,
     pub data_dir: PathBuf,
+    /// Address remote CLIs should use, e.g. `https://vps.example.com:7333`
+    pub public_url: String,
+    /// SHA-256 of the certificate when serving TLS
+    pub tls_fingerprint: Option<String>,
+    pub mdns: MdnsStatus,
+    /// Counters served on GET /metrics
+    pub metrics: Metrics,
+}
+
+/// What the server uses from config.toml: swapped in whole on reload, so a
+/// request sees either the old settings or the new ones, never a mix.
+pub struct Settings {
+    pub config: Config,
     pub apns: Option<ApnsClient>,
-    pub relay_url: Option<String>,
+    /// Why `[apns]` is configured but no client could be built
+    pub apns_error: Option<String>,
+}
+
+impl Settings {
+    /// Build the APNs client for `config`; a key that does not load leaves APNs off.
+    pub fn new(config: Config) -> Self {
+        let (apns, apns_error) = match config.apns.as_ref().map(ApnsClient::new).transpose() {
+            Ok(apns) => (apns, None),
+            Err(e) => (None, Some(e)),
+        };
+        Self {
+            config,
+            apns,
+            apns_error,
+        }
+    }
+
+    /// Admin key, allowed everything
+    pub fn api_key(&self) -> &str {
+        &self.config.server.api_key
+    }
+
+    pub fn relay_url(&self) -> Option<&str> {
+        self.config.server.relay_url.as_deref()
+    }
+}
+
+/// Outcome of advertising the server over mDNS at startup
+#[derive(Debug, Clone)]
+pub enum MdnsStatus {
+    /// Registered under this instance name
+    Registered(String),
+    /// Bound to localhost, so there is nobody on the LAN to find it
+    Skipped,
+    Failed(String),
 }
 
 impl AppState {
     pub fn new(
-        api_key: String,
-        devices: HashMap<String, Device>,
+        settings: Settings,
+        persisted: Persisted,
         data_dir: PathBuf,
-        apns: Option<ApnsClient>,
-        relay_url: Option<String>,
+        public_url: String,
+        tls_fingerprint: Option<String>,
+        mdns: MdnsStatus,
     ) -> Self {
         let (client_tx, _) = broadcast::channel(256);
         Self {
             connections: RwLock::new(HashMap::new()),
-            devices: RwLock::new(devices),
+            devices: RwLock::new(persisted.devices),
             pending_pairings: RwLock::new(HashMap::new()),
+            pending_enrollments: RwLock::new(HashMap::new()),
+            pair_failures: RwLock::new(HashMap::new()),
+            settings: RwLock::new(Arc::new(settings)),
+            next_session_id: AtomicU64::new(1),
             pending_commands: RwLock::new(HashMap::new()),
-            api_key,
+            commands: RwLock::new(HashMap::new()),
+            queue: RwLock::new(persisted.queue),
+            schedules: RwLock::new(persisted.schedules),
+            history: RwLock::new(persisted.history.into()),
+            api_keys: RwLock::new(persisted.api_keys),
             client_tx,
             start_time: Instant::now(),
             data_dir,
-            apns,
-            relay_url,
+            public_url,
+            tls_fingerprint,
+            mdns,
+            metrics: Metrics::default(),
+        }
+    }
+
+    /// The current settings. Hold on to the returned snapshot for the whole
+    /// operation rather than calling this repeatedly.
+    pub async fn settings(&self) -> Arc<Settings> {
+        self.settings.read().await.clone()
+    }
+
+    /// Swap in new settings, returning the ones they replace.
+    pub async fn replace_settings(&self, settings: Settings) -> Arc<Settings> {
+        std::mem::replace(&mut *self.settings.write().await, Arc::new(settings))
+    }
+
+    pub fn new_session_id(&self) -> u64 {
+        self.next_session_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Register a new command in the `queued` state and drop expired records.
+    pub async fn track_command(
+        &self,
+        id: &str,
+        device_id: &str,
+        req: &CommandRequest,
+        source: &str,
+    ) -> CommandStatus {
+        let now = now_secs();
+        let record = CommandRecord {
+            status: CommandStatus {
+                id: id.to_string(),
+                command: req.command.clone(),
+                device_id: device_id.to_string(),
+                source: source.to_string(),
+                state: CommandState::Queued,
+                delivered_via: None,
+                created_at: now,
+                updated_at: now,
+                response: None,
+            },
+            params: req.params.clone(),
+            started_ms: now_millis(),
+        };
+        let status = record.status.clone();
+        self.insert_command(record).await;
+        status
+    }
+
+    pub async fn insert_command(&self, record: CommandRecord) {
+        let now = now_secs();
+        let mut commands = self.commands.write().await;
+        commands.retain(|_, c| {
+            c.status.response.is_none()
+                || now.saturating_sub(c.status.updated_at) < COMMAND_RETENTION_SECS
+        });
+        commands.insert(record.status.id.clone(), record);
+    }
+
+    pub async fn command_status(&self, id: &str) -> Option<CommandStatus> {
+        self.commands.read().await.get(id).map(|c| c.status.clone())
+    }
+
+    /// Record which transport carried a command (`websocket`, `apns`, `voip`, `relay`, ...).
+    pub async fn set_delivery(&self, id: &str, via: &str) {
+        if let Some(record) = self.commands.write().await.get_mut(id) {
+            record.status.delivered_via = Some(via.to_string());
+        }
+    }
+
+    /// Move a tracked command to a new state. Attaching a response marks it
+    /// finished and appends it to the history log.
+    pub async fn update_command(
+        &self,
+        id: &str,
+        state: CommandState,
+        response: Option<CommandResponse>,
+    ) {
+        let mut commands = self.commands.write().await;
+        let Some(record) = commands.get_mut(id) else {
+            return;
+        };
+        record.status.state = state;
+        record.status.updated_at = now_secs();
+
+        let Some(resp) = response else {
+            return;
+        };
+        let entry = HistoryEntry {
+            id: record.status.id.clone(),
+            command: record.status.command.clone(),
+            params: record.params.clone(),
+            device_id: record.status.device_id.clone(),
+            source: record.status.source.clone(),
+            delivered_via: record.status.delivered_via.clone(),
+            state,
+            status: resp.status.clone(),
+            error: resp.error.clone(),
+            error_code: resp.error_code.clone(),
+            created_at: record.status.created_at,
+            finished_at: record.status.updated_at,
+            latency_ms: now_millis().saturating_sub(record.started_ms),
+        };
+        record.status.response = Some(resp);
+        drop(commands);
+
+        self.metrics.command_finished(
+            &entry.command,
+            state,
+            entry.delivered_via.as_deref(),
+            entry.latency_ms,
+        );
+
+        if let Err(e) = config::append_history(&entry) {
+            warn!("Failed to record command history: {e}");
+        }
+        let mut history = self.history.write().await;
+        if history.len() >= HISTORY_KEPT {
+            history.pop_front();
         }
+        history.push_back(entry);
+    }
+}
+
+#[cfg(test)]
+impl AppState {
+    /// Empty state with an admin key of `admin`, nothing read from disk
+    pub(crate) fn for_tests() -> Self {
+        let config: Config = toml::from_str("[server]\napi_key = \"admin\"").unwrap();
+        let persisted = Persisted {
+            devices: HashMap::new(),
+            queue: HashMap::new(),
+            schedules: HashMap::new(),
+            api_keys: HashMap::new(),
+            history: Vec::new(),
+        };
+        Self::new(
+            Settings::new(config),
+            persisted,
+            std::env::temp_dir(),
+            "http://127.0.0.1:0".into(),
+            None,
+            MdnsStatus::Skipped,
+        )
     }
 }
+
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
@@ -3,44 +3,57 @@ use axum::{
         ws::{Message, WebSocket, WebSocketUpgrade},
         Query, State,
     },
-    http::StatusCode,
     response::IntoResponse,
 };
 use serde::Deserialize;
 use std::sync::Arc;
+use utoipa::IntoParams;
 
+use crate::server::auth::{self, Caller};
+use crate::server::error::ApiError;
 use crate::server::state::AppState;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct WsClientParams {
+    /// API key; the event stream is allowed for read-only keys too
     pub token: String,
 }
 
+#[utoipa::path(
+    get, path = "/ws/client", tag = "events",
+    summary = "WebSocket stream of command and device events (`ClientEvent`)",
+    security(()),
+    params(WsClientParams),
+    responses((status = 101, description = "Switched to WebSocket"))
+)]
 pub async fn ws_client_handler(
     ws: WebSocketUpgrade,
     Query(params): Query<WsClientParams>,
     State(state): State<Arc<AppState>>,
-) -> Result<impl IntoResponse, StatusCode> {
-    if params.token != state.api_key {
-        return Err(StatusCode::UNAUTHORIZED);
-    }
-    Ok(ws.on_upgrade(move |socket| handle_client_socket(socket, state)))
+) -> Result<impl IntoResponse, ApiError> {
+    let caller = auth::authenticate(&state, &params.token).await?;
+    Ok(ws.on_upgrade(move |socket| handle_client_socket(socket, state, caller)))
 }
 
-async fn handle_client_socket(mut socket: WebSocket, state: Arc<AppState>) {
+async fn handle_client_socket(mut socket: WebSocket, state: Arc<AppState>, caller: Caller) {
     let mut rx = state.client_tx.subscribe();
 
     loop {
         tokio::select! {
             event = rx.recv() => {
                 match event {
+                    // Scoped keys only see events for their devices
+                    Ok(event) if !caller.can_see_device(&event.device_id) => {}
                     Ok(event) => {
                         let text = serde_json::to_string(&event).unwrap();
                         if socket.send(Message::Text(text.into())).await.is_err() {
                             break;
                         }
                     }
-                    Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                        state.metrics.client_events_dropped(n);
+                    }
                     Err(_) => break,
                 }
             }
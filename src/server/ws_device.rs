@@ -1,31 +1,94 @@
 use axum::{
     extract::{
         ws::{Message, WebSocket, WebSocketUpgrade},
-        State,
+        ConnectInfo, State,
     },
     response::IntoResponse,
 };
-use rand::Rng;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::sync::mpsc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{Instant, Interval, MissedTickBehavior};
 use tracing::{info, warn};
+use uuid::Uuid;
 
-use crate::config;
+use crate::config::{self, ReconnectPolicy};
 use crate::protocol::*;
-use crate::server::state::{AppState, DeviceConnection, PendingPairing};
+use crate::server::{auth, pairing, queue};
+use crate::server::state::{now_millis, now_secs, AppState, DeviceConnection, Presence};
 
+/// How long the token replaced at the last auth keeps working
+const PREVIOUS_TOKEN_GRACE_SECS: u64 = 3600;
+
+#[utoipa::path(
+    get, path = "/ws/device", tag = "events",
+    summary = "Device WebSocket; the device authenticates with its hello, see protocol/PROTOCOL.md",
+    security(()),
+    responses((status = 101, description = "Switched to WebSocket"))
+)]
 pub async fn ws_device_handler(
     ws: WebSocketUpgrade,
     State(state): State<Arc<AppState>>,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
 ) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_device_socket(socket, state))
+    ws.on_upgrade(move |socket| handle_device_socket(socket, state, remote_addr))
+}
+
+/// One device socket.
+///
+/// The connection lives in `AppState::connections` once registered. While another
+/// session of the same device is authenticated, a new socket keeps its connection
+/// here (`detached`) until it authenticates too.
+struct Session {
+    id: u64,
+    device_id: String,
+    detached: Option<DeviceConnection>,
+    /// From the hello, stored on the device once it authenticates
+    metadata: DeviceMetadata,
+}
+
+impl Session {
+    /// Send over this socket, whether registered or not.
+    async fn send(&self, state: &AppState, msg: ServerMessage) {
+        match &self.detached {
+            Some(conn) => {
+                let _ = conn.tx.send(msg);
+            }
+            None => {
+                let connections = state.connections.read().await;
+                if let Some(conn) = connections
+                    .get(&self.device_id)
+                    .filter(|c| c.session_id == self.id)
+                {
+                    let _ = conn.tx.send(msg);
+                }
+            }
+        }
+    }
+
+    /// Registered and authenticated — the only state in which device messages are trusted
+    async fn is_active(&self, state: &AppState) -> bool {
+        self.detached.is_none()
+            && state
+                .connections
+                .read()
+                .await
+                .get(&self.device_id)
+                .is_some_and(|c| c.session_id == self.id && c.authenticated)
+    }
 }
 
-async fn handle_device_socket(mut socket: WebSocket, state: Arc<AppState>) {
+async fn handle_device_socket(mut socket: WebSocket, state: Arc<AppState>, remote_addr: SocketAddr) {
     // 1. Wait for Hello message
-    let (device_id, name) = match socket.recv().await {
+    let (device_id, name, e2e_public_key, metadata) = match socket.recv().await {
         Some(Ok(Message::Text(text))) => match serde_json::from_str::<DeviceMessage>(&text) {
-            Ok(DeviceMessage::Hello { device_id, name }) => (device_id, name),
+            Ok(DeviceMessage::Hello {
+                device_id,
+                name,
+                e2e_public_key,
+                metadata,
+            }) => (device_id, name, e2e_public_key, metadata),
             _ => {
                 warn!("Expected Hello message, got something else");
                 return;
@@ -34,70 +97,119 @@ async fn handle_device_socket(mut socket: WebSocket, state: Arc<AppState>) {
         _ => return,
     };
 
-    info!("Device connected: {} ({})", name, device_id);
+    let version = metadata.protocol_version();
+    if version < MIN_PROTOCOL_VERSION {
+        warn!(
+            "Refusing {} ({}): protocol version {} is older than {}",
+            name, device_id, version, MIN_PROTOCOL_VERSION
+        );
+        let msg = ServerMessage::Incompatible {
+            protocol_version: PROTOCOL_VERSION,
+            min_protocol_version: MIN_PROTOCOL_VERSION,
+            error: format!("Protocol version {version} is no longer supported, update the app"),
+        };
+        let text = serde_json::to_string(&msg).unwrap();
+        let _ = socket.send(Message::Text(text.into())).await;
+        return;
+    }
+    if version > PROTOCOL_VERSION {
+        warn!(
+            "{} ({}) speaks protocol version {}, this server only {}; upgrade omcli",
+            name, device_id, version, PROTOCOL_VERSION
+        );
+    }
+
+    let session_id = state.new_session_id();
+    info!("Device connected: {} ({}) from {}, session {}", name, device_id, remote_addr, session_id);
 
     // 2. Create mpsc channel for sending commands to this device
     let (tx, mut rx) = mpsc::unbounded_channel::<ServerMessage>();
+    let (probe_tx, mut probe_rx) = mpsc::unbounded_channel::<oneshot::Sender<u64>>();
+    let presence = Arc::new(Presence::new());
 
     let conn = DeviceConnection {
         device_id: device_id.clone(),
+        session_id,
         name: name.clone(),
         authenticated: false,
-        tx: tx.clone(),
+        connected_since: now_secs(),
+        e2e_public_key,
+        metadata: metadata.clone(),
+        remote_addr,
+        presence: presence.clone(),
+        tx,
+        probe_tx,
     };
-    state
-        .connections
-        .write()
-        .await
-        .insert(device_id.clone(), conn);
-
-    // 3. Check if device is already paired
-    let is_paired = state.devices.read().await.contains_key(&device_id);
-
-    if !is_paired {
-        // Generate 6-digit pairing code
-        let code = format!("{:06}", rand::thread_rng().gen_range(100_000..999_999u32));
-        info!("Pairing code for {}: {}", device_id, code);
-
-        state.pending_pairings.write().await.insert(
-            code.clone(),
-            PendingPairing {
-                device_id: device_id.clone(),
-                name: name.clone(),
-            },
-        );
 
-        let msg = ServerMessage::PairingCode { code };
-        let text = serde_json::to_string(&msg).unwrap();
-        if socket.send(Message::Text(text.into())).await.is_err() {
-            cleanup(&state, &device_id).await;
-            return;
+    // 3. Register the connection unless the device is already authenticated on
+    // another socket. The map entry holds the only sender, so replacing or
+    // removing it ends that socket's loop.
+    let mut session = Session {
+        id: session_id,
+        device_id: device_id.clone(),
+        detached: None,
+        metadata,
+    };
+    {
+        let mut connections = state.connections.write().await;
+        if connections.get(&device_id).is_some_and(|c| c.authenticated) {
+            info!("{} already connected, session {} must authenticate first", device_id, session_id);
+            session.detached = Some(conn);
+        } else {
+            connections.insert(device_id.clone(), conn);
         }
+    }
+
+    // 4. Paired devices authenticate, new ones get a pairing code
+    let is_paired = state.devices.read().await.contains_key(&device_id);
+    if is_paired || session.detached.is_some() {
+        session.send(&state, ServerMessage::AuthRequired).await;
     } else {
-        // Device is already paired — tell it to authenticate
-        let msg = ServerMessage::AuthRequired;
-        let text = serde_json::to_string(&msg).unwrap();
-        if socket.send(Message::Text(text.into())).await.is_err() {
-            cleanup(&state, &device_id).await;
-            return;
-        }
+        pairing::start_pairing(&state, &device_id, &name, false).await;
     }
 
-    // Broadcast connect event
-    let _ = state.client_tx.send(ClientEvent {
-        event: "device.connected".into(),
-        device_id: device_id.clone(),
-        data: None,
-    });
+    if session.detached.is_none() {
+        let _ = state.client_tx.send(ClientEvent {
+            event: "device.connected".into(),
+            device_id: device_id.clone(),
+            data: None,
+        });
+    }
 
-    // 4. Main event loop
+    // 5. Main event loop. A ping's payload is its send time in Unix millis, so
+    // the pong carries what is needed to measure the round trip.
+    let mut heartbeat = heartbeat(state.settings().await.config.server.ping_interval_secs);
+    let mut last_frame = Instant::now();
+    let mut probes: Vec<(u64, oneshot::Sender<u64>)> = Vec::new();
     loop {
         tokio::select! {
             // Messages from device
             msg = socket.recv() => {
+                if let Some(Ok(_)) = &msg {
+                    last_frame = Instant::now();
+                    presence.touch();
+                }
                 match msg {
                     Some(Ok(Message::Text(text))) => {
-                        handle_device_message(&text, &device_id, &state).await;
+                        let keep_open = handle_device_message(&text, &mut session, &state).await;
+                        if !keep_open {
+                            break;
+                        }
+                    }
+                    Some(Ok(Message::Pong(payload))) => {
+                        let Ok(sent) = <[u8; 8]>::try_from(payload.as_ref()) else {
+                            continue;
+                        };
+                        let sent = u64::from_be_bytes(sent);
+                        let rtt_ms = now_millis().saturating_sub(sent);
+                        presence.set_rtt_ms(rtt_ms);
+                        let (answered, waiting) = std::mem::take(&mut probes)
+                            .into_iter()
+                            .partition(|(at, _)| *at <= sent);
+                        probes = waiting;
+                        for (_, reply) in answered {
+                            let _ = reply.send(rtt_ms);
+                        }
                     }
                     Some(Ok(Message::Close(_))) | None => break,
                     Some(Err(e)) => {
@@ -107,7 +219,7 @@ async fn handle_device_socket(mut socket: WebSocket, state: Arc<AppState>) {
                     _ => {}
                 }
             }
-            // Commands to send to device
+            // Commands to send to device; ends when another session takes over
             cmd = rx.recv() => {
                 match cmd {
                     Some(msg) => {
@@ -119,95 +231,139 @@ async fn handle_device_socket(mut socket: WebSocket, state: Arc<AppState>) {
                     None => break,
                 }
             }
+            // `omcli ping`
+            Some(reply) = probe_rx.recv() => {
+                let sent = now_millis();
+                probes.push((sent, reply));
+                if !send_ping(&mut socket, sent).await {
+                    break;
+                }
+            }
+            _ = next_tick(&mut heartbeat) => {
+                let Some(period) = heartbeat.as_ref().map(Interval::period) else {
+                    continue;
+                };
+                if last_frame.elapsed() >= 2 * period {
+                    warn!(
+                        "No answer from {} (session {}) in {}s, disconnecting",
+                        device_id,
+                        session_id,
+                        last_frame.elapsed().as_secs()
+                    );
+                    break;
+                }
+                if !send_ping(&mut socket, now_millis()).await {
+                    break;
+                }
+            }
+        }
+    }
+
+    // Deliver whatever was queued last, e.g. the reason for closing
+    while let Ok(msg) = rx.try_recv() {
+        let text = serde_json::to_string(&msg).unwrap();
+        if socket.send(Message::Text(text.into())).await.is_err() {
+            break;
         }
     }
 
-    info!("Device disconnected: {}", device_id);
-    cleanup(&state, &device_id).await;
+    info!("Device disconnected: {} (session {})", device_id, session_id);
+    cleanup(&state, &session).await;
+}
+
+/// Ticks every `interval_secs`, starting one interval from now; `None` when 0.
+fn heartbeat(interval_secs: u64) -> Option<Interval> {
+    if interval_secs == 0 {
+        return None;
+    }
+    let period = Duration::from_secs(interval_secs);
+    let mut interval = tokio::time::interval_at(Instant::now() + period, period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    Some(interval)
+}
+
+/// The next heartbeat tick, or never when the heartbeat is off.
+async fn next_tick(heartbeat: &mut Option<Interval>) {
+    match heartbeat {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
 }
 
-async fn handle_device_message(text: &str, device_id: &str, state: &Arc<AppState>) {
+async fn send_ping(socket: &mut WebSocket, sent_ms: u64) -> bool {
+    let payload = sent_ms.to_be_bytes().to_vec();
+    socket.send(Message::Ping(payload.into())).await.is_ok()
+}
+
+/// Handle one message from the device. Returns `false` to close the socket.
+async fn handle_device_message(text: &str, session: &mut Session, state: &Arc<AppState>) -> bool {
     let msg = match serde_json::from_str::<DeviceMessage>(text) {
         Ok(m) => m,
         Err(e) => {
             warn!("Failed to parse device message: {}", e);
-            return;
+            return true;
         }
     };
 
-    match msg {
-        DeviceMessage::Auth {
-            device_id: did,
-            token,
-        } => {
-            let valid = {
-                let devices = state.devices.read().await;
-                devices
-                    .get(&did)
-                    .map(|d| d.token == token)
-                    .unwrap_or(false)
-            };
-
-            let mut connections = state.connections.write().await;
-            if let Some(conn) = connections.get_mut(&did) {
-                if valid {
-                    conn.authenticated = true;
-                    info!("Device authenticated: {}", did);
-                    let _ = conn.tx.send(ServerMessage::AuthResult {
-                        success: true,
-                        token: None,
-                        error: None,
-                    });
-                } else {
-                    warn!("Auth failed for {}, generating new pairing code", did);
-                    drop(connections);
-
-                    // Remove stale device entry
-                    state.devices.write().await.remove(&did);
-                    let devices_vec: Vec<_> =
-                        state.devices.read().await.values().cloned().collect();
-                    let _ = config::save_devices(&devices_vec);
-
-                    // Generate new pairing code
-                    let code =
-                        format!("{:06}", rand::thread_rng().gen_range(100_000..999_999u32));
-                    info!("Re-pairing code for {}: {}", did, code);
-                    state.pending_pairings.write().await.insert(
-                        code.clone(),
-                        PendingPairing {
-                            device_id: did.clone(),
-                            name: state
-                                .connections
-                                .read()
-                                .await
-                                .get(&did)
-                                .map(|c| c.name.clone())
-                                .unwrap_or_default(),
-                        },
-                    );
+    if let DeviceMessage::Auth {
+        device_id: did,
+        token,
+    } = &msg
+    {
+        return authenticate(session, did, token, state).await;
+    }
+    if !session.is_active(state).await {
+        warn!(
+            "Ignoring message from unauthenticated session {} of {}",
+            session.id, session.device_id
+        );
+        return true;
+    }
 
-                    let mut connections = state.connections.write().await;
-                    if let Some(conn) = connections.get_mut(&did) {
-                        let _ = conn.tx.send(ServerMessage::PairingCode { code });
-                    }
-                }
-            }
-        }
+    let device_id = session.device_id.as_str();
+    match msg {
         DeviceMessage::Response {
             id,
             status,
             data,
             error,
         } => {
+            queue::dequeue(state, device_id, &id).await;
+            let resp = CommandResponse {
+                id: id.clone(),
+                status,
+                data,
+                error: error.as_ref().map(|e| e.message.clone()),
+                error_code: error.map(|e| e.code),
+            };
             let sender = state.pending_commands.write().await.remove(&id);
-            if let Some(sender) = sender {
-                let _ = sender.send(CommandResponse {
-                    id,
-                    status,
-                    data,
-                    error: error.as_ref().map(|e| e.message.clone()),
-                    error_code: error.map(|e| e.code),
-                });
+            match sender {
+                Some(sender) => {
+                    let _ = sender.send(resp);
+                }
+                // Queued commands have no waiting request — record the result directly
+                None => {
+                    let final_state = if resp.status == "ok" {
+                        CommandState::Completed
+                    } else {
+                        CommandState::Failed
+                    };
+                    state.update_command(&id, final_state, Some(resp)).await;
+                }
+            }
+        }
+        DeviceMessage::Ack { id } => {
+            let owned = state
+                .command_status(&id)
+                .await
+                .map(|c| c.device_id == device_id && c.state == CommandState::Sent)
+                .unwrap_or(false);
+            if owned {
+                state
+                    .update_command(&id, CommandState::Acknowledged, None)
+                    .await;
             }
         }
         DeviceMessage::Event { event, data } => {
@@ -242,14 +398,167 @@ async fn handle_device_message(text: &str, device_id: &str, state: &Arc<AppState
         DeviceMessage::Hello { .. } => {
             warn!("Unexpected Hello message from {}", device_id);
         }
+        // Handled above
+        DeviceMessage::Auth { .. } => {}
+    }
+    true
+}
+
+/// Check a device token and register the session. Returns `false` to close the socket.
+async fn authenticate(session: &mut Session, did: &str, token: &str, state: &Arc<AppState>) -> bool {
+    let fail = |error: &str| ServerMessage::AuthResult {
+        success: false,
+        token: None,
+        error: Some(error.to_string()),
+        e2e_commitment: None,
+    };
+    if did != session.device_id {
+        warn!("Session {} authenticated as {} after hello from {}", session.id, did, session.device_id);
+        session.send(state, fail("device_id does not match hello")).await;
+        return false;
+    }
+
+    let valid = state
+        .devices
+        .read()
+        .await
+        .get(did)
+        .is_some_and(|d| auth::device_token_matches(d, token, now_secs()));
+
+    if !valid {
+        // The device is online on another socket, so this one is not getting a pairing code
+        if session.detached.is_some() {
+            warn!("Auth failed for {} on session {} while already connected", did, session.id);
+            session.send(state, fail("Authentication failed")).await;
+            return false;
+        }
+
+        // Never drop the paired record here: anyone can claim a device id.
+        // Pairing again replaces its token only once the operator confirms.
+        let name = {
+            let connections = state.connections.read().await;
+            match connections.get(did).filter(|c| c.session_id == session.id) {
+                Some(conn) => conn.name.clone(),
+                None => return false,
+            }
+        };
+        let still_paired = state
+            .devices
+            .read()
+            .await
+            .get(did)
+            .is_some_and(|d| d.revoked_at.is_none());
+        if still_paired {
+            warn!("Auth failed for paired device {}, pairing needs confirmation", did);
+            let _ = state.client_tx.send(ClientEvent {
+                event: "device.auth_failed".into(),
+                device_id: did.to_string(),
+                data: None,
+            });
+        } else {
+            warn!("Auth failed for {}, generating new pairing code", did);
+        }
+        pairing::start_pairing(state, did, &name, still_paired).await;
+        return true;
+    }
+
+    // Second socket for a connected device. Close it without a failed
+    // auth_result: the token is fine, and the app forgets it on failure.
+    let policy = state.settings().await.config.server.reconnect_policy;
+    if session.detached.is_some() && policy == ReconnectPolicy::RejectNew {
+        info!("Rejecting session {} of {}: already connected", session.id, did);
+        return false;
+    }
+
+    let ok = ServerMessage::AuthResult {
+        success: true,
+        token: Some(rotate_token(state, did, token, &session.metadata).await),
+        error: None,
+        e2e_commitment: None,
+    };
+    match session.detached.take() {
+        Some(mut conn) => {
+            conn.authenticated = true;
+            let _ = conn.tx.send(ok);
+            let old = state.connections.write().await.insert(did.to_string(), conn);
+            if let Some(old) = old {
+                info!("Device {} reconnected, closing session {}", did, old.session_id);
+            }
+            let _ = state.client_tx.send(ClientEvent {
+                event: "device.connected".into(),
+                device_id: did.to_string(),
+                data: None,
+            });
+        }
+        None => {
+            let mut connections = state.connections.write().await;
+            let Some(conn) = connections.get_mut(did).filter(|c| c.session_id == session.id) else {
+                return false;
+            };
+            conn.authenticated = true;
+            let _ = conn.tx.send(ok);
+        }
+    }
+
+    info!("Device authenticated: {} (session {})", did, session.id);
+    queue::flush(state, did).await;
+    true
+}
+
+/// Issue a new token after a successful auth and record what the hello said.
+/// A current token just used stays valid for `PREVIOUS_TOKEN_GRACE_SECS` in
+/// case the device never receives the new one.
+async fn rotate_token(
+    state: &Arc<AppState>,
+    device_id: &str,
+    used: &str,
+    metadata: &DeviceMetadata,
+) -> String {
+    let token = Uuid::new_v4().to_string();
+    let now = now_secs();
+    let mut devices = state.devices.write().await;
+    if let Some(device) = devices.get_mut(device_id) {
+        auth::rotate_device_token(device, used, &token, now + PREVIOUS_TOKEN_GRACE_SECS);
+        device.last_seen = Some(now);
+        device.metadata = metadata.clone();
+        let devices_vec: Vec<_> = devices.values().cloned().collect();
+        let _ = config::save_devices(&devices_vec);
     }
+    token
 }
 
-async fn cleanup(state: &Arc<AppState>, device_id: &str) {
-    state.connections.write().await.remove(device_id);
+async fn touch_last_seen(state: &Arc<AppState>, device_id: &str, last_seen: u64) {
+    let mut devices = state.devices.write().await;
+    if let Some(device) = devices.get_mut(device_id) {
+        device.last_seen = Some(last_seen);
+        let devices_vec: Vec<_> = devices.values().cloned().collect();
+        let _ = config::save_devices(&devices_vec);
+    }
+}
+
+async fn cleanup(state: &Arc<AppState>, session: &Session) {
+    // Never registered, nothing to undo
+    if session.detached.is_some() {
+        return;
+    }
+    // Only remove our own entry; a newer session may have replaced it
+    let removed = {
+        let mut connections = state.connections.write().await;
+        match connections.get(&session.device_id) {
+            Some(c) if c.session_id == session.id => connections.remove(&session.device_id),
+            _ => None,
+        }
+    };
+    let Some(conn) = removed else {
+        return;
+    };
+    if conn.authenticated {
+        // Not now: a dead peer was last heard from before it was evicted
+        touch_last_seen(state, &session.device_id, conn.presence.last_seen()).await;
+    }
     let _ = state.client_tx.send(ClientEvent {
         event: "device.disconnected".into(),
-        device_id: device_id.to_string(),
+        device_id: session.device_id.clone(),
         data: None,
     });
 }
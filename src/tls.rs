@@ -0,0 +1,164 @@
+use axum_server::tls_openssl::OpenSSLConfig;
+use openssl::asn1::Asn1Time;
+use openssl::bn::{BigNum, MsbOption};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::PKey;
+use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+use openssl::x509::extension::{ExtendedKeyUsage, SubjectAlternativeName};
+use openssl::x509::{X509NameBuilder, X509};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::config::{self, Config, TlsConfig};
+
+/// Validity of generated certificates; clients pin the fingerprint rather than trust a CA
+const SELF_SIGNED_DAYS: u32 = 3650;
+
+/// Certificate loaded for a listener
+pub struct Tls {
+    pub config: OpenSSLConfig,
+    /// SHA-256 of the leaf certificate (DER), lowercase hex
+    pub fingerprint: String,
+}
+
+/// Certificate and key paths; `name` picks the default file names for self-signed pairs.
+pub fn paths(tls: &TlsConfig, name: &str) -> (PathBuf, PathBuf) {
+    let dir = Config::data_dir();
+    let cert = tls
+        .cert_path
+        .as_ref()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| dir.join(format!("{name}-cert.pem")));
+    let key = tls
+        .key_path
+        .as_ref()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| dir.join(format!("{name}-key.pem")));
+    (cert, key)
+}
+
+/// Load the configured certificate, generating a self-signed one for `hosts` if
+/// `self_signed` is set and the files do not exist yet.
+pub fn load(tls: &TlsConfig, name: &str, hosts: &[String]) -> Result<Tls, String> {
+    let (cert_path, key_path) = paths(tls, name);
+    if !cert_path.exists() || !key_path.exists() {
+        if !tls.self_signed {
+            return Err(format!(
+                "TLS certificate {} or key {} not found (set self_signed = true to generate one)",
+                cert_path.display(),
+                key_path.display()
+            ));
+        }
+        generate_self_signed(&cert_path, &key_path, name, hosts)?;
+        info!("Generated self-signed certificate {}", cert_path.display());
+    }
+
+    let pem = std::fs::read(&cert_path)
+        .map_err(|e| format!("Failed to read {}: {e}", cert_path.display()))?;
+    let fingerprint = fingerprint(&pem)?;
+    let config = OpenSSLConfig::from_pem_chain_file(&cert_path, &key_path)
+        .map_err(|e| format!("Failed to load TLS certificate {}: {e}", cert_path.display()))?;
+    Ok(Tls {
+        config,
+        fingerprint,
+    })
+}
+
+/// SHA-256 fingerprint of the first certificate in a PEM file.
+pub fn fingerprint(pem: &[u8]) -> Result<String, String> {
+    let cert = X509::from_pem(pem).map_err(|e| format!("Invalid certificate: {e}"))?;
+    cert_fingerprint(&cert)
+}
+
+pub fn cert_fingerprint(cert: &X509) -> Result<String, String> {
+    let digest = cert
+        .digest(MessageDigest::sha256())
+        .map_err(|e| format!("Failed to hash certificate: {e}"))?;
+    Ok(digest.iter().map(|b| format!("{b:02x}")).collect())
+}
+
+/// Fetch the certificate a server presents, without verifying it, so the
+/// caller can compare its fingerprint before trusting it.
+pub fn peer_certificate(addrs: &[SocketAddr], domain: Option<&str>) -> Result<X509, String> {
+    let err = |e: &dyn std::fmt::Display| format!("TLS handshake failed: {e}");
+    let mut builder = SslConnector::builder(SslMethod::tls()).map_err(|e| err(&e))?;
+    builder.set_verify(SslVerifyMode::NONE);
+    let mut ssl = builder.build().configure().map_err(|e| err(&e))?;
+    ssl.set_verify_hostname(false);
+    ssl.set_use_server_name_indication(domain.is_some());
+
+    let stream = TcpStream::connect(addrs).map_err(|e| format!("Cannot connect: {e}"))?;
+    let stream = ssl
+        .connect(domain.unwrap_or_default(), stream)
+        .map_err(|e| err(&e))?;
+    stream
+        .ssl()
+        .peer_certificate()
+        .ok_or_else(|| "Server sent no certificate".to_string())
+}
+
+fn generate_self_signed(
+    cert_path: &Path,
+    key_path: &Path,
+    name: &str,
+    hosts: &[String],
+) -> Result<(), String> {
+    let err = |e: openssl::error::ErrorStack| format!("Failed to generate certificate: {e}");
+
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).map_err(err)?;
+    let key = PKey::from_ec_key(EcKey::generate(&group).map_err(err)?).map_err(err)?;
+
+    let mut subject = X509NameBuilder::new().map_err(err)?;
+    subject
+        .append_entry_by_nid(Nid::COMMONNAME, &format!("omcli {name}"))
+        .map_err(err)?;
+    let subject = subject.build();
+
+    let mut serial = BigNum::new().map_err(err)?;
+    serial.rand(127, MsbOption::MAYBE_ZERO, false).map_err(err)?;
+
+    let mut san = SubjectAlternativeName::new();
+    san.dns("localhost").ip("127.0.0.1").ip("::1");
+    for host in hosts {
+        match host.parse::<IpAddr>() {
+            Ok(ip) if ip.is_unspecified() => {}
+            Ok(_) => {
+                san.ip(host);
+            }
+            Err(_) => {
+                san.dns(host);
+            }
+        }
+    }
+
+    let serial = serial.to_asn1_integer().map_err(err)?;
+    let not_before = Asn1Time::days_from_now(0).map_err(err)?;
+    let not_after = Asn1Time::days_from_now(SELF_SIGNED_DAYS).map_err(err)?;
+
+    let mut builder = X509::builder().map_err(err)?;
+    builder.set_version(2).map_err(err)?;
+    builder.set_serial_number(&serial).map_err(err)?;
+    builder.set_subject_name(&subject).map_err(err)?;
+    builder.set_issuer_name(&subject).map_err(err)?;
+    builder.set_not_before(&not_before).map_err(err)?;
+    builder.set_not_after(&not_after).map_err(err)?;
+    builder.set_pubkey(&key).map_err(err)?;
+    let san = san.build(&builder.x509v3_context(None, None)).map_err(err)?;
+    builder.append_extension(san).map_err(err)?;
+    builder
+        .append_extension(ExtendedKeyUsage::new().server_auth().build().map_err(err)?)
+        .map_err(err)?;
+    builder.sign(&key, MessageDigest::sha256()).map_err(err)?;
+    let cert = builder.build();
+
+    if let Some(dir) = cert_path.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {e}", dir.display()))?;
+    }
+    config::write_private(key_path, &key.private_key_to_pem_pkcs8().map_err(err)?)?;
+    std::fs::write(cert_path, cert.to_pem().map_err(err)?)
+        .map_err(|e| format!("Failed to write {}: {e}", cert_path.display()))
+}